@@ -1,13 +1,105 @@
-use rusqlite::{params, Connection};
+use rusqlite::{params, params_from_iter, Connection};
+use rusqlite::types::Value;
 use std::error::Error;
 use std::fmt;
 use serde::{Deserialize, Serialize};
 use std::os::unix::fs::PermissionsExt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
 
+mod migrations;
 
-#[derive(Serialize, Deserialize, Debug)]
+
+//Invoice states, numbered the same as the LND InvoiceState enum
+pub const INVOICE_STATE_OPEN: u8 = 0;
+pub const INVOICE_STATE_SETTLED: u8 = 1;
+pub const INVOICE_STATE_CANCELED: u8 = 2;
+pub const INVOICE_STATE_ACCEPTED: u8 = 3;
+
+//Payment categories, stored in the action column
+pub const ACTION_INVOICE: u8 = 0;  //A regular invoice paid through a payment request
+pub const ACTION_STREAM: u8 = 1;   //A per-minute podcast payment
+pub const ACTION_BOOST: u8 = 2;    //A manual boost or boost-a-gram
+pub const ACTION_UNKNOWN: u8 = 3;  //A podcast payment with an action we don't recognize
+pub const ACTION_KEYSEND: u8 = 4;  //A keysend payment without a podcast tlv
+
+//The names the api uses for the payment categories
+const ACTION_NAMES: [(u8, &str); 5] = [
+    (ACTION_INVOICE, "invoice"),
+    (ACTION_STREAM, "stream"),
+    (ACTION_BOOST, "boost"),
+    (ACTION_UNKNOWN, "unknown"),
+    (ACTION_KEYSEND, "keysend"),
+];
+
+//Htlc states, numbered the same as the LND InvoiceHTLCState enum
+pub const HTLC_STATE_ACCEPTED: u8 = 0;
+pub const HTLC_STATE_SETTLED: u8 = 1;
+pub const HTLC_STATE_CANCELED: u8 = 2;
+
+//Names of the positions kept in the sync_state table
+const SYNC_PAYMENT_SWEEP_INDEX: &str = "payment_sweep_index";
+
+//Outgoing payment states
+pub const OUTGOING_STATE_IN_FLIGHT: u8 = 0;
+pub const OUTGOING_STATE_SUCCEEDED: u8 = 1;
+pub const OUTGOING_STATE_FAILED: u8 = 2;
+pub const OUTGOING_STATE_SKIPPED: u8 = 3;  //Never sent, like a split payout too small to send
+
+//The name of a row's action, for grouping by it
+const ACTION_NAME_SQL: &str = "CASE action WHEN 0 THEN 'invoice' WHEN 1 THEN 'stream' WHEN 2 THEN 'boost' \
+                               WHEN 3 THEN 'unknown' WHEN 4 THEN 'keysend' ELSE 'other' END";
+
+//The columns of the boosts table that come from the invoice, in the order that boost_from_row() expects them
+macro_rules! invoice_columns {
+    () => {
+        "idx, time, value_msat, value_msat_total, action, sender, app, message, podcast, episode, tlv, \
+         settle_index, state, payment_hash, \
+         feed_id, item_id, guid, episode_guid, ts, episode_time, url, speed, uuid, boost_link, \
+         sender_id, app_version, name, signature, tlv_hex, tlv_error, tlv_conflict, \
+         reply_address, reply_custom_key, reply_custom_value"
+    };
+}
+const INVOICE_COLUMNS: &str = invoice_columns!();
+//Followed by the columns the host manages, which storing an invoice again must leave alone
+const BOOST_COLUMNS: &str = concat!(invoice_columns!(), ", read, starred, archived, note");
+
+//The columns of the outgoing table in the order that outgoing_from_row() expects them
+const OUTGOING_COLUMNS: &str = "idx, boost_idx, time, value_msat, fee_msat, recipient, custom_key, custom_value, \
+                                sender, message, tlv, payment_hash, state, error, split_id, preimage, \
+                                payment_index, action, podcast, episode, app, feed_id";
+
+//The columns of the splits table in the order that split_from_row() expects them
+const SPLIT_COLUMNS: &str = "id, name, recipient, custom_key, custom_value, percent, sats, podcast, feed_id, created";
+
+
+//One of the htlcs that paid an invoice.  Multi-part payments arrive as several of these.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Htlc {
+    pub boost_index: u64,
+    pub htlc_index: u64,
+    pub chan_id: u64,
+    pub amt_msat: u64,
+    pub state: u8,
+}
+
+
+//A custom tlv record carried by one of the htlcs that paid an invoice
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CustomRecord {
+    pub boost_index: u64,
+    pub htlc_index: u64,
+    pub record_type: u64,
+    pub value: Vec<u8>,
+}
+
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct BoostRecord {
     pub index: u64,
+    pub settle_index: u64,
+    pub state: u8,
+    pub payment_hash: String,
     pub time: i64,
     pub value_msat: i64,
     pub value_msat_total: i64,
@@ -18,6 +110,223 @@ pub struct BoostRecord {
     pub podcast: String,
     pub episode: String,
     pub tlv: String,
+    //Podcasting 2.0 tlv fields
+    pub feed_id: Option<u64>,
+    pub item_id: Option<u64>,
+    pub guid: String,
+    pub episode_guid: String,
+    pub ts: Option<u64>,
+    pub episode_time: String,
+    pub url: String,
+    pub speed: String,
+    pub uuid: String,
+    pub boost_link: String,
+    pub sender_id: String,
+    pub app_version: String,
+    pub name: String,
+    pub signature: String,
+    //Where the sender's app takes boosts in reply: a node pubkey, and the custom record (if any)
+    //that picks out the sender's wallet behind it
+    pub reply_address: String,
+    pub reply_custom_key: Option<u64>,
+    pub reply_custom_value: String,
+    //Set when the podcast tlv wasn't valid utf-8 and was stored hex encoded instead
+    pub tlv_hex: bool,
+    //Why the podcast tlv couldn't be fully decoded, if it couldn't
+    pub tlv_error: String,
+    //Set when the htlcs of a multi-part payment carried records that disagree
+    pub tlv_conflict: bool,
+    //Kept by the host for their own bookkeeping (ex. which boosts were read on air)
+    pub read: bool,
+    pub starred: bool,
+    pub archived: bool,
+    pub note: String,
+}
+
+
+//A payment we sent, like a boost in reply to a listener.  Where it went is the recipient's node
+//pubkey along with the custom record, if any, that routes it to their wallet.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct OutgoingPayment {
+    pub index: u64,
+    //The boost this was a reply to, if it was one
+    pub boost_index: Option<u64>,
+    pub time: i64,
+    pub value_msat: i64,
+    pub fee_msat: i64,
+    pub recipient: String,
+    pub custom_key: Option<u64>,
+    pub custom_value: String,
+    pub sender: String,
+    pub message: String,
+    pub tlv: String,
+    pub payment_hash: String,
+    pub state: u8,
+    //Why the payment failed, if it did
+    pub error: String,
+    //The split this paid out, if it was one
+    pub split_id: Option<u64>,
+    //Kept so a retry pays the same hash, which LND will only ever pay once
+    #[serde(skip_serializing, default)]
+    pub preimage: String,
+    //LND's index for the payment, once we've seen it in the node's payment list
+    #[serde(default)]
+    pub payment_index: Option<u64>,
+    //What the podcast record we sent says it was
+    #[serde(default)]
+    pub action: u8,
+    #[serde(default)]
+    pub podcast: String,
+    #[serde(default)]
+    pub episode: String,
+    #[serde(default)]
+    pub app: String,
+    #[serde(default)]
+    pub feed_id: Option<u64>,
+}
+
+
+//What we've sent to a podcast: how many payments went through, what they added up to and what the
+//fees came to
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SentTotal {
+    pub podcast: String,
+    pub count: u64,
+    pub value_msat: i64,
+    pub fee_msat: i64,
+}
+
+
+//A share of incoming podcast payments that's passed on to someone else, like a co-host.  It's
+//either a percent of what arrived or a fixed number of sats, and applies to every podcast unless a
+//podcast name or feed id is given.  Only payments settled after it was added are split.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Split {
+    #[serde(default)]
+    pub id: u64,
+    #[serde(default)]
+    pub name: String,
+    pub recipient: String,
+    #[serde(default)]
+    pub custom_key: Option<u64>,
+    #[serde(default)]
+    pub custom_value: String,
+    #[serde(default)]
+    pub percent: Option<f64>,
+    #[serde(default)]
+    pub sats: Option<u64>,
+    #[serde(default)]
+    pub podcast: String,
+    #[serde(default)]
+    pub feed_id: Option<u64>,
+    #[serde(default)]
+    pub created: i64,
+}
+
+
+//Changes to the host's flags and note on a boost.  Anything left as None is kept as it is.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct BoostFlags {
+    pub read: Option<bool>,
+    pub starred: Option<bool>,
+    pub archived: Option<bool>,
+    pub note: Option<String>,
+}
+
+
+//Which settled payments get_boosts() should return.  Anything left as None isn't filtered on.  Text
+//fields match whole values, ignoring case.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BoostFilter {
+    pub action: Option<u8>,
+    pub read: Option<bool>,
+    pub starred: Option<bool>,
+    pub archived: Option<bool>,
+    //An FTS5 query over the message, sender, app, podcast and episode (ex. "great show" OR sender:bob)
+    pub query: Option<String>,
+    //Settled at or after since_time and before until_time, in unix seconds
+    pub since_time: Option<i64>,
+    pub until_time: Option<i64>,
+    //The podcast by name or by podcast index feed id
+    pub podcast: Option<String>,
+    pub feed_id: Option<u64>,
+    pub episode: Option<String>,
+    pub app: Option<String>,
+    pub sender: Option<String>,
+    //What arrived, in millisats
+    pub min_msat: Option<i64>,
+    pub max_msat: Option<i64>,
+}
+
+
+//Where a page of boosts starts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cursor {
+    //The newest boosts
+    Newest,
+    //The boosts just older than this index
+    Before(u64),
+    //The boosts just newer than this index
+    After(u64),
+}
+
+
+//A page of boosts, newest first, with the cursors for the pages on either side of it.  Pass next as
+//"before" to get older boosts and prev as "after" to get newer ones.  Either is None when there's
+//nothing more that way.
+#[derive(Serialize, Debug)]
+pub struct BoostPage {
+    pub boosts: Vec<BoostRecord>,
+    pub next: Option<u64>,
+    pub prev: Option<u64>,
+}
+
+
+//How to group payments when adding them up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatsGrouping {
+    Day,
+    Week,
+    Month,
+    Podcast,
+    Episode,
+    App,
+    Action,
+    Sender,
+}
+
+
+//The payments in one group added up.  For the time groupings the key is the first day of the period
+//(ex. 2021-11-01), otherwise it's the podcast, episode, app, action or sender name.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct StatsRow {
+    pub key: String,
+    //Which podcast an episode belongs to, when grouping by episode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub podcast: Option<String>,
+    pub count: u64,
+    pub value_msat: i64,
+    pub value_msat_total: i64,
+}
+
+
+//A token that lets a client like an overlay or bot read from the api.  The token itself is only
+//shown once, when it's created.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ApiToken {
+    pub id: u64,
+    pub name: String,
+    pub created: i64,
+    pub last_used: Option<i64>,
+}
+
+
+//A handle to the database.  It holds one long-lived connection, so clone it to share it rather
+//than opening the database again.  Calls block while they run, so keep them off the async executor.
+#[derive(Clone, Debug)]
+pub struct Database {
+    filepath: String,
+    conn: Arc<Mutex<Connection>>,
 }
 
 
@@ -30,27 +339,19 @@ impl fmt::Display for HydraError {
 }
 impl Error for HydraError {}
 
-
-fn connect_to_database(init: bool, filepath: &String) -> Result<Connection, Box<dyn Error>> {
-    if let Ok(conn) = Connection::open(filepath.as_str()) {
-        if init {
-            match set_database_file_permissions(filepath.as_str()) {
-                Ok(_) => {},
-                Err(e) => {
-                    eprintln!("{:#?}", e);
-                }
-            }
-            println!("Using database file: [{}]", filepath.as_str());
-        }
-        Ok(conn)
-    } else {
-        return Err(Box::new(HydraError(format!("Could not open a database file at: [{}].", filepath).into())))
+//A search query that FTS5 couldn't understand.  This is the caller's mistake, not a database problem.
+#[derive(Debug)]
+pub struct InvalidSearchQuery(pub String);
+impl fmt::Display for InvalidSearchQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid search query: {}", self.0)
     }
 }
+impl Error for InvalidSearchQuery {}
 
 
 //Set permissions on the database file
-fn set_database_file_permissions(filepath: &str) -> Result<bool, Box<dyn Error>> {
+fn set_database_file_permissions(filepath: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
 
     match std::fs::File::open(filepath) {
         Ok(fh) => {
@@ -73,156 +374,1303 @@ fn set_database_file_permissions(filepath: &str) -> Result<bool, Box<dyn Error>>
 }
 
 
-//Create a new database file if needed
-pub fn create_database(filepath: &String) -> Result<bool, Box<dyn Error>> {
-    let conn = connect_to_database(true, filepath)?;
+impl Database {
+    //Open the database, creating it if needed, and bring its schema up to date
+    pub fn open(filepath: &str) -> Result<Database, Box<dyn Error + Send + Sync>> {
+        let mut conn = match Connection::open(filepath) {
+            Ok(conn) => conn,
+            Err(e) => {
+                eprintln!("{}", e);
+                return Err(Box::new(HydraError(format!("Could not open a database file at: [{}].", filepath))))
+            }
+        };
+        if let Err(e) = set_database_file_permissions(filepath) {
+            eprintln!("{:#?}", e);
+        }
+        println!("Using database file: [{}]", filepath);
 
-    match conn.execute(
-        "CREATE TABLE IF NOT EXISTS boosts (
-             idx integer primary key,
-             time integer,
-             value_msat integer,
-             value_msat_total integer,
-             action integer,
-             sender text,
-             app text,
-             message text,
-             podcast text,
-             episode text,
-             tlv text
-         )",
-        [],
-    ) {
-        Ok(_) => {
-            Ok(true)
+        //Write ahead logging keeps other readers of the file (ex. the export command or sqlite3) from
+        //waiting on our writes.  Within Helipad every call still takes its turn on the one connection.
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+
+        migrations::migrate(&mut conn, filepath)?;
+
+        Ok(Database {
+            filepath: filepath.to_string(),
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    //Take the connection for the length of one call
+    fn connection(&self) -> Result<MutexGuard<'_, Connection>, Box<dyn Error + Send + Sync>> {
+        match self.conn.lock() {
+            Ok(conn) => Ok(conn),
+            Err(_) => Err(Box::new(HydraError(format!("Database connection is unusable: [{}].", self.filepath)))),
         }
-        Err(e) => {
-            eprintln!("{}", e);
-            return Err(Box::new(HydraError(format!("Failed to create database: [{}].", filepath).into())))
+    }
+
+
+    //Add an invoice to the database, or update it if we have seen it before
+    pub fn add_invoice(&self, boost: BoostRecord) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+        upsert_invoice(&conn, &boost)?;
+
+        Ok(true)
+    }
+
+
+    //Store an invoice along with the htlcs that paid it and their custom records, all or nothing.  What
+    //was stored for it before is replaced, so htlcs and records LND no longer reports don't linger.
+    pub fn store_invoice(&self, boost: &BoostRecord, htlcs: &[Htlc], records: &[CustomRecord]) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let mut conn = self.connection()?;
+        let tx = conn.transaction()?;
+
+        tx.execute("DELETE FROM htlcs WHERE boost_idx = ?1", params![boost.index])?;
+        tx.execute("DELETE FROM custom_records WHERE boost_idx = ?1", params![boost.index])?;
+        upsert_invoice(&tx, boost)?;
+        insert_htlcs(&tx, htlcs)?;
+        insert_custom_records(&tx, records)?;
+        tx.commit()?;
+
+        Ok(true)
+    }
+
+
+    //Record a new state for an invoice we already have.  Does nothing if the invoice isn't stored.
+    pub fn update_invoice_state(&self, index: u64, state: u8) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+
+        match conn.execute("UPDATE boosts SET state = ?1 WHERE idx = ?2", params![state, index]) {
+            Ok(updated) => {
+                Ok(updated > 0)
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                Err(Box::new(HydraError(format!("Failed to update invoice state: [{}].", index))))
+            }
+        }
+    }
+
+
+    //Get a page of the settled payments that match the filter (ex. unread boosts), starting from the cursor
+    pub fn get_boosts(&self, filter: &BoostFilter, cursor: Cursor, max: u64) -> Result<BoostPage, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+
+        let (filters, mut values) = filter_conditions(filter);
+        let (bound, order) = match cursor {
+            Cursor::Newest => ("", "DESC"),
+            Cursor::Before(index) => {
+                values.push(Value::Integer(index as i64));
+                ("AND idx < ?", "DESC")
+            }
+            Cursor::After(index) => {
+                values.push(Value::Integer(index as i64));
+                ("AND idx > ?", "ASC")
+            }
+        };
+        //One extra row tells us whether there's another page past this one
+        values.push(Value::Integer(max.saturating_add(1) as i64));
+
+        let sqltxt = format!("SELECT {} \
+                                     FROM boosts \
+                                     WHERE state = {} \
+                                       {} \
+                                       {} \
+                                     ORDER BY idx {} \
+                                     LIMIT ?", BOOST_COLUMNS, INVOICE_STATE_SETTLED, filters, bound, order);
+
+        //Prepare and execute the query
+        let mut stmt = conn.prepare(sqltxt.as_str())?;
+        let rows = stmt.query_map(params_from_iter(values), boost_from_row)?;
+
+        //Parse the results
+        let mut boosts = match rows.collect::<rusqlite::Result<Vec<BoostRecord>>>() {
+            Ok(boosts) => boosts,
+            Err(e) => return Err(search_error(e, filter)),
+        };
+        let more = boosts.len() as u64 > max;
+        boosts.truncate(max as usize);
+        if let Cursor::After(_) = cursor {
+            boosts.reverse();
+        }
+
+        //Work out the cursors.  The side we paged towards is known from the extra row, the other
+        //side needs a look.
+        let newest = boosts.first().map(|b| b.index);
+        let oldest = boosts.last().map(|b| b.index);
+        let (next, prev) = match cursor {
+            Cursor::Newest => (oldest.filter(|_| more), None),
+            Cursor::Before(index) => {
+                let newer_than = newest.unwrap_or_else(|| index.saturating_sub(1));
+                let prev = Some(newer_than).filter(|_| boosts_exist(&conn, filter, "idx > ?", newer_than).unwrap_or(false));
+                (oldest.filter(|_| more), prev)
+            }
+            Cursor::After(index) => {
+                let older_than = oldest.unwrap_or_else(|| index.saturating_add(1));
+                let next = Some(older_than).filter(|_| boosts_exist(&conn, filter, "idx < ?", older_than).unwrap_or(false));
+                (next, newest.filter(|_| more))
+            }
+        };
+
+        Ok(BoostPage {
+            boosts,
+            next,
+            prev,
+        })
+    }
+
+
+    //Add up the settled payments that match the filter
+    pub fn get_totals(&self, filter: &BoostFilter) -> Result<StatsRow, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+
+        let (filters, values) = filter_conditions(filter);
+        let sqltxt = format!("SELECT COUNT(*), COALESCE(SUM(value_msat), 0), COALESCE(SUM(value_msat_total), 0) \
+                              FROM boosts \
+                              WHERE state = {} \
+                                {}", INVOICE_STATE_SETTLED, filters);
+
+        let totals = conn.query_row(sqltxt.as_str(), params_from_iter(values), |row| {
+            Ok(StatsRow {
+                key: "total".to_string(),
+                podcast: None,
+                count: row.get(0)?,
+                value_msat: row.get(1)?,
+                value_msat_total: row.get(2)?,
+            })
+        });
+
+        match totals {
+            Ok(totals) => Ok(totals),
+            Err(e) => Err(search_error(e, filter)),
+        }
+    }
+
+
+    //Add up the settled payments that match the filter in groups.  Time periods come back in order and
+    //are in UTC.  Everything else comes back biggest earner first.  At most max groups, or all of them.
+    pub fn get_stats(&self, filter: &BoostFilter, grouping: StatsGrouping, max: Option<u64>) -> Result<Vec<StatsRow>, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+
+        let (key, podcast, order) = match grouping {
+            StatsGrouping::Day => ("date(time, 'unixepoch')", "NULL", "key ASC"),
+            StatsGrouping::Week => ("date(time, 'unixepoch', 'weekday 0', '-6 days')", "NULL", "key ASC"),
+            StatsGrouping::Month => ("date(time, 'unixepoch', 'start of month')", "NULL", "key ASC"),
+            StatsGrouping::Podcast => ("COALESCE(podcast, '')", "NULL", "2 DESC"),
+            StatsGrouping::Episode => ("COALESCE(episode, '')", "COALESCE(podcast, '')", "2 DESC"),
+            StatsGrouping::App => ("COALESCE(app, '')", "NULL", "2 DESC"),
+            StatsGrouping::Action => (ACTION_NAME_SQL, "NULL", "2 DESC"),
+            StatsGrouping::Sender => ("COALESCE(sender, '')", "NULL", "2 DESC"),
+        };
+
+        //A negative limit is no limit at all
+        let (filters, mut values) = filter_conditions(filter);
+        values.push(Value::Integer(max.map(|m| m as i64).unwrap_or(-1)));
+
+        let sqltxt = format!("SELECT {} AS key, COALESCE(SUM(value_msat), 0), COALESCE(SUM(value_msat_total), 0), COUNT(*), {} AS podcast_key \
+                              FROM boosts \
+                              WHERE state = {} \
+                                {} \
+                              GROUP BY key, podcast_key \
+                              ORDER BY {} \
+                              LIMIT ?", key, podcast, INVOICE_STATE_SETTLED, filters, order);
+
+        let mut stmt = conn.prepare(sqltxt.as_str())?;
+        let rows = stmt.query_map(params_from_iter(values), |row| {
+            Ok(StatsRow {
+                key: row.get(0)?,
+                value_msat: row.get(1)?,
+                value_msat_total: row.get(2)?,
+                count: row.get(3)?,
+                podcast: row.get(4)?,
+            })
+        })?;
+
+        match rows.collect::<rusqlite::Result<Vec<StatsRow>>>() {
+            Ok(stats) => Ok(stats),
+            Err(e) => Err(search_error(e, filter)),
+        }
+    }
+
+
+    //The biggest settled payments that match the filter, by what arrived
+    pub fn get_largest_boosts(&self, filter: &BoostFilter, max: u64) -> Result<Vec<BoostRecord>, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+
+        let (filters, mut values) = filter_conditions(filter);
+        values.push(Value::Integer(max as i64));
+
+        let sqltxt = format!("SELECT {} \
+                              FROM boosts \
+                              WHERE state = {} \
+                                {} \
+                              ORDER BY value_msat DESC, idx DESC \
+                              LIMIT ?", BOOST_COLUMNS, INVOICE_STATE_SETTLED, filters);
+
+        let mut stmt = conn.prepare(sqltxt.as_str())?;
+        let rows = stmt.query_map(params_from_iter(values), boost_from_row)?;
+
+        match rows.collect::<rusqlite::Result<Vec<BoostRecord>>>() {
+            Ok(boosts) => Ok(boosts),
+            Err(e) => Err(search_error(e, filter)),
+        }
+    }
+
+
+    //Get one payment by its index
+    pub fn get_boost(&self, index: u64) -> Result<Option<BoostRecord>, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+
+        let sqltxt = format!("SELECT {} FROM boosts WHERE idx = ?1", BOOST_COLUMNS);
+        let mut stmt = conn.prepare(sqltxt.as_str())?;
+        let mut rows = stmt.query_map(params![index], boost_from_row)?;
+
+        match rows.next() {
+            Some(boost) => Ok(Some(boost?)),
+            None => Ok(None)
         }
     }
+
+
+    //Change the host's flags and note on a payment.  Returns false if there's no such payment.
+    pub fn set_boost_flags(&self, index: u64, flags: &BoostFlags) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+
+        match conn.execute("UPDATE boosts \
+                            SET read = COALESCE(?1, read), \
+                                starred = COALESCE(?2, starred), \
+                                archived = COALESCE(?3, archived), \
+                                note = COALESCE(?4, note) \
+                            WHERE idx = ?5",
+                           params![flags.read, flags.starred, flags.archived, flags.note, index]) {
+            Ok(updated) => {
+                Ok(updated > 0)
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                Err(Box::new(HydraError(format!("Failed to update flags for boost: [{}].", index))))
+            }
+        }
+    }
+
+
+    //Get the last boost index number from the database
+    pub fn get_last_boost_index(&self) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+
+        Ok(conn.query_row("SELECT IFNULL(MAX(idx), 0) FROM boosts", [], |row| row.get(0))?)
+    }
+
+
+    //Get the highest settle index we have stored.  Subscribing from here replays anything settled since.
+    pub fn get_last_settle_index(&self) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+
+        let index: u64 = conn.query_row("SELECT IFNULL(MAX(settle_index), 0) FROM boosts", [], |row| row.get(0))?;
+
+        Ok(index)
+    }
+
+
+    //Get the invoice index to start a catch up sweep from.  That's the newest invoice we have, unless
+    //there are older ones we stored before they were settled, which need to be looked at again.
+    pub fn get_sweep_start_index(&self) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+
+        let unsettled: Option<u64> = conn.query_row("SELECT MIN(idx) FROM boosts WHERE state IN (?1, ?2)",
+                                                    params![INVOICE_STATE_OPEN, INVOICE_STATE_ACCEPTED],
+                                                    |row| row.get(0))?;
+        match unsettled {
+            Some(index) => Ok(index.saturating_sub(1)),
+            None => {
+                drop(conn);
+                self.get_last_boost_index()
+            }
+        }
+    }
+
+
+    //Get a page of the payments that carry a podcast tlv or other custom records, in index order,
+    //starting after the given index
+    pub fn get_boosts_with_records(&self, index: u64, max: u64) -> Result<Vec<BoostRecord>, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+        let mut boosts: Vec<BoostRecord> = Vec::new();
+
+        let sqltxt = format!("SELECT {} \
+                                     FROM boosts \
+                                     WHERE (tlv != '' OR idx IN (SELECT boost_idx FROM custom_records)) \
+                                       AND idx > :index \
+                                     ORDER BY idx ASC \
+                                     LIMIT :max", BOOST_COLUMNS);
+
+        //Prepare and execute the query
+        let mut stmt = conn.prepare(sqltxt.as_str())?;
+        let rows = stmt.query_map(&[(":index", index.to_string().as_str()), (":max", max.to_string().as_str())], boost_from_row)?;
+
+        //Parse the results
+        for row in rows {
+            boosts.push(row?);
+        }
+
+        Ok(boosts)
+    }
+
+
+    //Get the htlcs stored for a boost, in htlc order
+    pub fn get_htlcs(&self, index: u64) -> Result<Vec<Htlc>, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+        let mut htlcs: Vec<Htlc> = Vec::new();
+
+        let mut stmt = conn.prepare("SELECT boost_idx, htlc_index, chan_id, amt_msat, state \
+                                     FROM htlcs \
+                                     WHERE boost_idx = ?1 \
+                                     ORDER BY htlc_index ASC")?;
+        let rows = stmt.query_map(params![index], |row| {
+            Ok(Htlc {
+                boost_index: row.get(0)?,
+                htlc_index: row.get(1)?,
+                chan_id: row.get::<_, i64>(2)? as u64,
+                amt_msat: row.get(3)?,
+                state: row.get(4)?,
+            })
+        })?;
+
+        for row in rows {
+            htlcs.push(row?);
+        }
+
+        Ok(htlcs)
+    }
+
+
+    //Get the custom records stored for a boost, in htlc order
+    pub fn get_custom_records(&self, index: u64) -> Result<Vec<CustomRecord>, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+        let mut records: Vec<CustomRecord> = Vec::new();
+
+        let mut stmt = conn.prepare("SELECT boost_idx, htlc_index, record_type, value \
+                                     FROM custom_records \
+                                     WHERE boost_idx = ?1 \
+                                     ORDER BY htlc_index ASC, rowid ASC")?;
+        let rows = stmt.query_map(params![index], |row| {
+            Ok(CustomRecord {
+                boost_index: row.get(0)?,
+                htlc_index: row.get(1)?,
+                record_type: row.get::<_, i64>(2)? as u64,
+                value: row.get(3)?,
+            })
+        })?;
+
+        for row in rows {
+            records.push(row?);
+        }
+
+        Ok(records)
+    }
+
+
+    //Store a payment we're about to send and return its index
+    pub fn add_outgoing(&self, payment: &OutgoingPayment) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+
+        insert_outgoing(&conn, payment)
+    }
+
+
+    //Store a payment found in the node's payment list and return its index.  One we sent ourselves
+    //is matched on its payment hash and brought up to date instead (the newest attempt, if it took
+    //more than one).  One we haven't seen before is only kept if it carried a podcast record and hasn't
+    //failed, otherwise None is returned.
+    pub fn add_sent_payment(&self, payment: &OutgoingPayment) -> Result<Option<u64>, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+
+        let existing: Option<u64> = conn.query_row("SELECT MAX(idx) FROM outgoing WHERE payment_hash = ?1",
+                                                   params![payment.payment_hash],
+                                                   |row| row.get(0))?;
+        match existing {
+            Some(index) => {
+                match conn.execute("UPDATE outgoing \
+                                    SET payment_index = ?1, state = ?2, fee_msat = ?3, \
+                                        error = CASE WHEN ?2 = ?4 THEN ?5 ELSE error END \
+                                    WHERE idx = ?6",
+                                   params![payment.payment_index, payment.state, payment.fee_msat,
+                                           OUTGOING_STATE_FAILED, payment.error, index]) {
+                    Ok(_) => Ok(Some(index)),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        Err(Box::new(HydraError(format!("Failed to update outgoing payment: [{}].", index))))
+                    }
+                }
+            }
+            None if payment.tlv.is_empty() || payment.state == OUTGOING_STATE_FAILED => Ok(None),
+            None => insert_outgoing(&conn, payment).map(Some),
+        }
+    }
+
+
+    //Get the payment index to start a sweep of the node's payment list from.  That's as far as the
+    //last sweep read, unless some stored payments were still in flight, which need to be looked at again.
+    pub fn get_payment_sweep_start_index(&self) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+
+        let in_flight: Option<u64> = conn.query_row("SELECT MIN(payment_index) FROM outgoing WHERE state = ?1",
+                                                    params![OUTGOING_STATE_IN_FLIGHT],
+                                                    |row| row.get(0))?;
+        if let Some(index) = in_flight {
+            return Ok(index.saturating_sub(1));
+        }
+
+        let newest: Option<u64> = conn.query_row("SELECT MAX(idx) FROM ( \
+                                                      SELECT MAX(payment_index) AS idx FROM outgoing \
+                                                      UNION ALL \
+                                                      SELECT value FROM sync_state WHERE name = ?1)",
+                                                 params![SYNC_PAYMENT_SWEEP_INDEX],
+                                                 |row| row.get(0))?;
+        Ok(newest.unwrap_or(0))
+    }
+
+
+    //Record how far a sweep of the node's payment list has read.  Going back over in flight payments
+    //never moves it backwards.
+    pub fn set_payment_sweep_index(&self, index: u64) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+
+        match conn.execute("INSERT INTO sync_state (name, value) VALUES (?1, ?2) \
+                            ON CONFLICT(name) DO UPDATE SET value = MAX(value, excluded.value)",
+                           params![SYNC_PAYMENT_SWEEP_INDEX, index]) {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                eprintln!("{}", e);
+                Err(Box::new(HydraError(format!("Failed to record the payment sweep index: [{}].", index))))
+            }
+        }
+    }
+
+
+    //Get a page of the podcast payments sent from the node, newest first, starting before the given
+    //index.  Split payouts are left out, they're passed on rather than sent.
+    pub fn get_sent(&self, action: Option<u8>, before: Option<u64>, max: u64) -> Result<Vec<OutgoingPayment>, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+        let mut payments: Vec<OutgoingPayment> = Vec::new();
+
+        let sqltxt = format!("SELECT {} FROM outgoing \
+                              WHERE split_id IS NULL AND action != ?1 \
+                                AND (?2 IS NULL OR action = ?2) \
+                                AND (?3 IS NULL OR idx < ?3) \
+                              ORDER BY idx DESC LIMIT ?4", OUTGOING_COLUMNS);
+        let mut stmt = conn.prepare(sqltxt.as_str())?;
+        let rows = stmt.query_map(params![ACTION_KEYSEND, action, before, max], outgoing_from_row)?;
+
+        for row in rows {
+            payments.push(row?);
+        }
+
+        Ok(payments)
+    }
+
+
+    //Add up what went through to each podcast we've sent to, biggest first.  Sent at or after
+    //since_time and before until_time, when given.
+    pub fn get_sent_totals(&self, since_time: Option<i64>, until_time: Option<i64>) -> Result<Vec<SentTotal>, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+        let mut totals: Vec<SentTotal> = Vec::new();
+
+        let mut stmt = conn.prepare("SELECT MAX(podcast), COUNT(*), SUM(value_msat), SUM(fee_msat) \
+                                     FROM outgoing \
+                                     WHERE state = ?1 AND split_id IS NULL AND podcast != '' \
+                                       AND (?2 IS NULL OR time >= ?2) \
+                                       AND (?3 IS NULL OR time < ?3) \
+                                     GROUP BY podcast COLLATE NOCASE \
+                                     ORDER BY SUM(value_msat) DESC")?;
+        let rows = stmt.query_map(params![OUTGOING_STATE_SUCCEEDED, since_time, until_time], |row| {
+            Ok(SentTotal {
+                podcast: row.get(0)?,
+                count: row.get(1)?,
+                value_msat: row.get(2)?,
+                fee_msat: row.get(3)?,
+            })
+        })?;
+
+        for row in rows {
+            totals.push(row?);
+        }
+
+        Ok(totals)
+    }
+
+
+    //Record how an outgoing payment turned out.  Returns false if there's no such payment.
+    pub fn update_outgoing_result(&self, index: u64, state: u8, fee_msat: i64, error: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+
+        match conn.execute("UPDATE outgoing SET state = ?1, fee_msat = ?2, error = ?3 WHERE idx = ?4",
+                           params![state, fee_msat, error, index]) {
+            Ok(updated) => Ok(updated > 0),
+            Err(e) => {
+                eprintln!("{}", e);
+                Err(Box::new(HydraError(format!("Failed to update outgoing payment: [{}].", index))))
+            }
+        }
+    }
+
+
+    //Get one outgoing payment by its index
+    pub fn get_outgoing(&self, index: u64) -> Result<Option<OutgoingPayment>, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+
+        let sqltxt = format!("SELECT {} FROM outgoing WHERE idx = ?1", OUTGOING_COLUMNS);
+        let mut stmt = conn.prepare(sqltxt.as_str())?;
+        let mut rows = stmt.query_map(params![index], outgoing_from_row)?;
+
+        match rows.next() {
+            Some(payment) => Ok(Some(payment?)),
+            None => Ok(None)
+        }
+    }
+
+
+    //Get the replies sent to a boost, oldest first
+    pub fn get_replies(&self, boost_index: u64) -> Result<Vec<OutgoingPayment>, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+        let mut payments: Vec<OutgoingPayment> = Vec::new();
+
+        let sqltxt = format!("SELECT {} FROM outgoing WHERE boost_idx = ?1 AND split_id IS NULL ORDER BY idx ASC", OUTGOING_COLUMNS);
+        let mut stmt = conn.prepare(sqltxt.as_str())?;
+        let rows = stmt.query_map(params![boost_index], outgoing_from_row)?;
+
+        for row in rows {
+            payments.push(row?);
+        }
+
+        Ok(payments)
+    }
+
+
+    //Get the split payouts made for a boost (or for every boost), newest first.  Each attempt is
+    //its own payment.
+    pub fn get_payouts(&self, boost_index: Option<u64>, state: Option<u8>, max: u64) -> Result<Vec<OutgoingPayment>, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+        let mut payments: Vec<OutgoingPayment> = Vec::new();
+
+        let sqltxt = format!("SELECT {} FROM outgoing \
+                              WHERE split_id IS NOT NULL AND (?1 IS NULL OR boost_idx = ?1) AND (?2 IS NULL OR state = ?2) \
+                              ORDER BY idx DESC LIMIT ?3", OUTGOING_COLUMNS);
+        let mut stmt = conn.prepare(sqltxt.as_str())?;
+        let rows = stmt.query_map(params![boost_index, state, max], outgoing_from_row)?;
+
+        for row in rows {
+            payments.push(row?);
+        }
+
+        Ok(payments)
+    }
+
+
+    //Get every attempt at paying a split of a boost, oldest first
+    pub fn get_payout_attempts(&self, boost_index: u64, split_id: u64) -> Result<Vec<OutgoingPayment>, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+        let mut payments: Vec<OutgoingPayment> = Vec::new();
+
+        let sqltxt = format!("SELECT {} FROM outgoing WHERE boost_idx = ?1 AND split_id = ?2 ORDER BY idx ASC", OUTGOING_COLUMNS);
+        let mut stmt = conn.prepare(sqltxt.as_str())?;
+        let rows = stmt.query_map(params![boost_index, split_id], outgoing_from_row)?;
+
+        for row in rows {
+            payments.push(row?);
+        }
+
+        Ok(payments)
+    }
+
+
+    //Add a split and return it with its id
+    pub fn add_split(&self, split: &Split) -> Result<Split, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+
+        match conn.execute("INSERT INTO splits (name, recipient, custom_key, custom_value, percent, sats, podcast, feed_id, created) \
+                            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                           params![split.name,
+                                   split.recipient,
+                                   split.custom_key,
+                                   split.custom_value,
+                                   split.percent,
+                                   split.sats,
+                                   split.podcast,
+                                   split.feed_id,
+                                   split.created]) {
+            Ok(_) => Ok(Split {
+                id: conn.last_insert_rowid() as u64,
+                ..split.clone()
+            }),
+            Err(e) => {
+                eprintln!("{}", e);
+                Err(Box::new(HydraError(format!("Failed to store split to: [{}].", split.recipient))))
+            }
+        }
+    }
+
+
+    //Get every split, in the order they're paid
+    pub fn get_splits(&self) -> Result<Vec<Split>, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+        let mut splits: Vec<Split> = Vec::new();
+
+        let sqltxt = format!("SELECT {} FROM splits ORDER BY id ASC", SPLIT_COLUMNS);
+        let mut stmt = conn.prepare(sqltxt.as_str())?;
+        let rows = stmt.query_map([], split_from_row)?;
+
+        for row in rows {
+            splits.push(row?);
+        }
+
+        Ok(splits)
+    }
+
+
+    //Get the splits that apply to a payment, in the order they're paid
+    pub fn get_splits_for(&self, boost: &BoostRecord) -> Result<Vec<Split>, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+        let mut splits: Vec<Split> = Vec::new();
+
+        let sqltxt = format!("SELECT {} FROM splits \
+                              WHERE created <= ?1 \
+                                AND (podcast = '' OR podcast = ?2 COLLATE NOCASE) \
+                                AND (feed_id IS NULL OR feed_id = ?3) \
+                              ORDER BY id ASC", SPLIT_COLUMNS);
+        let mut stmt = conn.prepare(sqltxt.as_str())?;
+        let rows = stmt.query_map(params![boost.time, boost.podcast, boost.feed_id], split_from_row)?;
+
+        for row in rows {
+            splits.push(row?);
+        }
+
+        Ok(splits)
+    }
+
+
+    //Remove a split.  Payouts already made for it are kept.  Returns false if there was no such split.
+    pub fn delete_split(&self, id: u64) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+
+        let deleted = conn.execute("DELETE FROM splits WHERE id = ?1", params![id])?;
+
+        Ok(deleted > 0)
+    }
+
+
+    //Get the podcast payments that still owe a split payout: ones that were never attempted, or
+    //whose attempts all failed and there have been fewer than max_attempts of them.  A payout that
+    //was skipped isn't owed.  Oldest first.
+    pub fn get_boosts_owing_payouts(&self, max_attempts: u64, max: u64) -> Result<Vec<u64>, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+        let mut indexes: Vec<u64> = Vec::new();
+
+        let mut stmt = conn.prepare("SELECT DISTINCT boosts.idx FROM splits \
+                                     JOIN boosts ON boosts.time >= splits.created \
+                                                AND (splits.podcast = '' OR boosts.podcast = splits.podcast COLLATE NOCASE) \
+                                                AND (splits.feed_id IS NULL OR boosts.feed_id = splits.feed_id) \
+                                     WHERE boosts.state = ?1 \
+                                       AND boosts.action IN (?2, ?3) \
+                                       AND NOT EXISTS (SELECT 1 FROM outgoing \
+                                                       WHERE outgoing.boost_idx = boosts.idx AND outgoing.split_id = splits.id \
+                                                         AND outgoing.state IN (?4, ?7)) \
+                                       AND (SELECT COUNT(*) FROM outgoing \
+                                            WHERE outgoing.boost_idx = boosts.idx AND outgoing.split_id = splits.id) < ?5 \
+                                     ORDER BY boosts.idx ASC \
+                                     LIMIT ?6")?;
+        let rows = stmt.query_map(params![INVOICE_STATE_SETTLED, ACTION_STREAM, ACTION_BOOST, OUTGOING_STATE_SUCCEEDED,
+                                          max_attempts, max, OUTGOING_STATE_SKIPPED],
+                                  |row| row.get(0))?;
+
+        for row in rows {
+            indexes.push(row?);
+        }
+
+        Ok(indexes)
+    }
+
+
+    //Start a login session that lasts until the expires time.  Sessions that have already expired
+    //are cleared out while we're here.
+    pub fn add_session(&self, token_hash: &str, created: i64, expires: i64) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+
+        conn.execute("DELETE FROM sessions WHERE expires <= ?1", params![created])?;
+        match conn.execute("INSERT INTO sessions (token_hash, created, expires) VALUES (?1, ?2, ?3)",
+                           params![token_hash, created, expires]) {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                eprintln!("{}", e);
+                Err(Box::new(HydraError("Failed to store the login session.".to_string())))
+            }
+        }
+    }
+
+
+    //Whether there's a login session with this hash that hasn't expired yet
+    pub fn session_is_valid(&self, token_hash: &str, now: i64) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM sessions WHERE token_hash = ?1 AND expires > ?2",
+                                        params![token_hash, now], |row| row.get(0))?;
+
+        Ok(count > 0)
+    }
+
+
+    //End a login session.  Returns false if there was no such session.
+    pub fn delete_session(&self, token_hash: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+
+        let deleted = conn.execute("DELETE FROM sessions WHERE token_hash = ?1", params![token_hash])?;
+
+        Ok(deleted > 0)
+    }
+
+
+    //Store a new api token and return it with its id
+    pub fn add_api_token(&self, name: &str, token_hash: &str, created: i64) -> Result<ApiToken, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+
+        match conn.execute("INSERT INTO api_tokens (name, token_hash, created) VALUES (?1, ?2, ?3)",
+                           params![name, token_hash, created]) {
+            Ok(_) => Ok(ApiToken {
+                id: conn.last_insert_rowid() as u64,
+                name: name.to_string(),
+                created,
+                last_used: None,
+            }),
+            Err(e) => {
+                eprintln!("{}", e);
+                Err(Box::new(HydraError(format!("Failed to store api token: [{}].", name))))
+            }
+        }
+    }
+
+
+    //Get every api token, oldest first
+    pub fn get_api_tokens(&self) -> Result<Vec<ApiToken>, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+        let mut tokens: Vec<ApiToken> = Vec::new();
+
+        let mut stmt = conn.prepare("SELECT id, name, created, last_used FROM api_tokens ORDER BY id ASC")?;
+        let rows = stmt.query_map([], api_token_from_row)?;
+
+        for row in rows {
+            tokens.push(row?);
+        }
+
+        Ok(tokens)
+    }
+
+
+    //Look up the api token with this hash and note that it was used
+    pub fn use_api_token(&self, token_hash: &str, now: i64) -> Result<Option<ApiToken>, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+
+        if conn.execute("UPDATE api_tokens SET last_used = ?1 WHERE token_hash = ?2", params![now, token_hash])? == 0 {
+            return Ok(None);
+        }
+
+        let token = conn.query_row("SELECT id, name, created, last_used FROM api_tokens WHERE token_hash = ?1",
+                                   params![token_hash], api_token_from_row)?;
+
+        Ok(Some(token))
+    }
+
+
+    //Revoke an api token.  Returns false if there was no such token.
+    pub fn delete_api_token(&self, id: u64) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let conn = self.connection()?;
+
+        let deleted = conn.execute("DELETE FROM api_tokens WHERE id = ?1", params![id])?;
+
+        Ok(deleted > 0)
+    }
 }
 
 
-//Add an invoice to the database
-pub fn add_invoice_to_db(filepath: &String, boost: BoostRecord) -> Result<bool, Box<dyn Error>> {
-    let conn = connect_to_database(false, filepath)?;
+//The name of a payment category (ex. "boost")
+pub fn action_name(action: u8) -> &'static str {
+    ACTION_NAMES.iter().find(|(a, _)| *a == action).map(|(_, name)| *name).unwrap_or("other")
+}
+
+//The payment category with the given name, if there is one
+pub fn action_from_name(name: &str) -> Option<u8> {
+    ACTION_NAMES.iter().find(|(_, n)| *n == name).map(|(action, _)| *action)
+}
+
+//Turn a filter into conditions for the WHERE clause of a boosts query, along with the values to bind
+//to their placeholders in order
+fn filter_conditions(filter: &BoostFilter) -> (String, Vec<Value>) {
+    let mut conditions = String::new();
+    let mut values: Vec<Value> = Vec::new();
+
+    if let Some(action) = filter.action {
+        conditions += "AND action = ? ";
+        values.push(Value::Integer(action as i64));
+    }
+    for (column, value) in [("read", filter.read), ("starred", filter.starred), ("archived", filter.archived)] {
+        if let Some(value) = value {
+            conditions += &format!("AND {} = ? ", column);
+            values.push(Value::Integer(value as i64));
+        }
+    }
+    if let Some(query) = &filter.query {
+        conditions += "AND idx IN (SELECT rowid FROM boosts_fts WHERE boosts_fts MATCH ?) ";
+        values.push(Value::Text(query.clone()));
+    }
+    if let Some(since_time) = filter.since_time {
+        conditions += "AND time >= ? ";
+        values.push(Value::Integer(since_time));
+    }
+    if let Some(until_time) = filter.until_time {
+        conditions += "AND time < ? ";
+        values.push(Value::Integer(until_time));
+    }
+    if let Some(podcast) = &filter.podcast {
+        conditions += "AND podcast = ? COLLATE NOCASE ";
+        values.push(Value::Text(podcast.clone()));
+    }
+    if let Some(feed_id) = filter.feed_id {
+        conditions += "AND feed_id = ? ";
+        values.push(Value::Integer(feed_id as i64));
+    }
+    for (column, value) in [("episode", &filter.episode), ("app", &filter.app), ("sender", &filter.sender)] {
+        if let Some(value) = value {
+            conditions += &format!("AND {} = ? COLLATE NOCASE ", column);
+            values.push(Value::Text(value.clone()));
+        }
+    }
+    if let Some(min_msat) = filter.min_msat {
+        conditions += "AND value_msat >= ? ";
+        values.push(Value::Integer(min_msat));
+    }
+    if let Some(max_msat) = filter.max_msat {
+        conditions += "AND value_msat <= ? ";
+        values.push(Value::Integer(max_msat));
+    }
 
-    match conn.execute("INSERT INTO boosts (idx, time, value_msat, value_msat_total, action, sender, app, message, podcast, episode, tlv) \
-                                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+    (conditions, values)
+}
+
+//Whether any settled payments that match the filter are on the given side of an index
+fn boosts_exist(conn: &Connection, filter: &BoostFilter, bound: &str, index: u64) -> rusqlite::Result<bool> {
+    let (filters, mut values) = filter_conditions(filter);
+    values.push(Value::Integer(index as i64));
+
+    let sqltxt = format!("SELECT EXISTS (SELECT 1 FROM boosts WHERE state = {} {} AND {})",
+                         INVOICE_STATE_SETTLED, filters, bound);
+    conn.query_row(sqltxt.as_str(), params_from_iter(values), |row| row.get(0))
+}
+
+//FTS5 reports a bad query when the rows are read, so tell those apart from real database errors
+fn search_error(e: rusqlite::Error, filter: &BoostFilter) -> Box<dyn Error + Send + Sync> {
+    match e {
+        rusqlite::Error::SqliteFailure(_, Some(message)) if filter.query.is_some()
+            && (message.starts_with("fts5:") || message.starts_with("no such column") || message == "unterminated string") => {
+            Box::new(InvalidSearchQuery(message))
+        }
+        e => Box::new(e),
+    }
+}
+
+//Map a row selected with OUTGOING_COLUMNS
+fn outgoing_from_row(row: &rusqlite::Row) -> rusqlite::Result<OutgoingPayment> {
+    Ok(OutgoingPayment {
+        index: row.get(0)?,
+        boost_index: row.get(1)?,
+        time: row.get(2)?,
+        value_msat: row.get(3)?,
+        fee_msat: row.get(4)?,
+        recipient: row.get(5)?,
+        custom_key: row.get(6)?,
+        custom_value: row.get(7)?,
+        sender: row.get(8)?,
+        message: row.get(9)?,
+        tlv: row.get(10)?,
+        payment_hash: row.get(11)?,
+        state: row.get(12)?,
+        error: row.get(13)?,
+        split_id: row.get(14)?,
+        preimage: row.get(15)?,
+        payment_index: row.get(16)?,
+        action: row.get(17)?,
+        podcast: row.get(18)?,
+        episode: row.get(19)?,
+        app: row.get(20)?,
+        feed_id: row.get(21)?,
+    })
+}
+
+//Insert a boost, or overwrite everything but its index when it's already stored
+fn upsert_invoice(conn: &Connection, boost: &BoostRecord) -> Result<(), Box<dyn Error + Send + Sync>> {
+    //Every invoice column but the index gets overwritten when the invoice is already stored
+    let columns: Vec<&str> = INVOICE_COLUMNS.split(',').map(|c| c.trim()).collect();
+    let placeholders: Vec<String> = (1..=columns.len()).map(|i| format!("?{}", i)).collect();
+    let updates: Vec<String> = columns[1..].iter().map(|c| format!("{} = excluded.{}", c, c)).collect();
+    let sqltxt = format!("INSERT INTO boosts ({}) VALUES ({}) ON CONFLICT(idx) DO UPDATE SET {}",
+                         INVOICE_COLUMNS, placeholders.join(", "), updates.join(", "));
+
+    match conn.execute(sqltxt.as_str(),
                        params![boost.index,
-                                       boost.time,
-                                       boost.value_msat,
-                                       boost.value_msat_total,
-                                       boost.action,
-                                       boost.sender,
-                                       boost.app,
-                                       boost.message,
-                                       boost.podcast,
-                                       boost.episode,
-                                       boost.tlv]
+                               boost.time,
+                               boost.value_msat,
+                               boost.value_msat_total,
+                               boost.action,
+                               boost.sender,
+                               boost.app,
+                               boost.message,
+                               boost.podcast,
+                               boost.episode,
+                               boost.tlv,
+                               boost.settle_index,
+                               boost.state,
+                               boost.payment_hash,
+                               boost.feed_id,
+                               boost.item_id,
+                               boost.guid,
+                               boost.episode_guid,
+                               boost.ts,
+                               boost.episode_time,
+                               boost.url,
+                               boost.speed,
+                               boost.uuid,
+                               boost.boost_link,
+                               boost.sender_id,
+                               boost.app_version,
+                               boost.name,
+                               boost.signature,
+                               boost.tlv_hex,
+                               boost.tlv_error,
+                               boost.tlv_conflict,
+                               boost.reply_address,
+                               boost.reply_custom_key,
+                               boost.reply_custom_value]
     ) {
-        Ok(_) => {
-            Ok(true)
+        Ok(_) => Ok(()),
+        Err(e) => {
+            eprintln!("{}", e);
+            Err(Box::new(HydraError(format!("Failed to add boost: [{}].", boost.index))))
         }
+    }
+}
+
+//Insert the htlcs that paid an invoice
+fn insert_htlcs(conn: &Connection, htlcs: &[Htlc]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    //Channel ids use the full u64 range, so they're stored as their i64 bit pattern
+    for htlc in htlcs {
+        if let Err(e) = conn.execute("INSERT INTO htlcs (boost_idx, htlc_index, chan_id, amt_msat, state) \
+                                      VALUES (?1, ?2, ?3, ?4, ?5)",
+                                     params![htlc.boost_index, htlc.htlc_index, htlc.chan_id as i64, htlc.amt_msat, htlc.state]) {
+            eprintln!("{}", e);
+            return Err(Box::new(HydraError(format!("Failed to add htlc: [{}] for boost: [{}].",
+                                                   htlc.htlc_index, htlc.boost_index))));
+        }
+    }
+
+    Ok(())
+}
+
+//Insert the custom records that came with an invoice
+fn insert_custom_records(conn: &Connection, records: &[CustomRecord]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    //Record types use the full u64 range, so they're stored as their i64 bit pattern
+    for record in records {
+        if let Err(e) = conn.execute("INSERT INTO custom_records (boost_idx, htlc_index, record_type, value) \
+                                      VALUES (?1, ?2, ?3, ?4)",
+                                     params![record.boost_index, record.htlc_index, record.record_type as i64, record.value]) {
+            eprintln!("{}", e);
+            return Err(Box::new(HydraError(format!("Failed to add custom record: [{}] for boost: [{}].",
+                                                   record.record_type, record.boost_index))));
+        }
+    }
+
+    Ok(())
+}
+
+//Insert an outgoing payment and return its index
+fn insert_outgoing(conn: &Connection, payment: &OutgoingPayment) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    match conn.execute("INSERT INTO outgoing (boost_idx, time, value_msat, fee_msat, recipient, custom_key, custom_value, \
+                                              sender, message, tlv, payment_hash, state, error, split_id, preimage, \
+                                              payment_index, action, podcast, episode, app, feed_id) \
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21)",
+                       params![payment.boost_index,
+                               payment.time,
+                               payment.value_msat,
+                               payment.fee_msat,
+                               payment.recipient,
+                               payment.custom_key,
+                               payment.custom_value,
+                               payment.sender,
+                               payment.message,
+                               payment.tlv,
+                               payment.payment_hash,
+                               payment.state,
+                               payment.error,
+                               payment.split_id,
+                               payment.preimage,
+                               payment.payment_index,
+                               payment.action,
+                               payment.podcast,
+                               payment.episode,
+                               payment.app,
+                               payment.feed_id]) {
+        Ok(_) => Ok(conn.last_insert_rowid() as u64),
         Err(e) => {
             eprintln!("{}", e);
-            return Err(Box::new(HydraError(format!("Failed to add boost: [{}].", boost.index).into())))
-        }
-    }
-}
-
-
-//Get all of the boosts from the database
-pub fn get_boosts_from_db(filepath: &String, index: u64, max: u64, direction: bool) -> Result<Vec<BoostRecord>, Box<dyn Error>> {
-    let conn = connect_to_database(false, filepath)?;
-    let mut boosts: Vec<BoostRecord> = Vec::new();
-
-    let mut ltgt = ">=";
-    if direction {
-        ltgt = "<=";
-    }
-
-    let sqltxt = format!("SELECT idx, \
-                                       time, \
-                                       value_msat, \
-                                       value_msat_total, \
-                                       action, \
-                                       sender, \
-                                       app, \
-                                       message, \
-                                       podcast, \
-                                       episode, \
-                                       tlv \
-                                 FROM boosts \
-                                 WHERE action = 2 \
-                                   AND idx {} :index \
-                                 ORDER BY idx ASC \
-                                 LIMIT :max", ltgt);
-
-    //Prepare and execute the query
-    let mut stmt = conn.prepare(sqltxt.as_str())?;
-    let rows = stmt.query_map(&[(":index", index.to_string().as_str()), (":max", max.to_string().as_str())], |row| {
-        Ok(BoostRecord {
-            index: row.get(0)?,
-            time: row.get(1)?,
-            value_msat: row.get(2)?,
-            value_msat_total: row.get(3)?,
-            action: row.get(4)?,
-            sender: row.get(5)?,
-            app: row.get(6)?,
-            message: row.get(7)?,
-            podcast: row.get(8)?,
-            episode: row.get(9)?,
-            tlv: row.get(10)?,
-        })
-    }).unwrap();
-
-    //Parse the results
-    for row in rows {
-        let boost: BoostRecord = row.unwrap();
-        boosts.push(boost);
-    }
-
-    Ok(boosts)
-}
-
-
-//Get the last boost index number from the database
-pub fn get_last_boost_index_from_db(filepath: &String) -> Result<u64, Box<dyn Error>> {
-    let conn = connect_to_database(false, filepath)?;
-    let mut boosts: Vec<BoostRecord> = Vec::new();
-    let max = 1;
-
-    //Prepare and execute the query
-    let mut stmt = conn.prepare("SELECT idx, time, value_msat, value_msat_total, action, sender, app, message, podcast, episode, tlv \
-                                 FROM boosts \
-                                 ORDER BY idx DESC LIMIT :max")?;
-    let rows = stmt.query_map(&[(":max", max.to_string().as_str())], |row| {
-        Ok(BoostRecord {
-            index: row.get(0)?,
-            time: row.get(1)?,
-            value_msat: row.get(2)?,
-            value_msat_total: row.get(3)?,
-            action: row.get(4)?,
-            sender: row.get(5)?,
-            app: row.get(6)?,
-            message: row.get(7)?,
-            podcast: row.get(8)?,
-            episode: row.get(9)?,
-            tlv: row.get(10)?,
-        })
-    }).unwrap();
+            Err(Box::new(HydraError(format!("Failed to store outgoing payment to: [{}].", payment.recipient))))
+        }
+    }
+}
+
+//Map a row selected with SPLIT_COLUMNS
+fn split_from_row(row: &rusqlite::Row) -> rusqlite::Result<Split> {
+    Ok(Split {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        recipient: row.get(2)?,
+        custom_key: row.get(3)?,
+        custom_value: row.get(4)?,
+        percent: row.get(5)?,
+        sats: row.get(6)?,
+        podcast: row.get(7)?,
+        feed_id: row.get(8)?,
+        created: row.get(9)?,
+    })
+}
+
+//Map a row of the api_tokens table, without the hash
+fn api_token_from_row(row: &rusqlite::Row) -> rusqlite::Result<ApiToken> {
+    Ok(ApiToken {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        created: row.get(2)?,
+        last_used: row.get(3)?,
+    })
+}
+
+//Map a row selected with BOOST_COLUMNS to a boost record
+fn boost_from_row(row: &rusqlite::Row) -> rusqlite::Result<BoostRecord> {
+    Ok(BoostRecord {
+        index: row.get(0)?,
+        time: row.get(1)?,
+        value_msat: row.get(2)?,
+        value_msat_total: row.get(3)?,
+        action: row.get(4)?,
+        sender: row.get(5)?,
+        app: row.get(6)?,
+        message: row.get(7)?,
+        podcast: row.get(8)?,
+        episode: row.get(9)?,
+        tlv: row.get(10)?,
+        settle_index: row.get(11)?,
+        state: row.get(12)?,
+        payment_hash: row.get(13)?,
+        feed_id: row.get(14)?,
+        item_id: row.get(15)?,
+        guid: row.get(16)?,
+        episode_guid: row.get(17)?,
+        ts: row.get(18)?,
+        episode_time: row.get(19)?,
+        url: row.get(20)?,
+        speed: row.get(21)?,
+        uuid: row.get(22)?,
+        boost_link: row.get(23)?,
+        sender_id: row.get(24)?,
+        app_version: row.get(25)?,
+        name: row.get(26)?,
+        signature: row.get(27)?,
+        tlv_hex: row.get(28)?,
+        tlv_error: row.get(29)?,
+        tlv_conflict: row.get(30)?,
+        reply_address: row.get(31)?,
+        reply_custom_key: row.get(32)?,
+        reply_custom_value: row.get(33)?,
+        read: row.get(34)?,
+        starred: row.get(35)?,
+        archived: row.get(36)?,
+        note: row.get(37)?,
+    })
+}
 
-    //Parse the results
-    for row in rows {
-        let boost: BoostRecord = row.unwrap();
-        boosts.push(boost);
+
+//Tests ------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //A fresh database file for one test
+    fn test_database(name: &str) -> Database {
+        let filepath = std::env::temp_dir().join(format!("helipad-dbif-{}-{}.db", name, std::process::id()));
+        let filepath = filepath.to_str().unwrap();
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", filepath, suffix));
+        }
+        Database::open(filepath).unwrap()
     }
 
-    if boosts.len() > 0 {
-        return Ok(boosts[0].index)
+    fn sent_payment(payment_index: u64, payment_hash: &str, state: u8) -> OutgoingPayment {
+        OutgoingPayment {
+            time: 1650000000,
+            value_msat: 10000,
+            recipient: "02ab".to_string(),
+            tlv: r#"{"action":"stream","podcast":"Podcasting 2.0"}"#.to_string(),
+            payment_hash: payment_hash.to_string(),
+            state,
+            payment_index: Some(payment_index),
+            action: ACTION_STREAM,
+            podcast: "Podcasting 2.0".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn settled_boost(index: u64, action: u8, value_msat: i64) -> BoostRecord {
+        BoostRecord {
+            index,
+            settle_index: index,
+            state: INVOICE_STATE_SETTLED,
+            payment_hash: format!("{:064x}", index),
+            time: 1650000000 + index as i64,
+            value_msat,
+            value_msat_total: value_msat,
+            action,
+            podcast: "Podcasting 2.0".to_string(),
+            ..Default::default()
+        }
     }
 
-    Ok(0)
-}
\ No newline at end of file
+    fn payout(boost_index: u64, split_id: u64, state: u8) -> OutgoingPayment {
+        OutgoingPayment {
+            boost_index: Some(boost_index),
+            time: 1650000000,
+            recipient: "02ab".to_string(),
+            state,
+            split_id: Some(split_id),
+            ..Default::default()
+        }
+    }
+
+    fn indexes(page: &BoostPage) -> Vec<u64> {
+        page.boosts.iter().map(|boost| boost.index).collect()
+    }
+
+    #[test]
+    fn boosts_page_both_ways() {
+        let db = test_database("paging");
+        assert_eq!(db.get_last_boost_index().unwrap(), 0);
+        for index in 1..=7 {
+            db.add_invoice(settled_boost(index, ACTION_BOOST, 1000)).unwrap();
+        }
+        assert_eq!(db.get_last_boost_index().unwrap(), 7);
+        let filter = BoostFilter::default();
+
+        let page = db.get_boosts(&filter, Cursor::Newest, 3).unwrap();
+        assert_eq!((indexes(&page), page.next, page.prev), (vec![7, 6, 5], Some(5), None));
+
+        let page = db.get_boosts(&filter, Cursor::Before(5), 3).unwrap();
+        assert_eq!((indexes(&page), page.next, page.prev), (vec![4, 3, 2], Some(2), Some(4)));
+
+        let page = db.get_boosts(&filter, Cursor::Before(2), 3).unwrap();
+        assert_eq!((indexes(&page), page.next, page.prev), (vec![1], None, Some(1)));
+
+        let page = db.get_boosts(&filter, Cursor::After(1), 3).unwrap();
+        assert_eq!((indexes(&page), page.next, page.prev), (vec![4, 3, 2], Some(2), Some(4)));
+
+        let page = db.get_boosts(&filter, Cursor::After(4), 3).unwrap();
+        assert_eq!((indexes(&page), page.next, page.prev), (vec![7, 6, 5], Some(5), None));
+    }
+
+    #[test]
+    fn boosts_are_filtered() {
+        let db = test_database("filters");
+        db.add_invoice(settled_boost(1, ACTION_BOOST, 1000)).unwrap();
+        db.add_invoice(settled_boost(2, ACTION_STREAM, 50)).unwrap();
+        db.add_invoice(BoostRecord {
+            podcast: "Other Show".to_string(),
+            message: "great episode".to_string(),
+            ..settled_boost(3, ACTION_BOOST, 5000)
+        }).unwrap();
+        db.add_invoice(BoostRecord { state: INVOICE_STATE_OPEN, ..settled_boost(4, ACTION_BOOST, 1000) }).unwrap();
+        db.set_boost_flags(1, &BoostFlags { read: Some(true), ..Default::default() }).unwrap();
+
+        let found = |filter: BoostFilter| indexes(&db.get_boosts(&filter, Cursor::Newest, 10).unwrap());
+        assert_eq!(found(BoostFilter::default()), vec![3, 2, 1]);
+        assert_eq!(found(BoostFilter { action: Some(ACTION_BOOST), ..Default::default() }), vec![3, 1]);
+        assert_eq!(found(BoostFilter { read: Some(false), ..Default::default() }), vec![3, 2]);
+        assert_eq!(found(BoostFilter { podcast: Some("other show".to_string()), ..Default::default() }), vec![3]);
+        assert_eq!(found(BoostFilter { min_msat: Some(1000), max_msat: Some(2000), ..Default::default() }), vec![1]);
+        assert_eq!(found(BoostFilter { since_time: Some(1650000002), ..Default::default() }), vec![3, 2]);
+        assert_eq!(found(BoostFilter { query: Some("great".to_string()), ..Default::default() }), vec![3]);
+
+        //Paging keeps to the filter
+        let page = db.get_boosts(&BoostFilter { action: Some(ACTION_BOOST), ..Default::default() }, Cursor::Newest, 1).unwrap();
+        assert_eq!((indexes(&page), page.next), (vec![3], Some(3)));
+        let page = db.get_boosts(&BoostFilter { action: Some(ACTION_BOOST), ..Default::default() }, Cursor::Before(3), 1).unwrap();
+        assert_eq!((indexes(&page), page.next, page.prev), (vec![1], None, Some(1)));
+    }
+
+    #[test]
+    fn skipped_payouts_are_not_owed() {
+        let db = test_database("skipped-payouts");
+        let split = db.add_split(&Split { recipient: "02ab".to_string(), percent: Some(1.0), ..Default::default() }).unwrap();
+        for index in 1..=3 {
+            db.add_invoice(settled_boost(index, ACTION_STREAM, 10_000)).unwrap();
+        }
+
+        //Until they're recorded, shares too small to send crowd out the rest of the batch
+        assert_eq!(db.get_boosts_owing_payouts(5, 2).unwrap(), vec![1, 2]);
+
+        db.add_outgoing(&payout(1, split.id, OUTGOING_STATE_SKIPPED)).unwrap();
+        db.add_outgoing(&payout(2, split.id, OUTGOING_STATE_SKIPPED)).unwrap();
+        db.add_outgoing(&payout(3, split.id, OUTGOING_STATE_FAILED)).unwrap();
+        assert_eq!(db.get_boosts_owing_payouts(5, 2).unwrap(), vec![3]);
+
+        //Nor once they've gone through, or have been tried too many times
+        db.add_outgoing(&payout(3, split.id, OUTGOING_STATE_FAILED)).unwrap();
+        assert!(db.get_boosts_owing_payouts(2, 2).unwrap().is_empty());
+        db.add_outgoing(&payout(3, split.id, OUTGOING_STATE_SUCCEEDED)).unwrap();
+        assert!(db.get_boosts_owing_payouts(5, 2).unwrap().is_empty());
+    }
+
+    #[test]
+    fn payment_sweep_resumes_past_payments_that_were_not_kept() {
+        let db = test_database("payment-sweep");
+        assert_eq!(db.get_payment_sweep_start_index().unwrap(), 0);
+
+        //Nothing stored, but the sweep read up to 500
+        db.set_payment_sweep_index(500).unwrap();
+        assert_eq!(db.get_payment_sweep_start_index().unwrap(), 500);
+        db.set_payment_sweep_index(200).unwrap();
+        assert_eq!(db.get_payment_sweep_start_index().unwrap(), 500);
+
+        //A payment still in flight is looked at again
+        db.add_sent_payment(&sent_payment(600, "aa", OUTGOING_STATE_IN_FLIGHT)).unwrap();
+        assert_eq!(db.get_payment_sweep_start_index().unwrap(), 599);
+
+        db.add_sent_payment(&sent_payment(600, "aa", OUTGOING_STATE_SUCCEEDED)).unwrap();
+        assert_eq!(db.get_payment_sweep_start_index().unwrap(), 600);
+    }
+
+    #[test]
+    fn storing_an_invoice_again_replaces_its_htlcs_and_records() {
+        let db = test_database("store-invoice");
+        let htlc = |htlc_index| Htlc { boost_index: 7, htlc_index, chan_id: u64::MAX, amt_msat: 500, state: 1 };
+        let record = |htlc_index, record_type| CustomRecord { boost_index: 7, htlc_index, record_type, value: vec![1, 2] };
+
+        db.store_invoice(&settled_boost(7, ACTION_BOOST, 1000),
+                         &[htlc(0), htlc(1)],
+                         &[record(0, 7629169), record(1, 34349334)]).unwrap();
+        assert_eq!(db.get_htlcs(7).unwrap().len(), 2);
+        assert_eq!(db.get_htlcs(7).unwrap()[0].chan_id, u64::MAX);
+
+        //LND now only reports the first htlc
+        db.store_invoice(&settled_boost(7, ACTION_BOOST, 500), &[htlc(0)], &[record(0, 7629169)]).unwrap();
+        let htlcs = db.get_htlcs(7).unwrap();
+        let records = db.get_custom_records(7).unwrap();
+        assert_eq!(htlcs.len(), 1);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].record_type, 7629169);
+        assert_eq!(db.get_boost(7).unwrap().unwrap().value_msat, 500);
+    }
+}
@@ -0,0 +1,380 @@
+use crate::{HydraError, ACTION_BOOST, ACTION_KEYSEND, ACTION_STREAM, ACTION_UNKNOWN, INVOICE_STATE_SETTLED};
+use rusqlite::{params, Connection, Transaction};
+use std::error::Error;
+
+
+//A schema change: what it does, for the log, and the function that makes it
+type Migration = (&'static str, fn(&Transaction) -> rusqlite::Result<()>);
+
+//Schema migrations, in order.  The database's user_version pragma records how many of these have
+//been applied.  Never change a migration once it has been released - add a new one instead.
+const MIGRATIONS: &[Migration] = &[
+    ("create the boosts table", create_boosts_table),
+    ("track invoice settlement", add_invoice_state),
+    ("store podcast tlv fields", add_podcast_fields),
+    ("record tlv decoding errors", add_tlv_errors),
+    ("store custom records", create_custom_records_table),
+    ("store htlcs", create_htlcs_table),
+    ("add host flags and notes", add_boost_flags),
+    ("full text search", create_boosts_fts),
+    ("store login sessions and api tokens", create_auth_tables),
+    ("store reply addresses and outgoing payments", create_outgoing_table),
+    ("value splits", create_splits_table),
+    ("track payments sent from the node", add_sent_payment_fields),
+    ("remember where sweeps left off", create_sync_state_table),
+];
+
+
+//Bring the schema up to date, applying each outstanding migration in its own transaction.  A
+//database from a newer version of Helipad is refused rather than risk damaging it.
+pub fn migrate(conn: &mut Connection, filepath: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    let latest = MIGRATIONS.len();
+
+    if version > latest {
+        return Err(Box::new(HydraError(format!("Database: [{}] has schema version: [{}] but this version of Helipad only \
+                                                 supports up to: [{}].  Please upgrade Helipad.", filepath, version, latest))))
+    }
+
+    for (index, (description, migration)) in MIGRATIONS.iter().enumerate().skip(version) {
+        let new_version = index + 1;
+        println!("Migrating database to schema version: [{}] ({})...", new_version, description);
+
+        let tx = conn.transaction()?;
+        let result = migration(&tx)
+            .and_then(|_| tx.execute_batch(format!("PRAGMA user_version = {}", new_version).as_str()));
+        match result {
+            Ok(_) => {
+                tx.commit()?;
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                return Err(Box::new(HydraError(format!("Failed to migrate database: [{}] to schema version: [{}]. \
+                                                         No changes were made by this step.", filepath, new_version))))
+            }
+        }
+    }
+
+    Ok(())
+}
+
+
+//Add a column to the boosts table unless it's already there.  Databases created before migrations
+//were versioned may already have some of the newer columns.  Returns true if it was added.
+fn add_column_if_missing(tx: &Transaction, column: &str, definition: &str) -> rusqlite::Result<bool> {
+    let count: i64 = tx.query_row("SELECT COUNT(*) FROM pragma_table_info('boosts') WHERE name = ?1",
+                                  params![column],
+                                  |row| row.get(0))?;
+    if count > 0 {
+        return Ok(false)
+    }
+
+    tx.execute(format!("ALTER TABLE boosts ADD COLUMN {} {}", column, definition).as_str(), [])?;
+    Ok(true)
+}
+
+
+//Migrations -------------------------------------------------------------------------------------------------
+fn create_boosts_table(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS boosts (
+             idx integer primary key,
+             time integer,
+             value_msat integer,
+             value_msat_total integer,
+             action integer,
+             sender text,
+             app text,
+             message text,
+             podcast text,
+             episode text,
+             tlv text
+         )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+//Rows from before we tracked state were stored whether they were paid or not, so work out what
+//we can from the settle date.
+fn add_invoice_state(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column_if_missing(tx, "settle_index", "integer NOT NULL DEFAULT 0")?;
+    add_column_if_missing(tx, "payment_hash", "text NOT NULL DEFAULT ''")?;
+    if add_column_if_missing(tx, "state", "integer NOT NULL DEFAULT 0")? {
+        tx.execute("UPDATE boosts SET state = ?1 WHERE time > 0", params![INVOICE_STATE_SETTLED])?;
+    }
+    tx.execute("CREATE INDEX IF NOT EXISTS boosts_settle_index ON boosts (settle_index)", [])?;
+
+    Ok(())
+}
+
+fn add_podcast_fields(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column_if_missing(tx, "feed_id", "integer")?;
+    add_column_if_missing(tx, "item_id", "integer")?;
+    add_column_if_missing(tx, "ts", "integer")?;
+    for column in ["guid", "episode_guid", "episode_time", "url", "speed", "uuid", "boost_link",
+                   "sender_id", "app_version", "name", "signature"] {
+        add_column_if_missing(tx, column, "text NOT NULL DEFAULT ''")?;
+    }
+    tx.execute("CREATE INDEX IF NOT EXISTS boosts_feed_id ON boosts (feed_id)", [])?;
+    tx.execute("CREATE INDEX IF NOT EXISTS boosts_episode_guid ON boosts (episode_guid)", [])?;
+    tx.execute("CREATE INDEX IF NOT EXISTS boosts_ts ON boosts (ts)", [])?;
+
+    Ok(())
+}
+
+fn add_tlv_errors(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column_if_missing(tx, "tlv_hex", "integer NOT NULL DEFAULT 0")?;
+    add_column_if_missing(tx, "tlv_error", "text NOT NULL DEFAULT ''")?;
+
+    Ok(())
+}
+
+fn create_custom_records_table(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS custom_records (
+             boost_idx integer NOT NULL REFERENCES boosts (idx),
+             htlc_index integer NOT NULL,
+             record_type integer NOT NULL,
+             value blob NOT NULL,
+             PRIMARY KEY (boost_idx, htlc_index, record_type)
+         )",
+        [],
+    )?;
+    tx.execute("CREATE INDEX IF NOT EXISTS custom_records_record_type ON custom_records (record_type)", [])?;
+
+    Ok(())
+}
+
+fn create_htlcs_table(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS htlcs (
+             boost_idx integer NOT NULL REFERENCES boosts (idx),
+             htlc_index integer NOT NULL,
+             chan_id integer NOT NULL,
+             amt_msat integer NOT NULL,
+             state integer NOT NULL,
+             PRIMARY KEY (boost_idx, htlc_index)
+         )",
+        [],
+    )?;
+    add_column_if_missing(tx, "tlv_conflict", "integer NOT NULL DEFAULT 0")?;
+
+    Ok(())
+}
+
+fn add_boost_flags(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column_if_missing(tx, "read", "integer NOT NULL DEFAULT 0")?;
+    add_column_if_missing(tx, "starred", "integer NOT NULL DEFAULT 0")?;
+    add_column_if_missing(tx, "archived", "integer NOT NULL DEFAULT 0")?;
+    add_column_if_missing(tx, "note", "text NOT NULL DEFAULT ''")?;
+    tx.execute("CREATE INDEX IF NOT EXISTS boosts_read ON boosts (read)", [])?;
+
+    Ok(())
+}
+
+//An external content FTS5 index over the text people search boosts by.  Triggers keep it in step
+//with the boosts table, and it's built from what's already there.
+fn create_boosts_fts(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS boosts_fts USING fts5(
+             message, sender, app, podcast, episode,
+             content = 'boosts', content_rowid = 'idx'
+         );
+         CREATE TRIGGER IF NOT EXISTS boosts_fts_insert AFTER INSERT ON boosts BEGIN
+             INSERT INTO boosts_fts (rowid, message, sender, app, podcast, episode)
+             VALUES (new.idx, new.message, new.sender, new.app, new.podcast, new.episode);
+         END;
+         CREATE TRIGGER IF NOT EXISTS boosts_fts_delete AFTER DELETE ON boosts BEGIN
+             INSERT INTO boosts_fts (boosts_fts, rowid, message, sender, app, podcast, episode)
+             VALUES ('delete', old.idx, old.message, old.sender, old.app, old.podcast, old.episode);
+         END;
+         CREATE TRIGGER IF NOT EXISTS boosts_fts_update AFTER UPDATE OF message, sender, app, podcast, episode ON boosts BEGIN
+             INSERT INTO boosts_fts (boosts_fts, rowid, message, sender, app, podcast, episode)
+             VALUES ('delete', old.idx, old.message, old.sender, old.app, old.podcast, old.episode);
+             INSERT INTO boosts_fts (rowid, message, sender, app, podcast, episode)
+             VALUES (new.idx, new.message, new.sender, new.app, new.podcast, new.episode);
+         END;
+         INSERT INTO boosts_fts (boosts_fts) VALUES ('rebuild');",
+    )
+}
+
+//Logins and the tokens handed out to read-only clients.  Only hashes of the secrets are kept, so the
+//database alone can't be used to get in.
+fn create_auth_tables(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS sessions (
+             token_hash text PRIMARY KEY,
+             created integer NOT NULL,
+             expires integer NOT NULL
+         );
+         CREATE TABLE IF NOT EXISTS api_tokens (
+             id integer PRIMARY KEY AUTOINCREMENT,
+             name text NOT NULL,
+             token_hash text NOT NULL UNIQUE,
+             created integer NOT NULL,
+             last_used integer
+         );",
+    )
+}
+
+//Where a boost's sender takes replies, and the payments we send out ourselves
+fn create_outgoing_table(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column_if_missing(tx, "reply_address", "text NOT NULL DEFAULT ''")?;
+    add_column_if_missing(tx, "reply_custom_key", "integer")?;
+    add_column_if_missing(tx, "reply_custom_value", "text NOT NULL DEFAULT ''")?;
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS outgoing (
+             idx integer PRIMARY KEY AUTOINCREMENT,
+             boost_idx integer REFERENCES boosts (idx),
+             time integer NOT NULL,
+             value_msat integer NOT NULL,
+             fee_msat integer NOT NULL DEFAULT 0,
+             recipient text NOT NULL,
+             custom_key integer,
+             custom_value text NOT NULL DEFAULT '',
+             sender text NOT NULL DEFAULT '',
+             message text NOT NULL DEFAULT '',
+             tlv text NOT NULL DEFAULT '',
+             payment_hash text NOT NULL,
+             state integer NOT NULL,
+             error text NOT NULL DEFAULT ''
+         );
+         CREATE INDEX IF NOT EXISTS outgoing_boost_idx ON outgoing (boost_idx);",
+    )
+}
+
+//Who gets a share of what comes in, and which outgoing payments paid them.  Every attempt at paying
+//a split is its own outgoing row, and they all share one preimage so LND never pays a split twice.
+fn create_splits_table(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS splits (
+             id integer PRIMARY KEY AUTOINCREMENT,
+             name text NOT NULL DEFAULT '',
+             recipient text NOT NULL,
+             custom_key integer,
+             custom_value text NOT NULL DEFAULT '',
+             percent real,
+             sats integer,
+             podcast text NOT NULL DEFAULT '',
+             feed_id integer,
+             created integer NOT NULL
+         );
+         ALTER TABLE outgoing ADD COLUMN split_id integer;
+         ALTER TABLE outgoing ADD COLUMN preimage text NOT NULL DEFAULT '';
+         CREATE INDEX IF NOT EXISTS outgoing_split ON outgoing (boost_idx, split_id);",
+    )
+}
+
+//Payments sent by other apps on the node are picked up from LND's payment list, so outgoing payments
+//keep LND's payment index and what their podcast record says.  The ones we sent ourselves are filled
+//in from the record we stored with them.
+fn add_sent_payment_fields(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(format!(
+        "ALTER TABLE outgoing ADD COLUMN payment_index integer;
+         ALTER TABLE outgoing ADD COLUMN action integer NOT NULL DEFAULT {keysend};
+         ALTER TABLE outgoing ADD COLUMN podcast text NOT NULL DEFAULT '';
+         ALTER TABLE outgoing ADD COLUMN episode text NOT NULL DEFAULT '';
+         ALTER TABLE outgoing ADD COLUMN app text NOT NULL DEFAULT '';
+         ALTER TABLE outgoing ADD COLUMN feed_id integer;
+         UPDATE outgoing
+            SET action = CASE lower(json_extract(tlv, '$.action')) WHEN 'stream' THEN {stream} WHEN 'boost' THEN {boost} ELSE {unknown} END,
+                podcast = coalesce(json_extract(tlv, '$.podcast'), ''),
+                episode = coalesce(json_extract(tlv, '$.episode'), ''),
+                app = coalesce(json_extract(tlv, '$.app_name'), ''),
+                feed_id = json_extract(tlv, '$.feedID')
+          WHERE json_valid(tlv);
+         CREATE UNIQUE INDEX IF NOT EXISTS outgoing_payment_index ON outgoing (payment_index);
+         CREATE INDEX IF NOT EXISTS outgoing_payment_hash ON outgoing (payment_hash);",
+        keysend = ACTION_KEYSEND, stream = ACTION_STREAM, boost = ACTION_BOOST, unknown = ACTION_UNKNOWN,
+    ).as_str())
+}
+
+//Positions in LND's lists that aren't tied to a stored row, like how far the payment sweep has read.
+//Most payments aren't podcast payments and aren't stored, so the outgoing table can't tell us that.
+fn create_sync_state_table(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS sync_state (
+             name text PRIMARY KEY,
+             value integer NOT NULL
+         );",
+    )
+}
+
+
+//Tests ------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BoostFilter, Cursor, Database, INVOICE_STATE_OPEN};
+
+    //A database file in the temp dir that isn't there yet
+    fn test_filepath(name: &str) -> String {
+        let filepath = std::env::temp_dir().join(format!("helipad-migrations-{}-{}.db", name, std::process::id()));
+        let filepath = filepath.to_str().unwrap().to_string();
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", filepath, suffix));
+        }
+        filepath
+    }
+
+    #[test]
+    fn boosts_from_before_migrations_survive_them() {
+        let filepath = test_filepath("baseline");
+
+        //The boosts table as the first releases made it, before user_version was used
+        {
+            let conn = Connection::open(&filepath).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE boosts (
+                     idx integer primary key,
+                     time integer,
+                     value_msat integer,
+                     value_msat_total integer,
+                     action integer,
+                     sender text,
+                     app text,
+                     message text,
+                     podcast text,
+                     episode text,
+                     tlv text
+                 );
+                 INSERT INTO boosts VALUES (1, 1650000000, 5000, 10000, 2, 'bob', 'Fountain', 'great show', 'Podcasting 2.0', 'Episode 80', '{}');
+                 INSERT INTO boosts VALUES (2, 1650000100, 1000, 1000, 1, 'alice', 'Breez', '', 'Podcasting 2.0', 'Episode 81', '{}');
+                 INSERT INTO boosts VALUES (3, 0, 2000, 2000, 2, 'carol', 'Fountain', 'never paid', 'Podcasting 2.0', 'Episode 81', '{}');",
+            ).unwrap();
+        }
+
+        let db = Database::open(&filepath).unwrap();
+        let version: usize = db.connection().unwrap().query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+
+        //Only rows with a settle time were paid, and nothing had a settle index yet
+        let boost = db.get_boost(1).unwrap().unwrap();
+        assert_eq!((boost.state, boost.action, boost.settle_index), (INVOICE_STATE_SETTLED, ACTION_BOOST, 0));
+        assert_eq!((boost.sender.as_str(), boost.message.as_str(), boost.value_msat), ("bob", "great show", 5000));
+        let boost = db.get_boost(2).unwrap().unwrap();
+        assert_eq!((boost.state, boost.action), (INVOICE_STATE_SETTLED, ACTION_STREAM));
+        assert_eq!(db.get_boost(3).unwrap().unwrap().state, INVOICE_STATE_OPEN);
+
+        //The search index is built from the rows that were already there
+        let found = |query: &str| {
+            let filter = BoostFilter { query: Some(query.to_string()), ..Default::default() };
+            db.get_boosts(&filter, Cursor::Newest, 10).unwrap().boosts.iter().map(|b| b.index).collect::<Vec<u64>>()
+        };
+        assert_eq!(found("great"), vec![1]);
+        assert_eq!(found("alice"), vec![2]);
+    }
+
+    #[test]
+    fn databases_from_a_newer_helipad_are_refused() {
+        let filepath = test_filepath("newer");
+        {
+            let conn = Connection::open(&filepath).unwrap();
+            conn.execute_batch(format!("PRAGMA user_version = {}", MIGRATIONS.len() + 1).as_str()).unwrap();
+        }
+
+        assert!(Database::open(&filepath).is_err());
+    }
+}
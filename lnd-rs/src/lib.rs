@@ -1,42 +1,69 @@
 /// Module including all tonic-build generated code.
 /// Each sub-module represents one proto service.
 pub mod lnrpc;
+mod rest;
 
 use hyper::client::HttpConnector;
 use hyper_openssl::HttpsConnector;
 use lnrpc::lnrpc::{
     lightning_client::LightningClient, AddInvoiceResponse, ChannelBalanceRequest,
-    ChannelBalanceResponse, Invoice, ListPaymentsRequest, ListPaymentsResponse, PayReq,
+    ChannelBalanceResponse, GetInfoRequest, GetInfoResponse, Invoice, ListPaymentsRequest, ListPaymentsResponse, PayReq,
     PayReqString, PaymentHash, SendRequest, SendResponse, WalletBalanceRequest,
-    WalletBalanceResponse, ListInvoiceRequest, ListInvoiceResponse,
+    WalletBalanceResponse, ListInvoiceRequest, ListInvoiceResponse, InvoiceSubscription,
 };
 use openssl::{
     error::ErrorStack,
     ssl::{SslConnector, SslMethod},
     x509::X509,
 };
+use rest::{InvoiceLines, RestClient};
 use std::convert::TryInto;
 use tonic::{
     codegen::{InterceptedService, StdError},
     metadata::{errors::InvalidMetadataValue, Ascii, MetadataValue},
     service::Interceptor,
     transport::{Channel, Endpoint},
-    Response, Status,
+    Response,
 };
 
+pub use tonic::{Code, Status, Streaming};
+
 #[derive(Debug, Clone)]
 pub struct Lnd {
-    lightning_client: LightningClient<InterceptedService<Channel, LndInterceptor>>,
+    transport: Transport,
+}
+
+/// How calls reach the node: gRPC, or the REST gateway for nodes whose gRPC port can't be reached.
+#[derive(Debug, Clone)]
+enum Transport {
+    Grpc(LightningClient<InterceptedService<Channel, LndInterceptor>>),
+    Rest(RestClient),
+}
+
+/// Invoice updates from `subscribe_invoices`, whichever transport they come over.
+#[derive(Debug)]
+pub struct InvoiceStream {
+    inner: InvoiceStreamInner,
+}
+
+#[derive(Debug)]
+enum InvoiceStreamInner {
+    Grpc(Streaming<Invoice>),
+    Rest(InvoiceLines),
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum LndConnectError {
     #[error("Connector creation failed: #{0}")]
     Connector(ErrorStack),
+    #[error("Invalid certificate: #{0}")]
+    Certificate(ErrorStack),
     #[error("Interceptor creation failed: #{0}")]
     Interceptor(InvalidMetadataValue),
     #[error("Transport connection failed: #{0}")]
     Transport(tonic::transport::Error),
+    #[error("Invalid REST url: #{0}")]
+    Url(String),
 }
 
 impl Lnd {
@@ -48,8 +75,7 @@ impl Lnd {
         D: TryInto<Endpoint>,
         D::Error: Into<StdError>,
     {
-        let https_connector =
-            Lnd::connector(certificate_bytes).map_err(LndConnectError::Connector)?;
+        let https_connector = Lnd::connector(certificate_bytes)?;
 
         let transport = tonic::transport::Endpoint::new(destination)
             .map_err(LndConnectError::Transport)?
@@ -59,7 +85,7 @@ impl Lnd {
 
         let lightning_client = LightningClient::with_interceptor(transport, LndInterceptor::noop());
 
-        Ok(Lnd { lightning_client })
+        Ok(Lnd { transport: Transport::Grpc(lightning_client) })
     }
 
     pub async fn connect_with_macaroon<D>(
@@ -71,8 +97,7 @@ impl Lnd {
         D: TryInto<Endpoint>,
         D::Error: Into<StdError>,
     {
-        let https_connector =
-            Lnd::connector(certificate_bytes).map_err(LndConnectError::Connector)?;
+        let https_connector = Lnd::connector(certificate_bytes)?;
 
         let interceptor =
             LndInterceptor::macaroon(macaroon_bytes).map_err(LndConnectError::Interceptor)?;
@@ -85,20 +110,50 @@ impl Lnd {
 
         let lightning_client = LightningClient::with_interceptor(transport, interceptor);
 
-        Ok(Lnd { lightning_client })
+        Ok(Lnd { transport: Transport::Grpc(lightning_client) })
+    }
+
+    /// Talks to LND's REST gateway at `base_url` (ex. https://localhost:8080) instead of gRPC.
+    ///
+    /// Nothing is sent until the first call, so a wrong address or macaroon shows up there.
+    pub fn connect_rest(
+        base_url: &str,
+        certificate_bytes: &[u8],
+        macaroon_bytes: &[u8],
+    ) -> Result<Self, LndConnectError> {
+        match base_url.parse::<hyper::Uri>() {
+            Ok(uri) if uri.scheme_str() == Some("https") && uri.host().is_some() => {}
+            _ => return Err(LndConnectError::Url(base_url.to_string())),
+        }
+
+        let https_connector = Lnd::connector_with_alpn(certificate_bytes, b"\x08http/1.1")?;
+        let macaroon = Some(hex::encode(macaroon_bytes)).filter(|m| !m.is_empty());
+
+        Ok(Lnd {
+            transport: Transport::Rest(RestClient::new(base_url, https_connector, macaroon)),
+        })
+    }
+
+    fn connector(certificate_bytes: &[u8]) -> Result<HttpsConnector<HttpConnector>, LndConnectError> {
+        Lnd::connector_with_alpn(certificate_bytes, b"\x02h2")
     }
 
-    fn connector(certificate_bytes: &[u8]) -> Result<HttpsConnector<HttpConnector>, ErrorStack> {
-        let mut connector = SslConnector::builder(SslMethod::tls())?;
-        let ca = X509::from_pem(&certificate_bytes).unwrap();
+    fn connector_with_alpn(
+        certificate_bytes: &[u8],
+        alpn_protos: &[u8],
+    ) -> Result<HttpsConnector<HttpConnector>, LndConnectError> {
+        let mut connector = SslConnector::builder(SslMethod::tls()).map_err(LndConnectError::Connector)?;
+        //The certificate is read from disk on every attempt, so it may be empty or half written while
+        //LND replaces it.  That's an error to retry, not a reason to panic.
+        let ca = X509::from_pem(certificate_bytes).map_err(LndConnectError::Certificate)?;
 
-        connector.cert_store_mut().add_cert(ca)?;
-        connector.set_alpn_protos(b"\x02h2")?;
+        connector.cert_store_mut().add_cert(ca).map_err(LndConnectError::Connector)?;
+        connector.set_alpn_protos(alpn_protos).map_err(LndConnectError::Connector)?;
 
         let mut http = HttpConnector::new();
         http.enforce_http(false);
 
-        HttpsConnector::with_connector(http, connector)
+        HttpsConnector::with_connector(http, connector).map_err(LndConnectError::Connector)
     }
 }
 
@@ -131,26 +186,54 @@ impl Interceptor for LndInterceptor {
     }
 }
 
+impl InvoiceStream {
+    /// The next invoice update, or None once LND closes the subscription.
+    pub async fn message(&mut self) -> Result<Option<Invoice>, Status> {
+        match &mut self.inner {
+            InvoiceStreamInner::Grpc(stream) => stream.message().await,
+            InvoiceStreamInner::Rest(lines) => lines.message().await,
+        }
+    }
+}
+
 impl Lnd {
+    /// Not available over REST.
     pub async fn add_invoice(&mut self, invoice: Invoice) -> Result<AddInvoiceResponse, Status> {
-        self.lightning_client
-            .add_invoice(invoice)
-            .await
-            .map(Response::into_inner)
+        match &mut self.transport {
+            Transport::Grpc(client) => client.add_invoice(invoice).await.map(Response::into_inner),
+            Transport::Rest(_) => Err(Status::unimplemented("add_invoice isn't supported over REST")),
+        }
     }
 
     pub async fn channel_balance(&mut self) -> Result<ChannelBalanceResponse, Status> {
-        self.lightning_client
-            .channel_balance(ChannelBalanceRequest {})
-            .await
-            .map(Response::into_inner)
+        match &mut self.transport {
+            Transport::Grpc(client) => client
+                .channel_balance(ChannelBalanceRequest {})
+                .await
+                .map(Response::into_inner),
+            Transport::Rest(client) => client.channel_balance().await,
+        }
     }
 
+    /// Not available over REST.
     pub async fn decode_pay_req(&mut self, pay_req: String) -> Result<PayReq, Status> {
-        self.lightning_client
-            .decode_pay_req(PayReqString { pay_req })
-            .await
-            .map(Response::into_inner)
+        match &mut self.transport {
+            Transport::Grpc(client) => client
+                .decode_pay_req(PayReqString { pay_req })
+                .await
+                .map(Response::into_inner),
+            Transport::Rest(_) => Err(Status::unimplemented("decode_pay_req isn't supported over REST")),
+        }
+    }
+
+    pub async fn get_info(&mut self) -> Result<GetInfoResponse, Status> {
+        match &mut self.transport {
+            Transport::Grpc(client) => client
+                .get_info(GetInfoRequest {})
+                .await
+                .map(Response::into_inner),
+            Transport::Rest(client) => client.get_info().await,
+        }
     }
 
     pub async fn list_payments(
@@ -160,15 +243,20 @@ impl Lnd {
         max_payments: u64,
         reversed: bool,
     ) -> Result<ListPaymentsResponse, Status> {
-        self.lightning_client
-            .list_payments(ListPaymentsRequest {
-                include_incomplete,
-                index_offset,
-                max_payments,
-                reversed,
-            })
-            .await
-            .map(Response::into_inner)
+        match &mut self.transport {
+            Transport::Grpc(client) => client
+                .list_payments(ListPaymentsRequest {
+                    include_incomplete,
+                    index_offset,
+                    max_payments,
+                    reversed,
+                })
+                .await
+                .map(Response::into_inner),
+            Transport::Rest(client) => {
+                client.list_payments(include_incomplete, index_offset, max_payments, reversed).await
+            }
+        }
     }
 
     pub async fn list_invoices(
@@ -178,43 +266,84 @@ impl Lnd {
         num_max_invoices: u64,
         reversed: bool,
     ) -> Result<ListInvoiceResponse, Status> {
-        self.lightning_client
-            .list_invoices(ListInvoiceRequest {
-                pending_only,
-                index_offset,
-                num_max_invoices,
-                reversed,
-            })
-            .await
-            .map(Response::into_inner)
+        match &mut self.transport {
+            Transport::Grpc(client) => client
+                .list_invoices(ListInvoiceRequest {
+                    pending_only,
+                    index_offset,
+                    num_max_invoices,
+                    reversed,
+                })
+                .await
+                .map(Response::into_inner),
+            Transport::Rest(client) => {
+                client.list_invoices(pending_only, index_offset, num_max_invoices, reversed).await
+            }
+        }
     }
 
-    pub async fn lookup_invoice(&mut self, r_hash: Vec<u8>) -> Result<Invoice, Status> {
-        #[allow(deprecated)]
-        let payment_hash = PaymentHash {
-            r_hash_str: String::from(""),
-            r_hash,
+    /// Streams invoice updates as they happen.
+    ///
+    /// A non-zero `add_index` or `settle_index` first replays every invoice added or settled after
+    /// that index, so a caller can resume from the last invoice it has seen.
+    pub async fn subscribe_invoices(
+        &mut self,
+        add_index: u64,
+        settle_index: u64,
+    ) -> Result<InvoiceStream, Status> {
+        let inner = match &mut self.transport {
+            Transport::Grpc(client) => client
+                .subscribe_invoices(InvoiceSubscription {
+                    add_index,
+                    settle_index,
+                })
+                .await
+                .map(|response| InvoiceStreamInner::Grpc(response.into_inner()))?,
+            Transport::Rest(client) => {
+                InvoiceStreamInner::Rest(client.subscribe_invoices(add_index, settle_index).await?)
+            }
         };
-        self.lightning_client
-            .lookup_invoice(payment_hash)
-            .await
-            .map(Response::into_inner)
+
+        Ok(InvoiceStream { inner })
+    }
+
+    pub async fn lookup_invoice(&mut self, r_hash: Vec<u8>) -> Result<Invoice, Status> {
+        match &mut self.transport {
+            Transport::Grpc(client) => {
+                #[allow(deprecated)]
+                let payment_hash = PaymentHash {
+                    r_hash_str: String::from(""),
+                    r_hash,
+                };
+                client
+                    .lookup_invoice(payment_hash)
+                    .await
+                    .map(Response::into_inner)
+            }
+            Transport::Rest(client) => client.lookup_invoice(&r_hash).await,
+        }
     }
 
     pub async fn send_payment_sync(
         &mut self,
         send_request: SendRequest,
     ) -> Result<SendResponse, Status> {
-        self.lightning_client
-            .send_payment_sync(send_request)
-            .await
-            .map(Response::into_inner)
+        match &mut self.transport {
+            Transport::Grpc(client) => client
+                .send_payment_sync(send_request)
+                .await
+                .map(Response::into_inner),
+            Transport::Rest(client) => client.send_payment_sync(send_request).await,
+        }
     }
 
     pub async fn wallet_balance(&mut self) -> Result<WalletBalanceResponse, Status> {
-        self.lightning_client
-            .wallet_balance(WalletBalanceRequest {})
-            .await
-            .map(Response::into_inner)
+        match &mut self.transport {
+            Transport::Grpc(client) => client
+                .wallet_balance(WalletBalanceRequest {})
+                .await
+                .map(Response::into_inner),
+            Transport::Rest(client) => client.wallet_balance().await,
+        }
     }
 }
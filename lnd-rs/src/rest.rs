@@ -0,0 +1,619 @@
+//! The REST transport, for nodes that can only be reached through LND's REST port (ex. behind a
+//! reverse proxy that doesn't pass HTTP/2).
+//!
+//! LND's REST gateway speaks the same messages as gRPC in JSON: 64 bit numbers come as strings,
+//! bytes as base64 and enums by name.  Responses are read into the same generated types, so callers
+//! can't tell the transports apart.  Only the fields that carry information are filled in.
+
+use crate::lnrpc::lnrpc::{
+    invoice::InvoiceState, fee_limit::Limit, htlc_attempt::HtlcStatus, payment::PaymentStatus, Amount,
+    ChannelBalanceResponse, GetInfoResponse, Hop, HtlcAttempt, Invoice, InvoiceHtlc, InvoiceHtlcState,
+    ListInvoiceResponse, ListPaymentsResponse, Payment, PaymentFailureReason, Route, SendRequest, SendResponse,
+    WalletBalanceResponse,
+};
+use hyper::body::HttpBody;
+use hyper::client::HttpConnector;
+use hyper::{header, Body, Client, Method, Request, StatusCode};
+use hyper_openssl::HttpsConnector;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use tonic::{Code, Status};
+
+const MACAROON_HEADER: &str = "Grpc-Metadata-macaroon";
+
+const INVOICE_STATES: [(&str, InvoiceState); 4] = [
+    ("OPEN", InvoiceState::Open),
+    ("SETTLED", InvoiceState::Settled),
+    ("CANCELED", InvoiceState::Canceled),
+    ("ACCEPTED", InvoiceState::Accepted),
+];
+const INVOICE_HTLC_STATES: [(&str, InvoiceHtlcState); 3] = [
+    ("ACCEPTED", InvoiceHtlcState::Accepted),
+    ("SETTLED", InvoiceHtlcState::Settled),
+    ("CANCELED", InvoiceHtlcState::Canceled),
+];
+const PAYMENT_STATUSES: [(&str, PaymentStatus); 4] = [
+    ("UNKNOWN", PaymentStatus::Unknown),
+    ("IN_FLIGHT", PaymentStatus::InFlight),
+    ("SUCCEEDED", PaymentStatus::Succeeded),
+    ("FAILED", PaymentStatus::Failed),
+];
+const HTLC_STATUSES: [(&str, HtlcStatus); 3] = [
+    ("IN_FLIGHT", HtlcStatus::InFlight),
+    ("SUCCEEDED", HtlcStatus::Succeeded),
+    ("FAILED", HtlcStatus::Failed),
+];
+const FAILURE_REASONS: [(&str, PaymentFailureReason); 6] = [
+    ("FAILURE_REASON_NONE", PaymentFailureReason::FailureReasonNone),
+    ("FAILURE_REASON_TIMEOUT", PaymentFailureReason::FailureReasonTimeout),
+    ("FAILURE_REASON_NO_ROUTE", PaymentFailureReason::FailureReasonNoRoute),
+    ("FAILURE_REASON_ERROR", PaymentFailureReason::FailureReasonError),
+    ("FAILURE_REASON_INCORRECT_PAYMENT_DETAILS", PaymentFailureReason::FailureReasonIncorrectPaymentDetails),
+    ("FAILURE_REASON_INSUFFICIENT_BALANCE", PaymentFailureReason::FailureReasonInsufficientBalance),
+];
+
+#[derive(Debug, Clone)]
+pub(crate) struct RestClient {
+    base_url: String,
+    macaroon: Option<String>,
+    client: Client<HttpsConnector<HttpConnector>>,
+}
+
+/// The invoice updates from a subscription, which the gateway sends as one JSON object per line.
+#[derive(Debug)]
+pub(crate) struct InvoiceLines {
+    body: Body,
+    buffer: Vec<u8>,
+}
+
+impl RestClient {
+    pub(crate) fn new(
+        base_url: &str,
+        connector: HttpsConnector<HttpConnector>,
+        macaroon: Option<String>,
+    ) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            macaroon,
+            client: Client::builder().build(connector),
+        }
+    }
+
+    pub(crate) async fn channel_balance(&self) -> Result<ChannelBalanceResponse, Status> {
+        let response = self.call(Method::GET, "/v1/balance/channels", None).await?;
+
+        Ok(ChannelBalanceResponse {
+            local_balance: amount(&response, "local_balance"),
+            remote_balance: amount(&response, "remote_balance"),
+            unsettled_local_balance: amount(&response, "unsettled_local_balance"),
+            unsettled_remote_balance: amount(&response, "unsettled_remote_balance"),
+            pending_open_local_balance: amount(&response, "pending_open_local_balance"),
+            pending_open_remote_balance: amount(&response, "pending_open_remote_balance"),
+            ..Default::default()
+        })
+    }
+
+    pub(crate) async fn get_info(&self) -> Result<GetInfoResponse, Status> {
+        let response = self.call(Method::GET, "/v1/getinfo", None).await?;
+
+        Ok(GetInfoResponse {
+            version: string(&response, "version"),
+            commit_hash: string(&response, "commit_hash"),
+            identity_pubkey: string(&response, "identity_pubkey"),
+            alias: string(&response, "alias"),
+            color: string(&response, "color"),
+            num_pending_channels: number(&response, "num_pending_channels"),
+            num_active_channels: number(&response, "num_active_channels"),
+            num_inactive_channels: number(&response, "num_inactive_channels"),
+            num_peers: number(&response, "num_peers"),
+            block_height: number(&response, "block_height"),
+            block_hash: string(&response, "block_hash"),
+            best_header_timestamp: number(&response, "best_header_timestamp"),
+            synced_to_chain: boolean(&response, "synced_to_chain"),
+            synced_to_graph: boolean(&response, "synced_to_graph"),
+            uris: array(&response, "uris").iter().filter_map(|uri| uri.as_str().map(String::from)).collect(),
+            ..Default::default()
+        })
+    }
+
+    pub(crate) async fn list_payments(
+        &self,
+        include_incomplete: bool,
+        index_offset: u64,
+        max_payments: u64,
+        reversed: bool,
+    ) -> Result<ListPaymentsResponse, Status> {
+        let path = format!(
+            "/v1/payments?include_incomplete={}&index_offset={}&max_payments={}&reversed={}",
+            include_incomplete, index_offset, max_payments, reversed
+        );
+        let response = self.call(Method::GET, &path, None).await?;
+
+        Ok(ListPaymentsResponse {
+            payments: array(&response, "payments").iter().map(payment).collect(),
+            first_index_offset: number(&response, "first_index_offset"),
+            last_index_offset: number(&response, "last_index_offset"),
+        })
+    }
+
+    pub(crate) async fn list_invoices(
+        &self,
+        pending_only: bool,
+        index_offset: u64,
+        num_max_invoices: u64,
+        reversed: bool,
+    ) -> Result<ListInvoiceResponse, Status> {
+        let path = format!(
+            "/v1/invoices?pending_only={}&index_offset={}&num_max_invoices={}&reversed={}",
+            pending_only, index_offset, num_max_invoices, reversed
+        );
+        let response = self.call(Method::GET, &path, None).await?;
+
+        Ok(ListInvoiceResponse {
+            invoices: array(&response, "invoices").iter().map(invoice).collect(),
+            last_index_offset: number(&response, "last_index_offset"),
+            first_index_offset: number(&response, "first_index_offset"),
+        })
+    }
+
+    pub(crate) async fn subscribe_invoices(
+        &self,
+        add_index: u64,
+        settle_index: u64,
+    ) -> Result<InvoiceLines, Status> {
+        let path = format!("/v1/invoices/subscribe?add_index={}&settle_index={}", add_index, settle_index);
+        let response = self.request(Method::GET, &path, None).await?;
+
+        Ok(InvoiceLines {
+            body: response.into_body(),
+            buffer: Vec::new(),
+        })
+    }
+
+    pub(crate) async fn lookup_invoice(&self, r_hash: &[u8]) -> Result<Invoice, Status> {
+        let response = self.call(Method::GET, &format!("/v1/invoice/{}", hex::encode(r_hash)), None).await?;
+
+        Ok(invoice(&response))
+    }
+
+    pub(crate) async fn send_payment_sync(&self, send_request: SendRequest) -> Result<SendResponse, Status> {
+        let response = self.call(Method::POST, "/v1/channels/transactions", Some(send_request_json(send_request))).await?;
+
+        Ok(SendResponse {
+            payment_error: string(&response, "payment_error"),
+            payment_preimage: bytes(&response, "payment_preimage"),
+            payment_route: response.get("payment_route").filter(|r| r.is_object()).map(route),
+            payment_hash: bytes(&response, "payment_hash"),
+        })
+    }
+
+    pub(crate) async fn wallet_balance(&self) -> Result<WalletBalanceResponse, Status> {
+        let response = self.call(Method::GET, "/v1/balance/blockchain", None).await?;
+
+        Ok(WalletBalanceResponse {
+            total_balance: number(&response, "total_balance"),
+            confirmed_balance: number(&response, "confirmed_balance"),
+            unconfirmed_balance: number(&response, "unconfirmed_balance"),
+            ..Default::default()
+        })
+    }
+
+    /// Make a call and read the whole response as JSON.
+    async fn call(&self, method: Method, path: &str, body: Option<Value>) -> Result<Value, Status> {
+        let response = self.request(method, path, body).await?;
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(|e| Status::unavailable(format!("Reading the response from LND failed: {}", e)))?;
+
+        serde_json::from_slice(&body)
+            .map_err(|e| Status::internal(format!("LND sent a response that isn't JSON: {}", e)))
+    }
+
+    /// Make a call, turning an error response into the status gRPC would have returned.
+    async fn request(&self, method: Method, path: &str, body: Option<Value>) -> Result<hyper::Response<Body>, Status> {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(format!("{}{}", self.base_url, path))
+            .header(header::ACCEPT, "application/json");
+        if let Some(macaroon) = &self.macaroon {
+            request = request.header(MACAROON_HEADER, macaroon.as_str());
+        }
+        let request = match body {
+            Some(body) => request
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        }
+        .map_err(|e| Status::invalid_argument(format!("Invalid request: {}", e)))?;
+
+        let response = self
+            .client
+            .request(request)
+            .await
+            .map_err(|e| Status::unavailable(format!("Calling LND failed: {}", e)))?;
+        if response.status().is_success() {
+            return Ok(response);
+        }
+
+        let http_status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap_or_default();
+        Err(error_status(http_status, &body))
+    }
+}
+
+impl InvoiceLines {
+    /// The next invoice update, or None once LND closes the subscription.
+    pub(crate) async fn message(&mut self) -> Result<Option<Invoice>, Status> {
+        loop {
+            if let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..=end).collect();
+                if let Some(invoice) = invoice_update(&line).map_err(|e| *e)? {
+                    return Ok(Some(invoice));
+                }
+                continue;
+            }
+
+            match self.body.data().await {
+                Some(Ok(chunk)) => self.buffer.extend_from_slice(&chunk),
+                Some(Err(e)) => return Err(Status::unavailable(format!("Invoice subscription failed: {}", e))),
+                None => {
+                    //Whatever is left is the last update, if anything
+                    let line = std::mem::take(&mut self.buffer);
+                    return invoice_update(&line).map_err(|e| *e);
+                }
+            }
+        }
+    }
+}
+
+/// Read one line of a subscription: {"result": {...}} for an update or {"error": {...}} when the
+/// stream failed.  Blank lines are skipped.  The status is boxed to keep the result small.
+fn invoice_update(line: &[u8]) -> Result<Option<Invoice>, Box<Status>> {
+    if line.iter().all(u8::is_ascii_whitespace) {
+        return Ok(None);
+    }
+
+    let update: Value = serde_json::from_slice(line)
+        .map_err(|e| Box::new(Status::internal(format!("LND sent an invoice update that isn't JSON: {}", e))))?;
+    if let Some(error) = update.get("error") {
+        return Err(Box::new(status_from_json(error).unwrap_or_else(|| Status::unknown(error.to_string()))));
+    }
+
+    match update.get("result") {
+        Some(result) => Ok(Some(invoice(result))),
+        None => Ok(None),
+    }
+}
+
+/// The gateway reports errors as {"code": 5, "message": "..."} (older versions also as
+/// {"error": "...", "grpc_code": 5}), so use that code when there is one and the HTTP status
+/// otherwise.
+fn error_status(http_status: StatusCode, body: &[u8]) -> Status {
+    let json = serde_json::from_slice::<Value>(body).ok();
+    if let Some(status) = json.as_ref().and_then(status_from_json) {
+        return status;
+    }
+
+    let message = String::from_utf8_lossy(body).trim().to_string();
+    let message = if message.is_empty() { http_status.to_string() } else { message };
+    let code = match http_status {
+        StatusCode::BAD_REQUEST => Code::InvalidArgument,
+        StatusCode::UNAUTHORIZED => Code::Unauthenticated,
+        StatusCode::FORBIDDEN => Code::PermissionDenied,
+        StatusCode::NOT_FOUND => Code::NotFound,
+        StatusCode::CONFLICT => Code::AlreadyExists,
+        StatusCode::TOO_MANY_REQUESTS => Code::ResourceExhausted,
+        StatusCode::NOT_IMPLEMENTED => Code::Unimplemented,
+        StatusCode::SERVICE_UNAVAILABLE | StatusCode::BAD_GATEWAY | StatusCode::GATEWAY_TIMEOUT => Code::Unavailable,
+        _ => Code::Unknown,
+    };
+
+    Status::new(code, message)
+}
+
+fn status_from_json(error: &Value) -> Option<Status> {
+    let code = error.get("code").or_else(|| error.get("grpc_code"))?.as_i64()?;
+    let message = match (error.get("message"), error.get("error")) {
+        (Some(Value::String(message)), _) if !message.is_empty() => message.clone(),
+        (_, Some(Value::String(message))) => message.clone(),
+        _ => String::new(),
+    };
+
+    Some(Status::new(Code::from_i32(code as i32), message))
+}
+
+fn send_request_json(send_request: SendRequest) -> Value {
+    let mut request = Map::new();
+    let mut put = |name: &str, value: Value| {
+        request.insert(name.to_string(), value);
+    };
+
+    if !send_request.dest.is_empty() {
+        put("dest", base64::encode(&send_request.dest).into());
+    }
+    if send_request.amt != 0 {
+        put("amt", send_request.amt.to_string().into());
+    }
+    if send_request.amt_msat != 0 {
+        put("amt_msat", send_request.amt_msat.to_string().into());
+    }
+    if !send_request.payment_hash.is_empty() {
+        put("payment_hash", base64::encode(&send_request.payment_hash).into());
+    }
+    if !send_request.payment_request.is_empty() {
+        put("payment_request", send_request.payment_request.into());
+    }
+    if send_request.final_cltv_delta != 0 {
+        put("final_cltv_delta", send_request.final_cltv_delta.into());
+    }
+    if let Some(limit) = send_request.fee_limit.and_then(|fee_limit| fee_limit.limit) {
+        let (name, value) = match limit {
+            Limit::Fixed(sats) => ("fixed", sats),
+            Limit::FixedMsat(msats) => ("fixed_msat", msats),
+            Limit::Percent(percent) => ("percent", percent),
+        };
+        let mut fee_limit = Map::new();
+        fee_limit.insert(name.to_string(), value.to_string().into());
+        put("fee_limit", Value::Object(fee_limit));
+    }
+    if send_request.outgoing_chan_id != 0 {
+        put("outgoing_chan_id", send_request.outgoing_chan_id.to_string().into());
+    }
+    if !send_request.last_hop_pubkey.is_empty() {
+        put("last_hop_pubkey", base64::encode(&send_request.last_hop_pubkey).into());
+    }
+    if send_request.cltv_limit != 0 {
+        put("cltv_limit", send_request.cltv_limit.into());
+    }
+    if !send_request.dest_custom_records.is_empty() {
+        let records = send_request
+            .dest_custom_records
+            .iter()
+            .map(|(record_type, value)| (record_type.to_string(), base64::encode(value).into()))
+            .collect();
+        put("dest_custom_records", Value::Object(records));
+    }
+    if send_request.allow_self_payment {
+        put("allow_self_payment", true.into());
+    }
+    if !send_request.dest_features.is_empty() {
+        put("dest_features", send_request.dest_features.into());
+    }
+    if !send_request.payment_addr.is_empty() {
+        put("payment_addr", base64::encode(&send_request.payment_addr).into());
+    }
+
+    Value::Object(request)
+}
+
+fn invoice(json: &Value) -> Invoice {
+    Invoice {
+        memo: string(json, "memo"),
+        r_preimage: bytes(json, "r_preimage"),
+        r_hash: bytes(json, "r_hash"),
+        value: number(json, "value"),
+        value_msat: number(json, "value_msat"),
+        creation_date: number(json, "creation_date"),
+        settle_date: number(json, "settle_date"),
+        payment_request: string(json, "payment_request"),
+        description_hash: bytes(json, "description_hash"),
+        expiry: number(json, "expiry"),
+        fallback_addr: string(json, "fallback_addr"),
+        cltv_expiry: number(json, "cltv_expiry"),
+        private: boolean(json, "private"),
+        add_index: number(json, "add_index"),
+        settle_index: number(json, "settle_index"),
+        amt_paid_sat: number(json, "amt_paid_sat"),
+        amt_paid_msat: number(json, "amt_paid_msat"),
+        state: enumeration(json, "state", &INVOICE_STATES),
+        htlcs: array(json, "htlcs").iter().map(invoice_htlc).collect(),
+        is_keysend: boolean(json, "is_keysend"),
+        payment_addr: bytes(json, "payment_addr"),
+        is_amp: boolean(json, "is_amp"),
+        ..Default::default()
+    }
+}
+
+fn invoice_htlc(json: &Value) -> InvoiceHtlc {
+    InvoiceHtlc {
+        chan_id: number(json, "chan_id"),
+        htlc_index: number(json, "htlc_index"),
+        amt_msat: number(json, "amt_msat"),
+        accept_height: number(json, "accept_height"),
+        accept_time: number(json, "accept_time"),
+        resolve_time: number(json, "resolve_time"),
+        expiry_height: number(json, "expiry_height"),
+        state: enumeration(json, "state", &INVOICE_HTLC_STATES),
+        custom_records: custom_records(json, "custom_records"),
+        mpp_total_amt_msat: number(json, "mpp_total_amt_msat"),
+        ..Default::default()
+    }
+}
+
+fn payment(json: &Value) -> Payment {
+    Payment {
+        payment_hash: string(json, "payment_hash"),
+        payment_preimage: string(json, "payment_preimage"),
+        value_sat: number(json, "value_sat"),
+        value_msat: number(json, "value_msat"),
+        payment_request: string(json, "payment_request"),
+        status: enumeration(json, "status", &PAYMENT_STATUSES),
+        fee_sat: number(json, "fee_sat"),
+        fee_msat: number(json, "fee_msat"),
+        creation_time_ns: number(json, "creation_time_ns"),
+        htlcs: array(json, "htlcs").iter().map(htlc_attempt).collect(),
+        payment_index: number(json, "payment_index"),
+        failure_reason: enumeration(json, "failure_reason", &FAILURE_REASONS),
+        ..Default::default()
+    }
+}
+
+fn htlc_attempt(json: &Value) -> HtlcAttempt {
+    HtlcAttempt {
+        attempt_id: number(json, "attempt_id"),
+        status: enumeration(json, "status", &HTLC_STATUSES),
+        route: json.get("route").filter(|r| r.is_object()).map(route),
+        attempt_time_ns: number(json, "attempt_time_ns"),
+        resolve_time_ns: number(json, "resolve_time_ns"),
+        preimage: bytes(json, "preimage"),
+        ..Default::default()
+    }
+}
+
+fn route(json: &Value) -> Route {
+    Route {
+        total_time_lock: number(json, "total_time_lock"),
+        hops: array(json, "hops").iter().map(hop).collect(),
+        total_fees_msat: number(json, "total_fees_msat"),
+        total_amt_msat: number(json, "total_amt_msat"),
+        ..Default::default()
+    }
+}
+
+fn hop(json: &Value) -> Hop {
+    Hop {
+        chan_id: number(json, "chan_id"),
+        expiry: number(json, "expiry"),
+        amt_to_forward_msat: number(json, "amt_to_forward_msat"),
+        fee_msat: number(json, "fee_msat"),
+        pub_key: string(json, "pub_key"),
+        tlv_payload: boolean(json, "tlv_payload"),
+        custom_records: custom_records(json, "custom_records"),
+        ..Default::default()
+    }
+}
+
+fn amount(json: &Value, name: &str) -> Option<Amount> {
+    json.get(name).filter(|a| a.is_object()).map(|a| Amount {
+        sat: number(a, "sat"),
+        msat: number(a, "msat"),
+    })
+}
+
+/// A number of any width.  64 bit numbers come as strings so they survive javascript.
+fn number<T: std::str::FromStr + Default + TryFrom<i64> + TryFrom<u64>>(json: &Value, name: &str) -> T {
+    match json.get(name) {
+        Some(Value::String(s)) => s.trim().parse().unwrap_or_default(),
+        Some(Value::Number(n)) => match (n.as_u64(), n.as_i64()) {
+            (Some(n), _) => T::try_from(n).unwrap_or_default(),
+            (None, Some(n)) => T::try_from(n).unwrap_or_default(),
+            _ => T::default(),
+        },
+        _ => T::default(),
+    }
+}
+
+fn string(json: &Value, name: &str) -> String {
+    json.get(name).and_then(Value::as_str).unwrap_or_default().to_string()
+}
+
+fn boolean(json: &Value, name: &str) -> bool {
+    json.get(name).and_then(Value::as_bool).unwrap_or_default()
+}
+
+fn bytes(json: &Value, name: &str) -> Vec<u8> {
+    json.get(name).and_then(Value::as_str).map(decode_base64).unwrap_or_default()
+}
+
+fn array<'a>(json: &'a Value, name: &str) -> &'a [Value] {
+    json.get(name).and_then(Value::as_array).map(Vec::as_slice).unwrap_or_default()
+}
+
+/// An enum, by name or by number
+fn enumeration<T: Copy + Into<i32>>(json: &Value, name: &str, names: &[(&str, T)]) -> i32 {
+    match json.get(name) {
+        Some(Value::String(s)) => names.iter().find(|(n, _)| *n == s).map(|(_, v)| (*v).into()).unwrap_or_default(),
+        Some(Value::Number(n)) => n.as_i64().unwrap_or_default() as i32,
+        _ => 0,
+    }
+}
+
+/// Custom records are a map of the record type, as a string, to the base64 value
+fn custom_records(json: &Value, name: &str) -> HashMap<u64, Vec<u8>> {
+    json.get(name)
+        .and_then(Value::as_object)
+        .map(|records| {
+            records
+                .iter()
+                .filter_map(|(record_type, value)| {
+                    Some((record_type.parse().ok()?, decode_base64(value.as_str()?)))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn decode_base64(value: &str) -> Vec<u8> {
+    base64::decode(value)
+        .or_else(|_| base64::decode_config(value, base64::URL_SAFE))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invoices_are_read_from_json() {
+        let json: Value = serde_json::from_str(r#"{
+            "memo": "", "r_hash": "3q2+7w==", "value": "0", "settle_date": "1650000000",
+            "add_index": "12", "settle_index": "7", "amt_paid_sat": "100", "amt_paid_msat": "100000",
+            "state": "SETTLED", "is_keysend": true,
+            "htlcs": [{"chan_id": "771003147839717377", "htlc_index": "3", "amt_msat": "100000",
+                       "state": "SETTLED", "custom_records": {"7629169": "eyJhY3Rpb24iOiJib29zdCJ9"}}]
+        }"#).unwrap();
+        let invoice = invoice(&json);
+
+        assert_eq!(invoice.r_hash, vec![0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(invoice.add_index, 12);
+        assert_eq!(invoice.settle_date, 1650000000);
+        assert_eq!(invoice.amt_paid_sat, 100);
+        assert_eq!(invoice.state, InvoiceState::Settled as i32);
+        assert!(invoice.is_keysend);
+        assert_eq!(invoice.htlcs[0].chan_id, 771003147839717377);
+        assert_eq!(invoice.htlcs[0].state, InvoiceHtlcState::Settled as i32);
+        assert_eq!(invoice.htlcs[0].custom_records[&7629169], br#"{"action":"boost"}"#.to_vec());
+    }
+
+    #[test]
+    fn subscription_lines() {
+        let update = invoice_update(br#"{"result": {"add_index": "5", "state": "OPEN"}}"#).unwrap().unwrap();
+        assert_eq!(update.add_index, 5);
+        assert_eq!(update.state, InvoiceState::Open as i32);
+
+        assert!(invoice_update(b"\n").unwrap().is_none());
+
+        let error = invoice_update(br#"{"error": {"grpc_code": 14, "message": "lnd is shutting down"}}"#).unwrap_err();
+        assert_eq!(error.code(), Code::Unavailable);
+        assert_eq!(error.message(), "lnd is shutting down");
+    }
+
+    #[test]
+    fn errors_keep_their_grpc_code() {
+        let status = error_status(StatusCode::INTERNAL_SERVER_ERROR, br#"{"code": 2, "message": "invoice is already paid", "details": []}"#);
+        assert_eq!(status.code(), Code::Unknown);
+        assert_eq!(status.message(), "invoice is already paid");
+
+        let status = error_status(StatusCode::FORBIDDEN, b"verification failed: signature mismatch");
+        assert_eq!(status.code(), Code::PermissionDenied);
+    }
+
+    #[test]
+    fn keysends_are_written_as_json() {
+        let mut dest_custom_records = HashMap::new();
+        dest_custom_records.insert(5482373484, vec![1, 2, 3]);
+        let json = send_request_json(SendRequest {
+            dest: vec![2; 33],
+            amt_msat: 5000,
+            payment_hash: vec![0xde, 0xad, 0xbe, 0xef],
+            dest_custom_records,
+            ..Default::default()
+        });
+
+        assert_eq!(json["amt_msat"], "5000");
+        assert_eq!(json["payment_hash"], "3q2+7w==");
+        assert_eq!(json["dest_custom_records"]["5482373484"], "AQID");
+        assert!(json.get("amt").is_none());
+    }
+}
@@ -0,0 +1,214 @@
+use crate::router::{Access, ApiError, IntoResponse};
+use crate::{db_call, Error, Response};
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use hyper::{header, Body, Method, Request, StatusCode};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+
+//Constants --------------------------------------------------------------------------------------------------
+pub const SESSION_COOKIE: &str = "helipad_session";
+pub const SESSION_LIFETIME: i64 = 30 * 24 * 60 * 60;
+const SECRET_BYTES: usize = 32;
+
+
+//Structs and Enums ------------------------------------------------------------------------------------------
+//How the web UI and api are protected.  With no password hash set everyone is let in, so startup
+//warns about it unless we only listen on loopback.  Cookies are marked secure when we're serving https.
+#[derive(Clone, Debug, Default)]
+pub struct AuthConfig {
+    pub password_hash: Option<String>,
+    pub secure_cookies: bool,
+}
+
+
+//Functions --------------------------------------------------------------------------------------------------
+//Check that a request may call a route.  A login session can call anything, an api token only the
+//routes that read.
+pub async fn authorize(access: Access, req: &Request<Body>, config: &AuthConfig, db: &dbif::Database) -> Result<(), ApiError> {
+    if access == Access::Public || config.password_hash.is_none() {
+        return Ok(());
+    }
+
+    if let Some(session) = session_cookie(req) {
+        let token_hash = hash_secret(&session);
+        if db_call(db, move |db| db.session_is_valid(&token_hash, now())).await? {
+            return Ok(());
+        }
+    }
+
+    if let Some(token) = api_token(req) {
+        let token_hash = hash_secret(&token);
+        return match db_call(db, move |db| db.use_api_token(&token_hash, now())).await? {
+            Some(_) if access == Access::Token => Ok(()),
+            Some(_) => Err(ApiError::Forbidden("Api tokens can only read.  Log in to make changes.".to_string())),
+            None => Err(ApiError::Unauthorized("Invalid api token.".to_string())),
+        };
+    }
+
+    Err(ApiError::Unauthorized("Log in or pass an api token.".to_string()))
+}
+
+//What to send back when a request isn't let in.  A browser asking for a page is sent to the login
+//page instead of getting an error.
+pub fn denied(req: &Request<Body>, e: ApiError) -> Response {
+    let wants_html = req.headers()
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"));
+
+    match e {
+        ApiError::Unauthorized(_) if req.method() == Method::GET && wants_html => {
+            hyper::Response::builder()
+                .status(StatusCode::SEE_OTHER)
+                .header(header::LOCATION, "/login")
+                .body(Body::empty())
+                .unwrap()
+        }
+        e => e.into_response(),
+    }
+}
+
+//Check a password against the configured hash.  This is slow on purpose, so keep it off the async
+//executor.
+pub fn check_password(password: &str, password_hash: &str) -> Result<bool, Error> {
+    let parsed = PasswordHash::new(password_hash)
+        .map_err(|e| format!("Invalid password hash: {}", e))?;
+
+    Ok(Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+}
+
+//Hash a password for the password_hash setting
+pub fn hash_password(password: &str) -> Result<String, Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default().hash_password(password.as_bytes(), &salt)
+        .map_err(|e| format!("Could not hash the password: {}", e))?;
+
+    Ok(hash.to_string())
+}
+
+//Make sure a configured password hash can be used before we start relying on it
+pub fn validate_password_hash(password_hash: &str) -> Result<(), Error> {
+    PasswordHash::new(password_hash)
+        .map_err(|e| format!("Invalid password hash: {}.  Use \"helipad hash-password\" to make one.", e))?;
+
+    Ok(())
+}
+
+//A new random secret for a session or api token
+pub fn new_secret() -> String {
+    let mut bytes = [0u8; SECRET_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+//Sessions and tokens are stored as hashes.  They're long and random, so a plain sha256 is enough.
+pub fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+//The Set-Cookie header value for a new login session
+pub fn session_cookie_header(session: &str, secure: bool) -> String {
+    format!("{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Strict{}",
+            SESSION_COOKIE, session, SESSION_LIFETIME, if secure { "; Secure" } else { "" })
+}
+
+//The Set-Cookie header value that makes the browser forget its login session
+pub fn cleared_cookie_header(secure: bool) -> String {
+    format!("{}=; Path=/; Max-Age=0; HttpOnly; SameSite=Strict{}", SESSION_COOKIE, if secure { "; Secure" } else { "" })
+}
+
+//The login session a request carries in its cookies, if any
+pub fn session_cookie(req: &Request<Body>) -> Option<String> {
+    req.headers()
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|cookies| cookies.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, value)| *name == SESSION_COOKIE && !value.is_empty())
+        .map(|(_, value)| value.to_string())
+}
+
+//The api token a request carries, either as "Authorization: Bearer <token>" or, for clients like
+//EventSource that can't set headers, as a "token" query parameter
+fn api_token(req: &Request<Body>) -> Option<String> {
+    let bearer = req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|authorization| authorization.to_str().ok())
+        .and_then(|authorization| authorization.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string());
+    if bearer.is_some() {
+        return bearer;
+    }
+
+    req.uri().query().and_then(|query| {
+        url::form_urlencoded::parse(query.as_bytes())
+            .find(|(name, _)| name == "token")
+            .map(|(_, token)| token.into_owned())
+    })
+}
+
+//The current time as unix seconds
+pub fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+
+//Tests ------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(uri: &str, headers: &[(&str, &str)]) -> Request<Body> {
+        let mut builder = Request::builder().uri(uri);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn passwords_check_against_their_hash() {
+        let hash = hash_password("correct horse").unwrap();
+        assert!(validate_password_hash(&hash).is_ok());
+        assert!(check_password("correct horse", &hash).unwrap());
+        assert!(!check_password("battery staple", &hash).unwrap());
+        assert!(validate_password_hash("correct horse").is_err());
+    }
+
+    #[test]
+    fn session_comes_from_its_cookie() {
+        let req = request("/", &[("Cookie", "theme=dark; helipad_session=abc123; other=1")]);
+        assert_eq!(session_cookie(&req), Some("abc123".to_string()));
+
+        let req = request("/", &[("Cookie", "helipad_session=")]);
+        assert_eq!(session_cookie(&req), None);
+        assert_eq!(session_cookie(&request("/", &[])), None);
+    }
+
+    #[test]
+    fn api_token_from_header_or_query() {
+        let req = request("/boosts", &[("Authorization", "Bearer abc123")]);
+        assert_eq!(api_token(&req), Some("abc123".to_string()));
+
+        let req = request("/boosts/stream?since=5&token=def456", &[]);
+        assert_eq!(api_token(&req), Some("def456".to_string()));
+
+        let req = request("/boosts", &[("Authorization", "Basic dXNlcjpwYXNz")]);
+        assert_eq!(api_token(&req), None);
+    }
+
+    #[test]
+    fn browsers_are_sent_to_login() {
+        let req = request("/", &[("Accept", "text/html,application/xhtml+xml")]);
+        let response = denied(&req, ApiError::Unauthorized("".to_string()));
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()[header::LOCATION], "/login");
+
+        let req = request("/boosts", &[("Accept", "application/json")]);
+        assert_eq!(denied(&req, ApiError::Unauthorized("".to_string())).status(), StatusCode::UNAUTHORIZED);
+    }
+}
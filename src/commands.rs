@@ -0,0 +1,201 @@
+use crate::{apply_settled_value, auth, export, lightning, sweep_invoices, tlv};
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+use tokio::sync::broadcast;
+
+
+//Constants --------------------------------------------------------------------------------------------------
+const REPARSE_PAGE_SIZE: u64 = 500;
+
+
+//Structs and Enums ------------------------------------------------------------------------------------------
+//What helipad was asked to do on the command line.  With no subcommand it runs the web server.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Serve,
+    Reparse,
+    Resync { from_index: u64 },
+    Export { format: export::ExportFormat, filter: Box<dbif::BoostFilter>, output: String },
+    HashPassword,
+}
+
+
+//Functions --------------------------------------------------------------------------------------------------
+impl Command {
+    //Work out the subcommand from whatever arguments are left after the config options.  Anything
+    //that isn't a subcommand (ex. a listen port) means we should serve as usual.
+    pub fn from_args(args: &[String]) -> Result<Command, String> {
+        match args.first().map(|a| a.as_str()) {
+            Some("reparse") => {
+                if args.len() > 1 {
+                    return Err(format!("Unexpected argument to reparse: [{}]", args[1]))
+                }
+                Ok(Command::Reparse)
+            }
+            Some("resync") => {
+                let mut from_index = 0;
+                for (option, value) in options(&args[1..])? {
+                    match option.as_str() {
+                        "from-index" => {
+                            from_index = value.parse::<u64>()
+                                .map_err(|_| format!("Invalid --from-index: [{}]", value))?;
+                        }
+                        _ => return Err(format!("Unexpected argument to resync: [--{}]", option)),
+                    }
+                }
+                Ok(Command::Resync { from_index })
+            }
+            Some("export") => {
+                let mut format = export::ExportFormat::Csv;
+                let mut filter = dbif::BoostFilter::default();
+                let mut output = None;
+                for (option, value) in options(&args[1..])? {
+                    match option.as_str() {
+                        "format" => {
+                            format = export::ExportFormat::from_name(&value)
+                                .ok_or(format!("Invalid --format: [{}].  Use csv or jsonl.", value))?;
+                        }
+                        "from" => {
+                            filter.since_time = Some(export::parse_time(&value)
+                                .ok_or(format!("Invalid --from: [{}].  Use unix seconds or YYYY-MM-DD.", value))?);
+                        }
+                        "to" => {
+                            filter.until_time = Some(export::parse_time(&value)
+                                .ok_or(format!("Invalid --to: [{}].  Use unix seconds or YYYY-MM-DD.", value))?);
+                        }
+                        "action" => {
+                            filter.action = match value.as_str() {
+                                "all" => None,
+                                name => Some(dbif::action_from_name(name)
+                                    .ok_or(format!("Invalid --action: [{}].  Use boost, stream, invoice, keysend, unknown or all.", value))?),
+                            };
+                        }
+                        "output" => output = Some(value),
+                        _ => return Err(format!("Unexpected argument to export: [--{}]", option)),
+                    }
+                }
+                let output = output.unwrap_or_else(|| format!("boosts.{}", format.extension()));
+                Ok(Command::Export { format, filter: Box::new(filter), output })
+            }
+            Some("hash-password") => {
+                if args.len() > 1 {
+                    return Err(format!("Unexpected argument to hash-password: [{}]", args[1]))
+                }
+                Ok(Command::HashPassword)
+            }
+            _ => Ok(Command::Serve),
+        }
+    }
+}
+
+//Split "--name value" and "--name=value" options into names and values
+fn options(args: &[String]) -> Result<Vec<(String, String)>, String> {
+    let mut options = Vec::new();
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        let option = match arg.strip_prefix("--") {
+            Some(option) => option,
+            None => return Err(format!("Unexpected argument: [{}]", arg)),
+        };
+        match option.split_once('=') {
+            Some((name, value)) => options.push((name.to_string(), value.to_string())),
+            None => {
+                let value = rest.next().ok_or(format!("Missing a value for: [--{}]", option))?;
+                options.push((option.to_string(), value.clone()));
+            }
+        }
+    }
+
+    Ok(options)
+}
+
+pub fn usage() -> &'static str {
+    "Usage:\n  \
+     helipad [port]                    Run the web server\n  \
+     helipad reparse                   Decode the stored custom records again and update the boosts\n  \
+     helipad resync [--from-index N]   Fetch invoices after index N from LND again and update the boosts\n  \
+     helipad export [--format csv|jsonl] [--from DATE] [--to DATE] [--action ACTION] [--output FILE]\n                                    \
+     Write every settled payment to a file for accounting (boosts.csv by default)\n  \
+     helipad hash-password             Read a password from stdin and print its hash for the password_hash setting"
+}
+
+//Read a password from stdin and hash it for the password_hash setting
+pub fn hash_password() -> Result<String, Box<dyn Error + Send + Sync>> {
+    eprint!("Password: ");
+    let mut password = String::new();
+    std::io::stdin().read_line(&mut password)?;
+    let password = password.trim_end_matches(&['\r', '\n'][..]);
+    if password.is_empty() {
+        return Err("The password can't be empty.".into());
+    }
+
+    auth::hash_password(password)
+}
+
+//Run the record decoders over every stored payment that has a podcast tlv or other custom records
+//and update the columns derived from them.  Returns the number of payments reparsed.
+pub fn reparse(db: &dbif::Database) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let decoders = tlv::RecordDecoders::default();
+    let mut count = 0;
+    let mut index = 0;
+
+    loop {
+        let boosts = db.get_boosts_with_records(index, REPARSE_PAGE_SIZE)?;
+        if boosts.is_empty() {
+            break;
+        }
+
+        for mut boost in boosts {
+            index = boost.index;
+
+            let htlcs = db.get_htlcs(boost.index)?;
+
+            //Payments stored before we kept every custom record only have the podcast tlv
+            let mut records = db.get_custom_records(boost.index)?;
+            if records.is_empty() {
+                records.push(dbif::CustomRecord {
+                    boost_index: boost.index,
+                    htlc_index: 0,
+                    record_type: tlv::TLV_PODCAST,
+                    value: tlv::stored_podcast_record(&boost),
+                });
+            }
+
+            //Start from a blank slate so the decoders fill things in just as they would for a new payment
+            tlv::clear_decoded(&mut boost, &records);
+            decoders.decode(&mut boost, &records);
+            apply_settled_value(&mut boost, &htlcs);
+            db.add_invoice(boost)?;
+            count += 1;
+        }
+        println!("Reparsed {} payments...", count);
+    }
+
+    Ok(count)
+}
+
+//Fetch every invoice after the given index from LND again and store it over what we have.  LND is
+//only tried once, so a wrong address or credentials fail the command instead of retrying forever.
+pub async fn resync(lnd_connection: lightning::LndConnection, db: &dbif::Database, from_index: u64) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut lightning = lnd_connection.connect_once().await?;
+
+    //There's no web server running to stream boosts to
+    let (live_boosts, _) = broadcast::channel(1);
+    sweep_invoices(&mut lightning, db, &live_boosts, from_index).await?;
+
+    Ok(())
+}
+
+//Write every settled payment that matches the filter to a file.  Returns the number of payments exported.
+pub fn export(db: &dbif::Database, format: export::ExportFormat, filter: &dbif::BoostFilter, output: &str) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let mut file = File::create(output)?;
+
+    let count = export::export_boosts(db, filter, format, |chunk| {
+        file.write_all(&chunk)?;
+        Ok(())
+    })?;
+    file.sync_all()?;
+
+    Ok(count)
+}
@@ -0,0 +1,232 @@
+use chrono::{DateTime, NaiveDate, SecondsFormat, Utc};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::error::Error;
+
+
+//Constants --------------------------------------------------------------------------------------------------
+//How many payments are read from the database and written out at a time
+const EXPORT_PAGE_SIZE: u64 = 1000;
+
+
+//Structs and Enums ------------------------------------------------------------------------------------------
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    Jsonl,
+}
+
+//One line of the export.  Amounts are given in both sats and millisats since what arrived isn't always
+//a whole number of sats.
+#[derive(Serialize, Debug)]
+struct ExportRow<'a> {
+    index: u64,
+    date: String,
+    sats: String,
+    msats: i64,
+    total_msats: i64,
+    action: &'static str,
+    podcast: Cow<'a, str>,
+    episode: Cow<'a, str>,
+    sender: Cow<'a, str>,
+    app: Cow<'a, str>,
+    message: Cow<'a, str>,
+    payment_hash: &'a str,
+}
+
+
+//Functions --------------------------------------------------------------------------------------------------
+impl ExportFormat {
+    pub fn from_name(name: &str) -> Option<ExportFormat> {
+        match name {
+            "csv" => Some(ExportFormat::Csv),
+            "jsonl" => Some(ExportFormat::Jsonl),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Jsonl => "jsonl",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Jsonl => "application/jsonl",
+        }
+    }
+}
+
+//Write out every settled payment that matches the filter, oldest first.  The payments are read and
+//serialized a page at a time and each chunk is handed to write, so the whole ledger is never in
+//memory at once.  Returns how many payments were exported.
+pub fn export_boosts<W>(db: &dbif::Database, filter: &dbif::BoostFilter, format: ExportFormat, mut write: W) -> Result<u64, Box<dyn Error + Send + Sync>>
+where
+    W: FnMut(Vec<u8>) -> Result<(), Box<dyn Error + Send + Sync>>,
+{
+    let mut count = 0;
+    let mut cursor = dbif::Cursor::After(0);
+
+    loop {
+        let page = db.get_boosts(filter, cursor, EXPORT_PAGE_SIZE)?;
+
+        //Pages come newest first
+        let chunk = serialize_boosts(page.boosts.iter().rev(), format, count == 0)?;
+        count += page.boosts.len() as u64;
+        if !chunk.is_empty() {
+            write(chunk)?;
+        }
+
+        match page.prev {
+            Some(prev) => cursor = dbif::Cursor::After(prev),
+            None => break,
+        }
+    }
+
+    Ok(count)
+}
+
+fn serialize_boosts<'a, I>(boosts: I, format: ExportFormat, with_headers: bool) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>>
+where
+    I: Iterator<Item = &'a dbif::BoostRecord>,
+{
+    match format {
+        ExportFormat::Csv => {
+            //The csv writer only adds the header line above the first row, so give it an empty one for an
+            //empty export
+            let mut writer = csv::WriterBuilder::new().has_headers(with_headers).from_writer(Vec::new());
+            let mut empty = true;
+            for boost in boosts {
+                writer.serialize(export_row(boost, format))?;
+                empty = false;
+            }
+            if empty && with_headers {
+                writer.write_record(["index", "date", "sats", "msats", "total_msats", "action", "podcast", "episode",
+                                     "sender", "app", "message", "payment_hash"])?;
+            }
+            Ok(writer.into_inner().map_err(|e| e.to_string())?)
+        }
+        ExportFormat::Jsonl => {
+            let mut chunk = Vec::new();
+            for boost in boosts {
+                serde_json::to_writer(&mut chunk, &export_row(boost, format))?;
+                chunk.push(b'\n');
+            }
+            Ok(chunk)
+        }
+    }
+}
+
+fn export_row(boost: &dbif::BoostRecord, format: ExportFormat) -> ExportRow<'_> {
+    ExportRow {
+        index: boost.index,
+        date: DateTime::<Utc>::from_timestamp(boost.time, 0)
+            .map(|date| date.to_rfc3339_opts(SecondsFormat::Secs, true))
+            .unwrap_or_default(),
+        sats: format_sats(boost.value_msat),
+        msats: boost.value_msat,
+        total_msats: boost.value_msat_total,
+        action: dbif::action_name(boost.action),
+        podcast: sender_text(&boost.podcast, format),
+        episode: sender_text(&boost.episode, format),
+        sender: sender_text(&boost.sender, format),
+        app: sender_text(&boost.app, format),
+        message: sender_text(&boost.message, format),
+        payment_hash: &boost.payment_hash,
+    }
+}
+
+//Text that came from whoever sent the payment.  Spreadsheets run a csv cell that starts like a formula,
+//so those get a leading ' to keep them as text.
+fn sender_text(value: &str, format: ExportFormat) -> Cow<'_, str> {
+    match (format, value.chars().next()) {
+        (ExportFormat::Csv, Some('=' | '+' | '-' | '@' | '\t' | '\r')) => Cow::Owned(format!("'{}", value)),
+        _ => Cow::Borrowed(value),
+    }
+}
+
+//Millisats as sats, with the fraction only when there is one (ex. 1000 or 12.345)
+fn format_sats(msats: i64) -> String {
+    let sign = if msats < 0 { "-" } else { "" };
+    let msats = msats.unsigned_abs();
+    match msats % 1000 {
+        0 => format!("{}{}", sign, msats / 1000),
+        fraction => format!("{}{}.{:03}", sign, msats / 1000, fraction),
+    }
+}
+
+//Read a point in time given as unix seconds or as a date (ex. 2021-11-30, which is midnight UTC)
+pub fn parse_time(value: &str) -> Option<i64> {
+    if let Ok(seconds) = value.parse::<i64>() {
+        return Some(seconds);
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|date| date.and_utc().timestamp())
+}
+
+
+//Tests ------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sats_keep_their_millisats() {
+        assert_eq!(format_sats(1000000), "1000");
+        assert_eq!(format_sats(12345), "12.345");
+        assert_eq!(format_sats(5), "0.005");
+        assert_eq!(format_sats(0), "0");
+    }
+
+    #[test]
+    fn times_are_unix_seconds_or_dates() {
+        assert_eq!(parse_time("1635724800"), Some(1635724800));
+        assert_eq!(parse_time("2021-11-01"), Some(1635724800));
+        assert_eq!(parse_time("2021-11-31"), None);
+        assert_eq!(parse_time("tuesday"), None);
+    }
+
+    #[test]
+    fn csv_has_one_header_line() {
+        let boost = dbif::BoostRecord {
+            index: 7,
+            time: 1635724800,
+            value_msat: 2100,
+            action: dbif::ACTION_BOOST,
+            message: "Great show, \"really\"".to_string(),
+            ..Default::default()
+        };
+
+        let first = serialize_boosts(std::iter::once(&boost), ExportFormat::Csv, true).unwrap();
+        let second = serialize_boosts(std::iter::once(&boost), ExportFormat::Csv, false).unwrap();
+        let expected_row = "7,2021-11-01T00:00:00Z,2.100,2100,0,boost,,,,,\"Great show, \"\"really\"\"\",\n";
+        assert_eq!(String::from_utf8(first).unwrap(),
+                   format!("index,date,sats,msats,total_msats,action,podcast,episode,sender,app,message,payment_hash\n{}", expected_row));
+        assert_eq!(String::from_utf8(second).unwrap(), expected_row);
+    }
+    #[test]
+    fn csv_cells_never_start_a_formula() {
+        let boost = dbif::BoostRecord {
+            index: 7,
+            time: 1635724800,
+            sender: "=HYPERLINK(\"http://example.com\")".to_string(),
+            app: "@Fountain".to_string(),
+            podcast: "+1 show".to_string(),
+            episode: "-5".to_string(),
+            message: "2+2=4".to_string(),
+            ..Default::default()
+        };
+
+        let csv = String::from_utf8(serialize_boosts(std::iter::once(&boost), ExportFormat::Csv, false).unwrap()).unwrap();
+        assert_eq!(csv, "7,2021-11-01T00:00:00Z,0,0,0,invoice,'+1 show,'-5,\"'=HYPERLINK(\"\"http://example.com\"\")\",'@Fountain,2+2=4,\n");
+
+        //Only spreadsheets need it
+        let jsonl = String::from_utf8(serialize_boosts(std::iter::once(&boost), ExportFormat::Jsonl, false).unwrap()).unwrap();
+        assert!(jsonl.contains(r#""sender":"=HYPERLINK(\"http://example.com\")""#));
+    }
+}
@@ -1,9 +1,13 @@
-use crate::{Context, Response};
-use hyper::StatusCode;
-use std::collections::HashMap;
-use std::error::Error;
-use std::fmt;
+use crate::router::{json_response, ApiError, IntoResponse, Json};
+use crate::{auth, db_call, export, lightning, payments, tlv, Context, Response};
+use hyper::{header, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
 use std::fs;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
 use voca_rs::*;
 
 
@@ -12,202 +16,807 @@ const WEBROOT_PATH_HTML: &str = "webroot/html";
 const WEBROOT_PATH_IMAGE: &str = "webroot/image";
 const WEBROOT_PATH_STYLE: &str = "webroot/style";
 const WEBROOT_PATH_SCRIPT: &str = "webroot/script";
+const DEFAULT_PAGE_SIZE: u64 = 50;
+const MAX_PAGE_SIZE: u64 = 1000;
+const STREAM_REPLAY_PAGE_SIZE: u64 = 100;
+const STREAM_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+const EXPORT_CHUNK_BUFFER: usize = 4;
+const FAILED_LOGIN_DELAY: Duration = Duration::from_secs(1);
 
 
 //Structs and Enums ------------------------------------------------------------------------------------------
-#[derive(Debug)]
-struct HydraError(String);
-impl fmt::Display for HydraError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Fatal error: {}", self.0)
-    }
+#[derive(Deserialize, Debug)]
+struct LoginRequest {
+    password: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct NewApiToken {
+    name: String,
+}
+
+//A boost to send back to a listener, ex. {"sats": 100, "message": "Thanks!"}.  The sender name
+//defaults to the podcast the boost was for.
+#[derive(Deserialize, Debug)]
+struct ReplyRequest {
+    sats: u64,
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    sender: Option<String>,
+}
+
+//A newly created api token.  This is the only time the token itself is shown.
+#[derive(Serialize, Debug)]
+struct CreatedApiToken {
+    #[serde(flatten)]
+    api_token: dbif::ApiToken,
+    token: String,
 }
-impl Error for HydraError {}
 
 
 //Functions --------------------------------------------------------------------------------------------------
-pub async fn home(ctx: Context) -> Response {
+pub async fn home(_ctx: Context) -> Result<Response, ApiError> {
+    let doc = read_webroot_file("webroot/html/home.html")?;
+    Ok(hyper::Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-type", "text/html")
+        .body(hyper::Body::from(doc))
+        .unwrap())
+}
 
-    //Get query parameters
-    let _params: HashMap<String, String> = ctx.req.uri().query().map(|v| {
-        url::form_urlencoded::parse(v.as_bytes()).into_owned().collect()
-    }).unwrap_or_else(HashMap::new);
+pub async fn sent_page(_ctx: Context) -> Result<Response, ApiError> {
+    let doc = read_webroot_file("webroot/html/sent.html")?;
+    Ok(hyper::Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-type", "text/html")
+        .body(hyper::Body::from(doc))
+        .unwrap())
+}
 
-    //println!("** Params: {:#?}", _params);
+pub async fn login_page(ctx: Context) -> Result<Response, ApiError> {
+    //Nothing to log in to without a password
+    if ctx.state.auth.password_hash.is_none() {
+        return Ok(hyper::Response::builder()
+            .status(StatusCode::SEE_OTHER)
+            .header(header::LOCATION, "/")
+            .body(hyper::Body::empty())
+            .unwrap());
+    }
 
-    let doc = fs::read_to_string("webroot/html/home.html").expect("Something went wrong reading the file.");
-    return hyper::Response::builder()
+    let doc = read_webroot_file("webroot/html/login.html")?;
+    Ok(hyper::Response::builder()
         .status(StatusCode::OK)
-        .body(format!("{}", doc).into())
-        .unwrap();
+        .header("Content-type", "text/html")
+        .body(hyper::Body::from(doc))
+        .unwrap())
 }
 
-pub async fn pewmp3(_ctx: Context) -> Response {
-    let file = fs::read("webroot/extra/pew.mp3").expect("Something went wrong reading the file.");
-    return hyper::Response::builder()
+pub async fn pewmp3(_ctx: Context) -> Result<Response, ApiError> {
+    let file = read_webroot_file("webroot/extra/pew.mp3")?;
+    Ok(hyper::Response::builder()
         .status(StatusCode::OK)
         .header("Content-type", "audio/mpeg")
         .body(hyper::Body::from(file))
-        .unwrap();
+        .unwrap())
 }
 
-pub async fn favicon(_ctx: Context) -> Response {
-    let file = fs::read("webroot/extra/favicon.ico").expect("Something went wrong reading the file.");
-    return hyper::Response::builder()
+pub async fn favicon(_ctx: Context) -> Result<Response, ApiError> {
+    let file = read_webroot_file("webroot/extra/favicon.ico")?;
+    Ok(hyper::Response::builder()
         .status(StatusCode::OK)
         .header("Content-type", "image/x-icon")
         .body(hyper::Body::from(file))
-        .unwrap();
+        .unwrap())
 }
 
 //Serve a web asset by name from webroot subfolder according to it's requested type
-pub async fn asset(ctx: Context) -> Response {
+pub async fn asset(ctx: Context) -> Result<Response, ApiError> {
     //Get query parameters
-    let _params: HashMap<String, String> = ctx.req.uri().query().map(|v| {
-        url::form_urlencoded::parse(v.as_bytes()).into_owned().collect()
-    }).unwrap_or_else(HashMap::new);
-
-    println!("** Context: {:#?}", ctx);
-    println!("** Params: {:#?}", _params);
+    let params = query_params(&ctx);
 
     //Set up the response framework
-    let file_path;
-    let content_type;
-    let file_extension;
-    match ctx.path.as_str() {
-        "/html" => {
-            file_path = WEBROOT_PATH_HTML;
-            content_type = "text/html";
-            file_extension = "html";
-        },
-        "/image" => {
-            file_path = WEBROOT_PATH_IMAGE;
-            content_type = "image/png";
-            file_extension = "png";
-        },
-        "/style" => {
-            file_path = WEBROOT_PATH_STYLE;
-            content_type = "text/css";
-            file_extension = "css";
-        },
-        "/script" => {
-            file_path = WEBROOT_PATH_SCRIPT;
-            content_type = "text/javascript";
-            file_extension = "js";
-        },
+    let (file_path, content_type, file_extension) = match ctx.path.as_str() {
+        "/html" => (WEBROOT_PATH_HTML, "text/html", "html"),
+        "/image" => (WEBROOT_PATH_IMAGE, "image/png", "png"),
+        "/style" => (WEBROOT_PATH_STYLE, "text/css", "css"),
+        "/script" => (WEBROOT_PATH_SCRIPT, "text/javascript", "js"),
         _ => {
-            return hyper::Response::builder()
-                .status(StatusCode::from_u16(400).unwrap())
-                .body(format!("** Invalid asset type requested (ex. /images?name=filename.").into())
-                .unwrap();
+            return Err(ApiError::BadRequest("Invalid asset type requested (ex. /image?name=filename).".to_string()));
         },
     };
 
-    //Attempt to serve the file
-    if let Some(filename) = _params.get("name") {
-        let file_to_serve = format!("{}/{}.{}", file_path, filename, file_extension);
-        println!("** Serving file: [{}]", file_to_serve);
-        let file = fs::read(file_to_serve.as_str()).expect("Something went wrong reading the file.");
-        return hyper::Response::builder()
-            .status(StatusCode::OK)
-            .header("Content-type", content_type)
-            .body(hyper::Body::from(file))
-            .unwrap();
-    } else {
-        return hyper::Response::builder()
-            .status(StatusCode::from_u16(500).unwrap())
-            .body(format!("** No file specified.").into())
-            .unwrap();
+    //Attempt to serve the file.  Only plain names are allowed so nothing outside the webroot is reachable.
+    let filename = match params.get("name") {
+        Some(filename) => filename,
+        None => return Err(ApiError::BadRequest("No file specified.".to_string()))
+    };
+    if filename.is_empty() || filename.contains('/') || filename.contains('\\') || filename.contains("..") {
+        return Err(ApiError::BadRequest(format!("Invalid file name: [{}].", filename)));
     }
+
+    let file_to_serve = format!("{}/{}.{}", file_path, filename, file_extension);
+    println!("** Serving file: [{}]", file_to_serve);
+    let file = read_webroot_file(&file_to_serve)?;
+    Ok(hyper::Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-type", content_type)
+        .body(hyper::Body::from(file))
+        .unwrap())
 }
 
-pub async fn boosts(_ctx: Context) -> Response {
-    let default_boostcount: u64 = 50;
+pub async fn boosts(ctx: Context) -> Result<Response, ApiError> {
+    //Get query parameters
+    let params = query_params(&ctx);
+
+    //Which payments are wanted?  Boosts that haven't been archived unless told otherwise.
+    let mut filter = filter_params(&params)?;
+    if !params.contains_key("archived") {
+        filter.archived = Some(false);
+    }
+    let cursor = cursor_param(&params)?;
+    let boostcount = count_param(&params)?;
 
+    //Get the boosts from db for returning
+    let page = db_call(&ctx.db, move |db| db.get_boosts(&filter, cursor, boostcount)).await?;
+
+    Ok(stripped_json(&page))
+}
+
+
+//Full text search over the message, sender, app, podcast and episode of the boosts.  "q" takes FTS5
+//query syntax, so "exact phrases" and sender:name work.  Otherwise it works just like /boosts,
+//except that archived boosts are included unless asked not to be.
+pub async fn search(ctx: Context) -> Result<Response, ApiError> {
     //Get query parameters
-    let params: HashMap<String, String> = _ctx.req.uri().query().map(|v| {
-        url::form_urlencoded::parse(v.as_bytes()).into_owned().collect()
-    }).unwrap_or_else(HashMap::new);
-
-    //println!("Params: {:#?}", params);
-    println!("");
-
-    //Get the count parameter if one was given and convert to an integer
-    let boostcount: u64;
-    match params.get("count") {
-        Some(bcount) => {
-            boostcount = match bcount.parse::<u64>() {
-                Ok(boostcount) => {
-                    println!("** Supplied boostcount from call: [{}]", boostcount);
-                    boostcount
-                },
-                Err(_) => default_boostcount
-            };
-        },
-        None => {
-            println!("** No boostcount given.  Using: [{}]", default_boostcount);
-            boostcount = default_boostcount;
+    let params = query_params(&ctx);
+
+    let mut filter = filter_params(&params)?;
+    filter.query = match params.get("q").map(|q| q.trim()) {
+        Some(q) if !q.is_empty() => Some(q.to_string()),
+        _ => return Err(ApiError::BadRequest("Nothing to search for.  Use q=...".to_string()))
+    };
+    let cursor = cursor_param(&params)?;
+    let boostcount = count_param(&params)?;
+
+    let page = db_call(&ctx.db, move |db| db.get_boosts(&filter, cursor, boostcount)).await?;
+
+    Ok(stripped_json(&page))
+}
+
+
+//Earnings over the payments that match the filters: how many there were and what they added up to.
+//All categories of payment and archived boosts are counted unless the filters say otherwise.
+pub async fn stats(ctx: Context) -> Result<Response, ApiError> {
+    let filter = ledger_filter(&query_params(&ctx))?;
+
+    let totals = db_call(&ctx.db, move |db| db.get_totals(&filter)).await?;
+
+    Ok(stripped_json(&totals))
+}
+
+
+//Earnings grouped by day, week, month, podcast, episode, app, action or sender, ex. /stats/episode?action=stream
+//for the streaming income of each episode or /stats/sender?count=10 for the top senders
+pub async fn stats_grouped(ctx: Context) -> Result<Response, ApiError> {
+    let params = query_params(&ctx);
+
+    let grouping = match ctx.params.find("grouping") {
+        Some("day") => dbif::StatsGrouping::Day,
+        Some("week") => dbif::StatsGrouping::Week,
+        Some("month") => dbif::StatsGrouping::Month,
+        Some("podcast") => dbif::StatsGrouping::Podcast,
+        Some("episode") => dbif::StatsGrouping::Episode,
+        Some("app") => dbif::StatsGrouping::App,
+        Some("action") => dbif::StatsGrouping::Action,
+        Some("sender") => dbif::StatsGrouping::Sender,
+        other => {
+            return Err(ApiError::NotFound(format!("Can't group by: [{}].  Use day, week, month, podcast, episode, app, action or sender.",
+                                                  other.unwrap_or_default())));
         }
     };
+    let filter = ledger_filter(&params)?;
+    //Time periods are all returned unless a count is given
+    let period = matches!(grouping, dbif::StatsGrouping::Day | dbif::StatsGrouping::Week | dbif::StatsGrouping::Month);
+    let count = if period && !params.contains_key("count") {
+        None
+    } else {
+        Some(count_param(&params)?)
+    };
+
+    let stats = db_call(&ctx.db, move |db| db.get_stats(&filter, grouping, count)).await?;
+
+    Ok(stripped_json(&stats))
+}
+
 
-    //Was the "old" flag used?
-    let mut old = false;
-    match params.get("old") {
-        Some(_) => old = true,
-        None => { }
+//The biggest payments that match the filters
+pub async fn stats_largest(ctx: Context) -> Result<Response, ApiError> {
+    let params = query_params(&ctx);
+
+    let filter = ledger_filter(&params)?;
+    let count = count_param(&params)?;
+
+    let boosts = db_call(&ctx.db, move |db| db.get_largest_boosts(&filter, count)).await?;
+
+    Ok(stripped_json(&boosts))
+}
+
+
+//Download every payment that matches the filters for the books, as csv (the default) or jsonl.  The
+//rows are streamed out as they're read, so a large export doesn't sit in memory.
+pub async fn export(ctx: Context) -> Result<Response, ApiError> {
+    let params = query_params(&ctx);
+
+    let format = match params.get("format") {
+        None => export::ExportFormat::Csv,
+        Some(name) => match export::ExportFormat::from_name(name) {
+            Some(format) => format,
+            None => return Err(ApiError::BadRequest(format!("Invalid format: [{}].  Use csv or jsonl.", name)))
+        }
     };
+    let filter = ledger_filter(&params)?;
 
+    //The export reads the database, so it runs on the blocking pool and hands its chunks over to be
+    //sent.  The channel is small so it waits for a slow client rather than running ahead of it.
+    let (chunks, mut received) = mpsc::channel::<Vec<u8>>(EXPORT_CHUNK_BUFFER);
+    let (mut sender, body) = hyper::Body::channel();
+    let db = ctx.db.clone();
+    tokio::task::spawn_blocking(move || {
+        let result = export::export_boosts(&db, &filter, format, |chunk| {
+            chunks.blocking_send(chunk).map_err(|_| "The client went away.".into())
+        });
+        if let Err(e) = result {
+            eprintln!("** Export stopped: {}", e);
+        }
+    });
+    tokio::spawn(async move {
+        while let Some(chunk) = received.recv().await {
+            if sender.send_data(chunk.into()).await.is_err() {
+                return;
+            }
+        }
+    });
 
-    //Get the last known invoice index from the database
-    let mut last_index = match dbif::get_last_boost_index_from_db(&_ctx.database_file_path) {
-        Ok(index) => {
-            println!("** get_last_boost_index_from_db() -> [{}]", index);
-            index
-        },
-        Err(_) => 0
-    };
-    if last_index > boostcount {
-        last_index -= boostcount;
-    }
-
-    //Get the index url parameter if one was given and convert to an integer
-    //If one wasn't given, just use what we calculated above
-    let index: u64;
-    match params.get("index") {
-        Some(supplied_index) => {
-            index = match supplied_index.parse::<u64>() {
-                Ok(index) => {
-                    println!("** Supplied index from call: [{}]", index);
-                    index
-                },
-                Err(_) => last_index
-            };
-        },
-        None => {
-            println!("** No index given.  Using: [{}]", last_index);
-            index = last_index;
+    Ok(hyper::Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-type", format.content_type())
+        .header("Content-Disposition", format!("attachment; filename=\"boosts.{}\"", format.extension()))
+        .body(body)
+        .unwrap())
+}
+
+
+//Change the host's flags or note on a boost.  The body holds just the fields to change, ex.
+//{"read": true} or {"note": "Read on episode 42"}.  Returns the updated boost.
+pub async fn update_boost(mut ctx: Context) -> Result<Response, ApiError> {
+    let index = match ctx.params.find("index").map(|i| i.parse::<u64>()) {
+        Some(Ok(index)) => index,
+        _ => return Err(ApiError::BadRequest("Invalid boost index.".to_string()))
+    };
+    let Json(flags) = Json::<dbif::BoostFlags>::extract(&mut ctx).await?;
+
+    let boost = db_call(&ctx.db, move |db| {
+        if db.set_boost_flags(index, &flags)? {
+            db.get_boost(index)
+        } else {
+            Ok(None)
         }
+    }).await?;
+
+    match boost {
+        Some(boost) => Ok(stripped_json(&boost)),
+        None => Err(ApiError::NotFound(format!("No boost with index: [{}].", index)))
+    }
+}
+
+
+//Log in with the password, ex. {"password": "..."}.  A login session is started and handed back as
+//a cookie.
+pub async fn login(mut ctx: Context) -> Result<Response, ApiError> {
+    let password_hash = match ctx.state.auth.password_hash.clone() {
+        Some(password_hash) => password_hash,
+        None => return Err(ApiError::BadRequest("No password is set, so there's nothing to log in to.".to_string()))
     };
+    let Json(login) = Json::<LoginRequest>::extract(&mut ctx).await?;
 
+    //Checking the password is slow on purpose, so it goes on the blocking pool
+    let password_ok = tokio::task::spawn_blocking(move || auth::check_password(&login.password, &password_hash))
+        .await
+        .map_err(crate::Error::from)
+        .and_then(|checked| checked)?;
+    if !password_ok {
+        println!("** Failed login from: [{}]", ctx.state.remote_ip);
+        tokio::time::sleep(FAILED_LOGIN_DELAY).await;
+        return Err(ApiError::Unauthorized("Wrong password.".to_string()));
+    }
 
-    //Get the boosts from db for returning
-    match dbif::get_boosts_from_db(&_ctx.database_file_path, index, boostcount, old) {
-        Ok(boosts) => {
-            let json_doc_raw = serde_json::to_string(&boosts).unwrap();
-            let json_doc: String = strip::strip_tags(&json_doc_raw);
-
-            return hyper::Response::builder()
-                .status(StatusCode::OK)
-                .body(format!("{}", json_doc).into())
-                .unwrap();
+    let session = auth::new_secret();
+    let session_hash = auth::hash_secret(&session);
+    let created = auth::now();
+    let expires = created + auth::SESSION_LIFETIME;
+    db_call(&ctx.db, move |db| db.add_session(&session_hash, created, expires)).await?;
+
+    Ok(hyper::Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-type", "application/json")
+        .header(header::SET_COOKIE, auth::session_cookie_header(&session, ctx.state.auth.secure_cookies))
+        .body(serde_json::json!({ "expires": expires }).to_string().into())
+        .unwrap())
+}
+
+//End the login session the request carries, if any, and clear the cookie
+pub async fn logout(ctx: Context) -> Result<Response, ApiError> {
+    if let Some(session) = auth::session_cookie(&ctx.req) {
+        let session_hash = auth::hash_secret(&session);
+        db_call(&ctx.db, move |db| db.delete_session(&session_hash)).await?;
+    }
+
+    Ok(hyper::Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .header(header::SET_COOKIE, auth::cleared_cookie_header(ctx.state.auth.secure_cookies))
+        .body(hyper::Body::empty())
+        .unwrap())
+}
+
+
+//List the api tokens handed out to clients like overlays and bots
+pub async fn api_tokens(ctx: Context) -> Result<Json<Vec<dbif::ApiToken>>, ApiError> {
+    let api_tokens = db_call(&ctx.db, |db| db.get_api_tokens()).await?;
+    Ok(Json(api_tokens))
+}
+
+//Hand out a new read-only api token, ex. {"name": "OBS overlay"}
+pub async fn create_api_token(mut ctx: Context) -> Result<Response, ApiError> {
+    let Json(new_token) = Json::<NewApiToken>::extract(&mut ctx).await?;
+    let name = new_token.name.trim().to_string();
+    if name.is_empty() {
+        return Err(ApiError::BadRequest("Give the api token a name.".to_string()));
+    }
+
+    let token = auth::new_secret();
+    let token_hash = auth::hash_secret(&token);
+    let api_token = db_call(&ctx.db, move |db| db.add_api_token(&name, &token_hash, auth::now())).await?;
+
+    let mut response = Json(CreatedApiToken { api_token, token }).into_response();
+    *response.status_mut() = StatusCode::CREATED;
+    Ok(response)
+}
+
+//Revoke an api token by its id
+pub async fn delete_api_token(ctx: Context) -> Result<Response, ApiError> {
+    let id = match ctx.params.find("id").map(|i| i.parse::<u64>()) {
+        Some(Ok(id)) => id,
+        _ => return Err(ApiError::BadRequest("Invalid api token id.".to_string()))
+    };
+
+    if !db_call(&ctx.db, move |db| db.delete_api_token(id)).await? {
+        return Err(ApiError::NotFound(format!("No api token with id: [{}].", id)));
+    }
+
+    Ok(hyper::Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(hyper::Body::empty())
+        .unwrap())
+}
+
+
+//Send a boost back to whoever sent this one, using the reply address from their podcast record.
+//Returns the outgoing payment, whose state says whether it went through.
+pub async fn reply(mut ctx: Context) -> Result<Response, ApiError> {
+    let index = match ctx.params.find("index").map(|i| i.parse::<u64>()) {
+        Some(Ok(index)) => index,
+        _ => return Err(ApiError::BadRequest("Invalid boost index.".to_string()))
+    };
+    let Json(reply) = Json::<ReplyRequest>::extract(&mut ctx).await?;
+    let value_msat = match reply.sats.checked_mul(1000).and_then(|msat| i64::try_from(msat).ok()) {
+        Some(value_msat) if value_msat > 0 => value_msat,
+        _ => return Err(ApiError::BadRequest(format!("Invalid sats: [{}].", reply.sats)))
+    };
+
+    let boost = match db_call(&ctx.db, move |db| db.get_boost(index)).await? {
+        Some(boost) => boost,
+        None => return Err(ApiError::NotFound(format!("No boost with index: [{}].", index)))
+    };
+    if boost.reply_address.is_empty() {
+        return Err(ApiError::BadRequest(format!("Boost: [{}] has no reply address.", index)));
+    }
+    if payments::parse_pubkey(&boost.reply_address).is_none() {
+        return Err(ApiError::BadRequest(format!("Boost: [{}] has an invalid reply address: [{}].", index, boost.reply_address)));
+    }
+
+    let mut lightning = match ctx.state.lnd.client() {
+        Some(lightning) => lightning,
+        None => return Err(ApiError::Unavailable("Not connected to LND.".to_string()))
+    };
+
+    let sender = reply.sender.unwrap_or_else(|| boost.podcast.clone());
+    let podcast_record = tlv::OutgoingBoost::reply_to(&boost, value_msat as u64, &sender, &reply.message);
+    let payment = dbif::OutgoingPayment {
+        boost_index: Some(index),
+        value_msat,
+        recipient: boost.reply_address.clone(),
+        custom_key: boost.reply_custom_key,
+        custom_value: boost.reply_custom_value.clone(),
+        sender,
+        message: reply.message,
+        tlv: serde_json::to_string(&podcast_record).map_err(crate::Error::from)?,
+        ..Default::default()
+    };
+
+    let payment = payments::send_boost(&mut lightning, &ctx.db, payment).await?;
+    Ok(stripped_json(&payment))
+}
+
+//The boosts sent back in reply to a boost, oldest first
+pub async fn replies(ctx: Context) -> Result<Response, ApiError> {
+    let index = match ctx.params.find("index").map(|i| i.parse::<u64>()) {
+        Some(Ok(index)) => index,
+        _ => return Err(ApiError::BadRequest("Invalid boost index.".to_string()))
+    };
+
+    let replies = db_call(&ctx.db, move |db| db.get_replies(index)).await?;
+    Ok(stripped_json(&replies))
+}
+
+
+//The splits passed on from incoming payments, in the order they're paid
+pub async fn splits(ctx: Context) -> Result<Response, ApiError> {
+    let splits = db_call(&ctx.db, |db| db.get_splits()).await?;
+    Ok(stripped_json(&splits))
+}
+
+//Add a split, ex. {"name": "Co-host", "recipient": "03ae9f...", "percent": 10} or {..., "sats": 50}.
+//It applies to payments settled from now on.
+pub async fn create_split(mut ctx: Context) -> Result<Response, ApiError> {
+    let Json(mut split) = Json::<dbif::Split>::extract(&mut ctx).await?;
+    split.name = split.name.trim().to_string();
+    split.recipient = split.recipient.trim().to_string();
+    split.podcast = split.podcast.trim().to_string();
+
+    if payments::parse_pubkey(&split.recipient).is_none() {
+        return Err(ApiError::BadRequest(format!("Invalid recipient pubkey: [{}].", split.recipient)));
+    }
+    match (split.percent, split.sats) {
+        (Some(percent), None) if percent > 0.0 && percent <= 100.0 => {}
+        (None, Some(sats)) if sats > 0 && sats.checked_mul(1000).and_then(|msat| i64::try_from(msat).ok()).is_some() => {}
+        (Some(_), Some(_)) | (None, None) => {
+            return Err(ApiError::BadRequest("Give the split either a percent or a number of sats.".to_string()));
+        }
+        _ => return Err(ApiError::BadRequest("Invalid split amount.  Use a percent up to 100 or a positive number of sats.".to_string()))
+    }
+    if split.custom_key.is_none() && !split.custom_value.is_empty() {
+        return Err(ApiError::BadRequest("A custom value needs a custom key.".to_string()));
+    }
+    split.created = auth::now();
+
+    let split = db_call(&ctx.db, move |db| db.add_split(&split)).await?;
+    let mut response = stripped_json(&split);
+    *response.status_mut() = StatusCode::CREATED;
+    Ok(response)
+}
+
+//Remove a split by its id.  The payouts already made for it are kept.
+pub async fn delete_split(ctx: Context) -> Result<Response, ApiError> {
+    let id = match ctx.params.find("id").map(|i| i.parse::<u64>()) {
+        Some(Ok(id)) => id,
+        _ => return Err(ApiError::BadRequest("Invalid split id.".to_string()))
+    };
+
+    if !db_call(&ctx.db, move |db| db.delete_split(id)).await? {
+        return Err(ApiError::NotFound(format!("No split with id: [{}].", id)));
+    }
+
+    Ok(hyper::Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(hyper::Body::empty())
+        .unwrap())
+}
+
+//Every attempt at paying out a split, newest first.  Under /boosts/:index only the ones for that
+//boost.  "state" picks out the in_flight, succeeded, failed or skipped ones.
+pub async fn payouts(ctx: Context) -> Result<Response, ApiError> {
+    let index = match ctx.params.find("index").map(|i| i.parse::<u64>()) {
+        None => None,
+        Some(Ok(index)) => Some(index),
+        Some(Err(_)) => return Err(ApiError::BadRequest("Invalid boost index.".to_string()))
+    };
+
+    let params = query_params(&ctx);
+    let count = count_param(&params)?;
+    let state = match params.get("state").map(|state| state.as_str()) {
+        None => None,
+        Some("in_flight") => Some(dbif::OUTGOING_STATE_IN_FLIGHT),
+        Some("succeeded") => Some(dbif::OUTGOING_STATE_SUCCEEDED),
+        Some("failed") => Some(dbif::OUTGOING_STATE_FAILED),
+        Some("skipped") => Some(dbif::OUTGOING_STATE_SKIPPED),
+        Some(state) => return Err(ApiError::BadRequest(format!("Invalid state: [{}].  Use in_flight, succeeded, failed or skipped.", state)))
+    };
+
+    let payouts = db_call(&ctx.db, move |db| db.get_payouts(index, state, count)).await?;
+    Ok(stripped_json(&payouts))
+}
+
+//The podcast payments sent from the node, by us or any other app on it, newest first.  "action"
+//picks boosts, streams or all of them (the default), and "before" pages back from an index.
+pub async fn sent_boosts(ctx: Context) -> Result<Response, ApiError> {
+    let params = query_params(&ctx);
+
+    let action = match params.get("action") {
+        None => None,
+        Some(_) => action_param(&params)?,
+    };
+    let before = u64_param(&params, "before")?;
+    let count = count_param(&params)?;
+
+    let sent = db_call(&ctx.db, move |db| db.get_sent(action, before, count)).await?;
+    Ok(stripped_json(&sent))
+}
+
+//What went through to each podcast we support, biggest first.  Takes "from" and "to" like /stats.
+pub async fn sent_podcasts(ctx: Context) -> Result<Response, ApiError> {
+    let params = query_params(&ctx);
+
+    let since_time = time_param(&params, "from")?;
+    let until_time = time_param(&params, "to")?;
+
+    let totals = db_call(&ctx.db, move |db| db.get_sent_totals(since_time, until_time)).await?;
+    Ok(stripped_json(&totals))
+}
+
+//Push boosts to the client as server-sent events as they arrive.  A client that was disconnected can
+//pass the last index it saw as "since" (or let the browser send it as Last-Event-ID) and everything
+//after it is replayed from the database before the live boosts.
+pub async fn boosts_stream(ctx: Context) -> Result<Response, ApiError> {
+    //Get query parameters
+    let params = query_params(&ctx);
+
+    let action = action_param(&params)?;
+    let filter = dbif::BoostFilter {
+        action,
+        ..Default::default()
+    };
+
+    //Where to replay from, if anywhere
+    let last_event_id = ctx.req.headers()
+        .get("Last-Event-ID")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+    let since = match params.get("since").cloned().or(last_event_id) {
+        Some(since) => {
+            match since.trim().parse::<u64>() {
+                Ok(since) => Some(since),
+                Err(_) => return Err(ApiError::BadRequest(format!("Invalid since index: [{}].", since)))
+            }
+        },
+        None => None
+    };
+
+    //Listen before replaying so nothing that arrives in between is missed
+    let mut live_boosts = ctx.state.live_boosts.subscribe();
+    let (mut sender, body) = hyper::Body::channel();
+    let db = ctx.db.clone();
+
+    tokio::spawn(async move {
+        //Replay what was missed, remembering what was sent in case it also comes through live
+        let mut replayed: HashSet<u64> = HashSet::new();
+        if let Some(since) = since {
+            let mut cursor = dbif::Cursor::After(since);
+            loop {
+                let filter = filter.clone();
+                let page = match db_call(&db, move |db| db.get_boosts(&filter, cursor, STREAM_REPLAY_PAGE_SIZE)).await {
+                    Ok(page) => page,
+                    Err(e) => {
+                        eprintln!("** Error getting boosts to replay: {}.\n", e);
+                        return;
+                    }
+                };
+                //Pages come newest first, but the client should see them in the order they arrived
+                for boost in page.boosts.iter().rev() {
+                    replayed.insert(boost.index);
+                    if sender.send_data(boost_event(boost).into()).await.is_err() {
+                        return;
+                    }
+                }
+                match page.prev {
+                    Some(prev) => cursor = dbif::Cursor::After(prev),
+                    None => break
+                }
+            }
         }
-        Err(e) => {
-            eprintln!("** Error getting boosts: {}.\n", e);
-            return hyper::Response::builder()
-                .status(StatusCode::from_u16(500).unwrap())
-                .body(format!("** Error getting boosts.").into())
-                .unwrap();
+
+        //Then pass along the live boosts, with a comment now and then so we notice when the client leaves
+        let mut keepalive = tokio::time::interval(STREAM_KEEPALIVE_INTERVAL);
+        loop {
+            let event = tokio::select! {
+                received = live_boosts.recv() => {
+                    match received {
+                        Ok(boost) => {
+                            if replayed.contains(&boost.index) || action.is_some_and(|a| a != boost.action) {
+                                continue;
+                            }
+                            boost_event(&boost)
+                        }
+                        //Too far behind to catch up from the channel.  Ending the stream makes the
+                        //browser reconnect with its Last-Event-ID and replay from the database.
+                        Err(RecvError::Lagged(_)) => return,
+                        Err(RecvError::Closed) => return,
+                    }
+                }
+                _ = keepalive.tick() => ": keepalive\n\n".to_string(),
+            };
+            if sender.send_data(event.into()).await.is_err() {
+                return;
+            }
         }
+    });
+
+    Ok(hyper::Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-type", "text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .body(body)
+        .unwrap())
+}
+
+
+//Report the state of the connection to LND
+pub async fn status(ctx: Context) -> Json<lightning::ConnectionStatus> {
+    Json(ctx.state.lnd.status())
+}
+
+//The node's alias, pubkey, sync state, channels and balances, with a warning when it's running low on
+//inbound liquidity
+pub async fn node(ctx: Context) -> Result<Response, ApiError> {
+    let mut client = match ctx.state.lnd.client() {
+        Some(client) => client,
+        None => return Err(ApiError::Unavailable("Not connected to LND.".to_string()))
+    };
+
+    match lightning::NodeInfo::fetch(&mut client, ctx.state.inbound_warning_msat).await {
+        Ok(node_info) => Ok(stripped_json(&node_info)),
+        Err(status) => Err(ApiError::Unavailable(format!("Could not get node info from LND: {}", status.message()))),
+    }
+}
+
+
+//Read the "action" query parameter into the category of payments it asks for.  None means all of them.
+fn action_param(params: &HashMap<String, String>) -> Result<Option<u8>, ApiError> {
+    match params.get("action").map(|a| a.as_str()) {
+        None => Ok(Some(dbif::ACTION_BOOST)),
+        Some("all") => Ok(None),
+        Some(name) => match dbif::action_from_name(name) {
+            Some(action) => Ok(Some(action)),
+            None => Err(ApiError::BadRequest(format!("Invalid action: [{}].  Use boost, stream, invoice, keysend, unknown or all.", name))),
+        },
+    }
+}
+
+//Read the filters shared by the boost listing endpoints from the query parameters
+fn filter_params(params: &HashMap<String, String>) -> Result<dbif::BoostFilter, ApiError> {
+    Ok(dbif::BoostFilter {
+        action: action_param(params)?,
+        read: match bool_param(params, "unread")? {
+            Some(unread) => Some(!unread),
+            None => bool_param(params, "read")?,
+        },
+        starred: bool_param(params, "starred")?,
+        archived: match params.get("archived").map(|a| a.as_str()) {
+            Some("all") => None,
+            _ => bool_param(params, "archived")?,
+        },
+        query: None,
+        since_time: time_param(params, "from")?,
+        until_time: time_param(params, "to")?,
+        podcast: params.get("podcast").cloned(),
+        feed_id: u64_param(params, "feed_id")?,
+        episode: params.get("episode").cloned(),
+        app: params.get("app").cloned(),
+        sender: params.get("sender").cloned(),
+        min_msat: i64_param(params, "min_sats")?.map(|sats| sats.saturating_mul(1000)),
+        max_msat: i64_param(params, "max_sats")?.map(|sats| sats.saturating_mul(1000)),
+    })
+}
+
+//The filters for the endpoints that account for payments (stats and export).  Unlike the listings
+//these count every category of payment, archived or not, unless asked not to.
+fn ledger_filter(params: &HashMap<String, String>) -> Result<dbif::BoostFilter, ApiError> {
+    let mut filter = filter_params(params)?;
+    if !params.contains_key("action") {
+        filter.action = None;
+    }
+
+    Ok(filter)
+}
+
+//Read which page is wanted from the "before" or "after" query parameters.  The newest page if neither.
+fn cursor_param(params: &HashMap<String, String>) -> Result<dbif::Cursor, ApiError> {
+    match (u64_param(params, "before")?, u64_param(params, "after")?) {
+        (None, None) => Ok(dbif::Cursor::Newest),
+        (Some(before), None) => Ok(dbif::Cursor::Before(before)),
+        (None, Some(after)) => Ok(dbif::Cursor::After(after)),
+        (Some(_), Some(_)) => Err(ApiError::BadRequest("Use either before or after, not both.".to_string())),
+    }
+}
+
+//Read how many boosts are wanted per page
+fn count_param(params: &HashMap<String, String>) -> Result<u64, ApiError> {
+    match u64_param(params, "count")? {
+        None => Ok(DEFAULT_PAGE_SIZE),
+        Some(count) if (1..=MAX_PAGE_SIZE).contains(&count) => Ok(count),
+        Some(count) => Err(ApiError::BadRequest(format!("Invalid count: [{}].  Use 1 to {}.", count, MAX_PAGE_SIZE))),
+    }
+}
+
+//Read a whole number query parameter, if it was given
+fn u64_param(params: &HashMap<String, String>, name: &str) -> Result<Option<u64>, ApiError> {
+    match params.get(name) {
+        None => Ok(None),
+        Some(value) => match value.parse::<u64>() {
+            Ok(value) => Ok(Some(value)),
+            Err(_) => Err(ApiError::BadRequest(format!("Invalid {}: [{}].", name, value))),
+        },
+    }
+}
+
+fn i64_param(params: &HashMap<String, String>, name: &str) -> Result<Option<i64>, ApiError> {
+    match params.get(name) {
+        None => Ok(None),
+        Some(value) => match value.parse::<i64>() {
+            Ok(value) => Ok(Some(value)),
+            Err(_) => Err(ApiError::BadRequest(format!("Invalid {}: [{}].", name, value))),
+        },
     }
+}
+
+//Read a point in time given as unix seconds or a date (ex. 2021-11-30), if it was given
+fn time_param(params: &HashMap<String, String>, name: &str) -> Result<Option<i64>, ApiError> {
+    match params.get(name) {
+        None => Ok(None),
+        Some(value) => match export::parse_time(value) {
+            Some(value) => Ok(Some(value)),
+            None => Err(ApiError::BadRequest(format!("Invalid {}: [{}].  Use unix seconds or YYYY-MM-DD.", name, value))),
+        },
+    }
+}
 
-}
\ No newline at end of file
+//Read a true/false query parameter, if it was given
+fn bool_param(params: &HashMap<String, String>, name: &str) -> Result<Option<bool>, ApiError> {
+    match params.get(name).map(|v| v.as_str()) {
+        None => Ok(None),
+        Some("true") | Some("1") => Ok(Some(true)),
+        Some("false") | Some("0") => Ok(Some(false)),
+        Some(other) => Err(ApiError::BadRequest(format!("Invalid {}: [{}].  Use true or false.", name, other))),
+    }
+}
+
+//The query string parameters of a request
+fn query_params(ctx: &Context) -> HashMap<String, String> {
+    ctx.req.uri().query().map(|v| {
+        url::form_urlencoded::parse(v.as_bytes()).into_owned().collect()
+    }).unwrap_or_default()
+}
+
+//Read a file to serve from the webroot.  A missing file is a 404 rather than a crash.
+fn read_webroot_file(path: &str) -> Result<Vec<u8>, ApiError> {
+    fs::read(path).map_err(|e| {
+        eprintln!("** Could not read file: [{}]: {}", path, e);
+        ApiError::NotFound(format!("Not found: [{}].", path))
+    })
+}
+
+//Boosts as a JSON response.  The web UI inserts the text fields as html, so any tags are stripped.
+fn stripped_json<T: Serialize>(value: &T) -> Response {
+    let json_doc_raw = serde_json::to_string(value).unwrap();
+    let json_doc: String = strip::strip_tags(&json_doc_raw);
+
+    json_response(StatusCode::OK, json_doc)
+}
+
+//Format a boost as a server-sent event, using its index as the event id
+fn boost_event(boost: &dbif::BoostRecord) -> String {
+    let json_doc_raw = serde_json::to_string(boost).unwrap();
+    let json_doc: String = strip::strip_tags(&json_doc_raw);
+
+    format!("id: {}\nevent: boost\ndata: {}\n\n", boost.index, json_doc)
+}
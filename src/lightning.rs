@@ -0,0 +1,442 @@
+use crate::{Config, HELIPAD_CONFIG_FILE};
+use lnd::lnrpc::lnrpc::{Amount, ChannelBalanceResponse, GetInfoResponse};
+use serde::Serialize;
+use std::fs;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+
+//Constants --------------------------------------------------------------------------------------------------
+const LND_STANDARD_GRPC_URL: &str = "https://127.0.0.1:10009";
+const LND_REST_URL_SCHEME: &str = "rest://";
+const LND_STANDARD_MACAROON_LOCATION: &str = "/lnd/data/chain/bitcoin/mainnet/admin.macaroon";
+const LND_STANDARD_TLSCERT_LOCATION: &str = "/lnd/tls.cert";
+const LND_RECONNECT_MIN_DELAY: Duration = Duration::from_secs(1);
+const LND_RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+pub const INBOUND_WARNING_STANDARD_SATS: u64 = 50_000;
+
+
+//Structs and Enums ------------------------------------------------------------------------------------------
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Reconnecting,
+    AuthFailed,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ConnectionStatus {
+    pub state: ConnectionState,
+    pub since: u64,
+    pub last_error: Option<String>,
+}
+
+//Whether we talk to LND over grpc or through its REST gateway.  REST is for nodes whose grpc port
+//can't be reached, like behind a proxy that only passes http/1.1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LndTransport {
+    Grpc,
+    Rest,
+}
+
+//Where to find the LND node and the credentials needed to talk to it
+#[derive(Clone, Debug)]
+pub struct LndConfig {
+    pub node_address: String,
+    pub macaroon_path: String,
+    pub cert_path: String,
+    pub transport: LndTransport,
+}
+
+#[derive(Debug)]
+struct ConnectionInner {
+    status: ConnectionStatus,
+    client: Option<lnd::Lnd>,
+    delay: Duration,
+}
+
+//Supervises the connection to LND.  Clones share the same underlying connection and status, so
+//one can be handed to the web server while the poller owns another.
+#[derive(Clone, Debug)]
+pub struct LndConnection {
+    config: LndConfig,
+    inner: Arc<RwLock<ConnectionInner>>,
+}
+
+
+//Who the node is, whether it has caught up, and where the funds in its channels sit
+#[derive(Clone, Debug, Serialize)]
+pub struct NodeInfo {
+    pub alias: String,
+    pub pubkey: String,
+    pub version: String,
+    pub color: String,
+    pub synced_to_chain: bool,
+    pub synced_to_graph: bool,
+    pub block_height: u32,
+    pub peers: u32,
+    pub channels: ChannelCounts,
+    pub balance: ChannelBalances,
+    pub inbound: InboundLiquidity,
+    //Anything the host should know about, like running low on inbound
+    pub warnings: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ChannelCounts {
+    pub active: u32,
+    pub inactive: u32,
+    pub pending: u32,
+}
+
+//What's ours (local) and what's our peers' (remote) in the node's channels
+#[derive(Clone, Debug, Serialize)]
+pub struct ChannelBalances {
+    pub local_msat: u64,
+    pub remote_msat: u64,
+    pub unsettled_local_msat: u64,
+    pub unsettled_remote_msat: u64,
+    pub pending_open_local_msat: u64,
+    pub pending_open_remote_msat: u64,
+}
+
+//How much more the node can receive.  Once that runs out boosts stop arriving, and nothing says so.
+#[derive(Clone, Debug, Serialize)]
+pub struct InboundLiquidity {
+    pub msat: u64,
+    pub warning_msat: u64,
+    pub low: bool,
+}
+
+
+//Functions --------------------------------------------------------------------------------------------------
+impl LndConfig {
+    //Work out the node address and credential locations.  The environment is tried first, then the
+    //config file, then the current directory.
+    pub fn discover(server_config: &Config) -> LndConfig {
+        println!("\nDiscovering macaroon file path...");
+        let macaroon_path;
+        if let Ok(env_macaroon_path) = std::env::var("LND_ADMINMACAROON") {
+            macaroon_path = env_macaroon_path;
+            println!(" - Trying environment var(LND_ADMINMACAROON): [{}]", macaroon_path);
+        } else if let Some(config_macaroon_path) = &server_config.macaroon {
+            macaroon_path = config_macaroon_path.clone();
+            println!(" - Trying config file({}): [{}]", HELIPAD_CONFIG_FILE, macaroon_path);
+        } else {
+            macaroon_path = "admin.macaroon".to_string();
+            println!(" - Trying current directory: [{}]", macaroon_path);
+        }
+
+        println!("\nDiscovering certificate file path...");
+        let cert_path;
+        if let Ok(env_cert_path) = std::env::var("LND_TLSCERT") {
+            cert_path = env_cert_path;
+            println!(" - Trying environment var(LND_TLSCERT): [{}]", cert_path);
+        } else if let Some(config_cert_path) = &server_config.cert {
+            cert_path = config_cert_path.clone();
+            println!(" - Trying config file({}): [{}]", HELIPAD_CONFIG_FILE, cert_path);
+        } else {
+            cert_path = "tls.cert".to_string();
+            println!(" - Trying current directory: [{}]", cert_path);
+        }
+
+        println!("\nDiscovering LND node address...");
+        let node_address;
+        if let Ok(env_lnd_url) = std::env::var("LND_URL") {
+            node_address = match env_lnd_url.starts_with(LND_REST_URL_SCHEME) {
+                true => env_lnd_url,
+                false => "https://".to_owned() + env_lnd_url.as_str(),
+            };
+            println!(" - Trying environment var(LND_URL): [{}]", node_address);
+        } else if let Some(config_lnd_url) = &server_config.lnd_url {
+            node_address = config_lnd_url.clone();
+            println!(" - Trying config file({}): [{}]", HELIPAD_CONFIG_FILE, node_address);
+        } else {
+            node_address = String::from(LND_STANDARD_GRPC_URL);
+            println!(" - Trying localhost default: [{}].", node_address);
+        }
+
+        //A rest:// address picks the REST gateway, which is still https underneath
+        let (node_address, url_transport) = match node_address.strip_prefix(LND_REST_URL_SCHEME) {
+            Some(address) => ("https://".to_owned() + address, Some(LndTransport::Rest)),
+            None => (node_address, None),
+        };
+
+        println!("\nDiscovering LND transport...");
+        let transport;
+        if let Ok(env_lnd_transport) = std::env::var("LND_TRANSPORT") {
+            transport = LndTransport::parse(&env_lnd_transport).unwrap_or_else(|| {
+                eprintln!("Invalid LND_TRANSPORT: [{}].  Use \"grpc\" or \"rest\".", env_lnd_transport);
+                std::process::exit(1);
+            });
+            println!(" - Using environment var(LND_TRANSPORT): [{:?}]", transport);
+        } else if let Some(config_lnd_transport) = &server_config.lnd_transport {
+            transport = LndTransport::parse(config_lnd_transport).unwrap_or_else(|| {
+                eprintln!("Invalid lnd_transport in {}: [{}].  Use \"grpc\" or \"rest\".", HELIPAD_CONFIG_FILE, config_lnd_transport);
+                std::process::exit(1);
+            });
+            println!(" - Using config file({}): [{:?}]", HELIPAD_CONFIG_FILE, transport);
+        } else if let Some(url_transport) = url_transport {
+            transport = url_transport;
+            println!(" - Using the {} address scheme: [{:?}]", LND_REST_URL_SCHEME, transport);
+        } else {
+            transport = LndTransport::Grpc;
+            println!(" - Nothing else found. Using default: [{:?}]", transport);
+        }
+
+        LndConfig {
+            node_address,
+            macaroon_path,
+            cert_path,
+            transport,
+        }
+    }
+}
+
+impl LndTransport {
+    pub fn parse(transport: &str) -> Option<LndTransport> {
+        match transport.trim().to_lowercase().as_str() {
+            "grpc" => Some(LndTransport::Grpc),
+            "rest" => Some(LndTransport::Rest),
+            _ => None,
+        }
+    }
+}
+
+impl LndConnection {
+    pub fn new(config: LndConfig) -> LndConnection {
+        LndConnection {
+            config,
+            inner: Arc::new(RwLock::new(ConnectionInner {
+                status: ConnectionStatus {
+                    state: ConnectionState::Connecting,
+                    since: now(),
+                    last_error: None,
+                },
+                client: None,
+                delay: Duration::from_secs(0),
+            })),
+        }
+    }
+
+    //A snapshot of the current connection state
+    pub fn status(&self) -> ConnectionStatus {
+        self.inner.read().unwrap().status.clone()
+    }
+
+    //The live LND client, if we are currently connected
+    pub fn client(&self) -> Option<lnd::Lnd> {
+        self.inner.read().unwrap().client.clone()
+    }
+
+    //Connect to LND, retrying with exponential backoff until it works.  The macaroon and certificate
+    //are read from disk on every attempt since LND may have replaced them while restarting.
+    pub async fn connect(&self) -> lnd::Lnd {
+        loop {
+            let delay = self.inner.read().unwrap().delay;
+            if !delay.is_zero() {
+                println!("Connecting to LND in {} seconds...", delay.as_secs());
+                tokio::time::sleep(delay).await;
+            }
+
+            match self.try_connect().await {
+                Ok(lightning) => {
+                    println!("Connected to LND at: [{}]", self.config.node_address);
+                    let mut inner = self.inner.write().unwrap();
+                    inner.client = Some(lightning.clone());
+                    inner.delay = Duration::from_secs(0);
+                    inner.status = ConnectionStatus {
+                        state: ConnectionState::Connected,
+                        since: now(),
+                        last_error: None,
+                    };
+                    return lightning;
+                }
+                Err((state, error)) => {
+                    eprintln!("Could not connect to LND: {}", error);
+                    self.set_failed(state, error);
+                }
+            }
+        }
+    }
+
+    //Try to connect to LND just once, for commands that should report a problem rather than wait it out
+    pub async fn connect_once(&self) -> Result<lnd::Lnd, String> {
+        match self.try_connect().await {
+            Ok(lightning) => {
+                println!("Connected to LND at: [{}]", self.config.node_address);
+                Ok(lightning)
+            }
+            Err((state, error)) => {
+                self.set_failed(state, error.clone());
+                Err(format!("Could not connect to LND at: [{}]: {}", self.config.node_address, error))
+            }
+        }
+    }
+
+    //Report that a call on the current client failed so the next connect() starts over
+    pub fn disconnected(&self, status: &lnd::Status) {
+        eprintln!("Lost connection to LND: {}", status);
+        self.set_failed(state_for_status(status), status.to_string());
+    }
+
+    async fn try_connect(&self) -> Result<lnd::Lnd, (ConnectionState, String)> {
+        let macaroon = read_credential(&self.config.macaroon_path, LND_STANDARD_MACAROON_LOCATION)
+            .map_err(|e| (ConnectionState::Reconnecting, format!("Cannot find a valid admin.macaroon file: {}", e)))?;
+        let cert = read_credential(&self.config.cert_path, LND_STANDARD_TLSCERT_LOCATION)
+            .map_err(|e| (ConnectionState::Reconnecting, format!("Cannot find a valid tls.cert file: {}", e)))?;
+
+        let mut lightning = match self.config.transport {
+            LndTransport::Grpc => lnd::Lnd::connect_with_macaroon(self.config.node_address.clone(), &cert, &macaroon).await,
+            LndTransport::Rest => lnd::Lnd::connect_rest(&self.config.node_address, &cert, &macaroon),
+        }
+        .map_err(|e| (ConnectionState::Reconnecting, format!("{}", e)))?;
+
+        //Connecting doesn't check the macaroon, so make a cheap call to be sure we're let in
+        lightning.get_info()
+            .await
+            .map_err(|status| (state_for_status(&status), status.to_string()))?;
+
+        Ok(lightning)
+    }
+
+    fn set_failed(&self, state: ConnectionState, error: String) {
+        let mut inner = self.inner.write().unwrap();
+        inner.client = None;
+        inner.delay = (inner.delay * 2).clamp(LND_RECONNECT_MIN_DELAY, LND_RECONNECT_MAX_DELAY);
+        if inner.status.state != state {
+            inner.status.state = state;
+            inner.status.since = now();
+        }
+        inner.status.last_error = Some(error);
+    }
+}
+
+impl NodeInfo {
+    //Ask the node about itself and its channels.  Inbound below inbound_warning_msat is flagged, unless
+    //that's zero.
+    pub async fn fetch(lightning: &mut lnd::Lnd, inbound_warning_msat: u64) -> Result<NodeInfo, lnd::Status> {
+        let info = lightning.get_info().await?;
+        let balance = lightning.channel_balance().await?;
+
+        Ok(NodeInfo::new(info, balance, inbound_warning_msat))
+    }
+
+    fn new(info: GetInfoResponse, balance: ChannelBalanceResponse, inbound_warning_msat: u64) -> NodeInfo {
+        let msat = |amount: Option<Amount>| amount.map(|amount| amount.msat).unwrap_or(0);
+        let balance = ChannelBalances {
+            local_msat: msat(balance.local_balance),
+            remote_msat: msat(balance.remote_balance),
+            unsettled_local_msat: msat(balance.unsettled_local_balance),
+            unsettled_remote_msat: msat(balance.unsettled_remote_balance),
+            pending_open_local_msat: msat(balance.pending_open_local_balance),
+            pending_open_remote_msat: msat(balance.pending_open_remote_balance),
+        };
+        let inbound = InboundLiquidity {
+            msat: balance.remote_msat,
+            warning_msat: inbound_warning_msat,
+            low: balance.remote_msat < inbound_warning_msat,
+        };
+
+        let mut warnings = Vec::new();
+        if inbound.low {
+            warnings.push(format!("Inbound liquidity is low: the channels can receive {} sats in total, below the warning \
+                                   threshold of {} sats.  Larger payments may fail, since each has to fit through the \
+                                   inbound of the channels on its route.",
+                                  inbound.msat / 1000, inbound.warning_msat / 1000));
+        }
+        if info.num_active_channels == 0 {
+            warnings.push("The node has no active channels, so it can't receive anything.".to_string());
+        }
+        if !info.synced_to_chain {
+            warnings.push("The node hasn't caught up with the chain yet.".to_string());
+        }
+
+        NodeInfo {
+            alias: info.alias,
+            pubkey: info.identity_pubkey,
+            version: info.version,
+            color: info.color,
+            synced_to_chain: info.synced_to_chain,
+            synced_to_graph: info.synced_to_graph,
+            block_height: info.block_height,
+            peers: info.num_peers,
+            channels: ChannelCounts {
+                active: info.num_active_channels,
+                inactive: info.num_inactive_channels,
+                pending: info.num_pending_channels,
+            },
+            balance,
+            inbound,
+            warnings,
+        }
+    }
+}
+
+//Read a credential file, falling back to its standard LND location
+fn read_credential(path: &str, fallback: &str) -> Result<Vec<u8>, std::io::Error> {
+    match fs::read(path) {
+        Ok(content) => Ok(content),
+        Err(_) => fs::read(fallback),
+    }
+}
+
+fn state_for_status(status: &lnd::Status) -> ConnectionState {
+    match status.code() {
+        lnd::Code::Unauthenticated | lnd::Code::PermissionDenied => ConnectionState::AuthFailed,
+        _ => ConnectionState::Reconnecting,
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+
+//Tests ------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(num_active_channels: u32, remote_msat: u64) -> (GetInfoResponse, ChannelBalanceResponse) {
+        let info = GetInfoResponse {
+            alias: "helipad".to_string(),
+            num_active_channels,
+            synced_to_chain: true,
+            ..Default::default()
+        };
+        let balance = ChannelBalanceResponse {
+            local_balance: Some(Amount { sat: 2_000, msat: 2_000_000 }),
+            remote_balance: Some(Amount { sat: remote_msat / 1000, msat: remote_msat }),
+            ..Default::default()
+        };
+        (info, balance)
+    }
+
+    #[test]
+    fn low_inbound_is_a_warning() {
+        let (info, balance) = node(2, 30_000_000);
+        let node_info = NodeInfo::new(info, balance, 50_000_000);
+
+        assert_eq!(node_info.balance.local_msat, 2_000_000);
+        assert_eq!(node_info.inbound.msat, 30_000_000);
+        assert!(node_info.inbound.low);
+        assert_eq!(node_info.warnings.len(), 1);
+        assert!(node_info.warnings[0].contains("30000 sats"));
+    }
+
+    #[test]
+    fn enough_inbound_or_no_threshold() {
+        let (info, balance) = node(2, 80_000_000);
+        let node_info = NodeInfo::new(info, balance, 50_000_000);
+        assert!(!node_info.inbound.low);
+        assert!(node_info.warnings.is_empty());
+
+        let (info, balance) = node(0, 0);
+        let node_info = NodeInfo::new(info, balance, 0);
+        assert!(!node_info.inbound.low);
+        assert_eq!(node_info.warnings, vec!["The node has no active channels, so it can't receive anything.".to_string()]);
+    }
+}
@@ -6,15 +6,16 @@ use hyper::{
     Body, Request, Server,
 };
 use route_recognizer::Params;
-use router::Router;
+use router::{Access, Router};
 use std::sync::Arc;
+use tokio::sync::broadcast;
 use hyper::server::conn::AddrStream;
-use std::fs;
+use std::convert::TryFrom;
 use std::env;
+use std::net::SocketAddr;
+use std::path::Path;
 use drop_root::set_user_group;
-use lnd;
-use serde::{Deserialize, Deserializer};
-use serde_json::Value;
+use lnd::lnrpc::lnrpc::invoice::InvoiceState;
 // use hyper::http::Request;
 
 #[macro_use]
@@ -24,8 +25,15 @@ extern crate configure_me;
 
 //Globals ----------------------------------------------------------------------------------------------------
 //------------------------------------------------------------------------------------------------------------
+mod auth;
+mod commands;
+mod export;
 mod handler;
+mod lightning;
+mod payments;
 mod router;
+mod tls;
+mod tlv;
 
 type Response = hyper::Response<hyper::Body>;
 type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
@@ -33,9 +41,18 @@ type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 const HELIPAD_CONFIG_FILE: &str = "./helipad.conf";
 const HELIPAD_DATABASE_DIR: &str = "database.db";
 const HELIPAD_STANDARD_PORT: &str = "2112";
-const LND_STANDARD_GRPC_URL: &str = "https://127.0.0.1:10009";
-const LND_STANDARD_MACAROON_LOCATION: &str = "/lnd/data/chain/bitcoin/mainnet/admin.macaroon";
-const LND_STANDARD_TLSCERT_LOCATION: &str = "/lnd/tls.cert";
+const HELIPAD_STANDARD_LISTEN_HOST: &str = "0.0.0.0";
+const LND_INVOICE_PAGE_SIZE: u64 = 500;
+const LIVE_BOOSTS_CHANNEL_SIZE: usize = 100;
+const HELIPAD_TLS_CERT_FILE: &str = "tls.cert";
+const HELIPAD_TLS_KEY_FILE: &str = "tls.key";
+//How often failed split payouts are tried again
+const SPLIT_RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+const SPLIT_RETRY_BATCH_SIZE: u64 = 100;
+//How often the node's payment list is checked for payments sent by other apps
+const PAYMENT_SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+//How often the node's inbound liquidity is checked
+const INBOUND_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10 * 60);
 
 //Structs ----------------------------------------------------------------------------------------------------
 //------------------------------------------------------------------------------------------------------------
@@ -43,6 +60,10 @@ const LND_STANDARD_TLSCERT_LOCATION: &str = "/lnd/tls.cert";
 pub struct AppState {
     pub state_thing: String,
     pub remote_ip: String,
+    pub lnd: lightning::LndConnection,
+    pub live_boosts: broadcast::Sender<dbif::BoostRecord>,
+    pub auth: auth::AuthConfig,
+    pub inbound_warning_msat: u64,
 }
 
 #[derive(Clone, Debug)]
@@ -59,93 +80,14 @@ pub struct Context {
     pub req: Request<Body>,
     pub path: String,
     pub params: Params,
-    pub database_file_path: String,
+    pub db: dbif::Database,
     body_bytes: Option<hyper::body::Bytes>,
 }
 
-#[allow(dead_code)]
-#[derive(Deserialize, Debug)]
-#[allow(non_snake_case)]
-struct RawBoost {
-    #[serde(default="d_action")]
-    action: Option<String>,
-    #[serde(default="d_blank")]
-    app_name: Option<String>,
-    #[serde(default="d_blank")]
-    app_version: Option<String>,
-    #[serde(default="d_blank")]
-    boost_link: Option<String>,
-    #[serde(default="d_blank")]
-    message: Option<String>,
-    #[serde(default="d_blank")]
-    name: Option<String>,
-    #[serde(default="d_blank")]
-    pubkey: Option<String>,
-    #[serde(default="d_blank")]
-    sender_key: Option<String>,
-    #[serde(default="d_blank")]
-    sender_name: Option<String>,
-    #[serde(default="d_blank")]
-    sender_id: Option<String>,
-    #[serde(default="d_blank")]
-    sig_fields: Option<String>,
-    #[serde(default="d_blank")]
-    signature: Option<String>,
-    #[serde(default="d_blank")]
-    speed: Option<String>,
-    #[serde(default="d_blank")]
-    uuid: Option<String>,
-    #[serde(default="d_blank")]
-    podcast: Option<String>,
-    #[serde(default="d_zero", deserialize_with="de_optional_string_or_number")]
-    feedID: Option<u64>,
-    #[serde(default="d_blank")]
-    guid: Option<String>,
-    #[serde(default="d_blank")]
-    url: Option<String>,
-    #[serde(default="d_blank")]
-    episode: Option<String>,
-    #[serde(default="d_zero", deserialize_with="de_optional_string_or_number")]
-    itemID: Option<u64>,
-    #[serde(default="d_blank")]
-    episode_guid: Option<String>,
-    #[serde(default="d_blank")]
-    time: Option<String>,
-    #[serde(default="d_zero", deserialize_with="de_optional_string_or_number")]
-    ts: Option<u64>,
-    #[serde(default="d_zero", deserialize_with="de_optional_string_or_number")]
-    value_msat: Option<u64>,
-    #[serde(default="d_zero", deserialize_with="de_optional_string_or_number")]
-    value_msat_total: Option<u64>,
-}
-
 
 
 //Traits------------------------------------------------------------------------------------------------------
 //------------------------------------------------------------------------------------------------------------
-fn d_action() -> Option<String> {
-    Some("stream".to_string())
-}
-fn d_blank() -> Option<String> {
-    None
-}
-fn d_zero() -> Option<u64> {
-    None
-}
-
-fn de_optional_string_or_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
-    Ok(match Value::deserialize(deserializer)? {
-        Value::String(s) => {
-            if s.is_empty() {
-                return Ok(None)
-            }
-            Some(s.parse().unwrap())
-        },
-        Value::Number(num) => Some(num.as_u64().unwrap()),
-        _ => Some(0)
-    })
-}
-
 //Configure_me
 include_config!();
 
@@ -169,7 +111,31 @@ async fn main() {
     };
 
     //Bring in the configuration info
-    let (server_config, _remaining_args) = Config::including_optional_config_files(&[HELIPAD_CONFIG_FILE]).unwrap_or_exit();
+    let (server_config, remaining_args) = Config::including_optional_config_files(&[HELIPAD_CONFIG_FILE]).unwrap_or_exit();
+
+    //Was a subcommand given?
+    let remaining_args: Vec<String> = remaining_args.map(|arg| arg.to_string_lossy().into_owned()).collect();
+    let command = match commands::Command::from_args(&remaining_args) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, commands::usage());
+            std::process::exit(1);
+        }
+    };
+
+    //Hashing a password doesn't need anything else set up
+    if command == commands::Command::HashPassword {
+        match commands::hash_password() {
+            Ok(password_hash) => {
+                println!("{}", password_hash);
+                std::process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("Hash password error: {:#?}", e);
+                std::process::exit(4);
+            }
+        }
+    }
 
     //Debugging
     println!("Config file(database_dir): {:#?}", server_config.database_dir);
@@ -181,16 +147,15 @@ async fn main() {
     println!("\nDiscovering listen port...");
     let mut listen_port = String::from(HELIPAD_STANDARD_PORT);
     let args: Vec<String> = env::args().collect();
-    let env_listen_port = std::env::var("HELIPAD_LISTEN_PORT");
     //First try from the environment
-    if env_listen_port.is_ok() {
-        listen_port = env_listen_port.unwrap();
+    if let Ok(env_listen_port) = std::env::var("HELIPAD_LISTEN_PORT") {
+        listen_port = env_listen_port;
         println!(" - Using environment var(HELIPAD_LISTEN_PORT): [{}]", listen_port);
-    } else if server_config.listen_port.is_some() {
+    } else if let Some(config_listen_port) = server_config.listen_port {
         //If that fails, try from the config file
-        listen_port = server_config.listen_port.unwrap().to_string();
+        listen_port = config_listen_port.to_string();
         println!(" - Using config file({}): [{}]", HELIPAD_CONFIG_FILE, listen_port);
-    } else if let Some(arg_port) = args.get(1) {
+    } else if let (commands::Command::Serve, Some(arg_port)) = (&command, args.get(1)) {
         //If that fails, try from the command line
         listen_port = arg_port.to_owned();
         println!(" - Using arg from command line: [{}]", listen_port);
@@ -200,12 +165,38 @@ async fn main() {
     }
     helipad_config.listen_port = listen_port.clone();
 
+    //LISTEN HOST -----
+    //Every interface by default.  Set it to 127.0.0.1 to keep Helipad to this machine.
+    println!("\nDiscovering listen address...");
+    let listen_host_setting = match std::env::var("HELIPAD_LISTEN_HOST") {
+        Ok(env_listen_host) => {
+            println!(" - Using environment var(HELIPAD_LISTEN_HOST): [{}]", env_listen_host);
+            env_listen_host
+        }
+        Err(_) => match server_config.listen_host.clone() {
+            Some(config_listen_host) => {
+                println!(" - Using config file({}): [{}]", HELIPAD_CONFIG_FILE, config_listen_host);
+                config_listen_host
+            }
+            None => {
+                println!(" - Nothing else found. Using default: [{}]", HELIPAD_STANDARD_LISTEN_HOST);
+                HELIPAD_STANDARD_LISTEN_HOST.to_string()
+            }
+        },
+    };
+    let listen_host: std::net::IpAddr = match listen_host_setting.trim().parse() {
+        Ok(listen_host) => listen_host,
+        Err(_) => {
+            eprintln!("Invalid listen address: [{}].  Use an ip address like 0.0.0.0 or 127.0.0.1.", listen_host_setting);
+            std::process::exit(1);
+        }
+    };
+
     //DATABASE FILE -----
     //First try to get the database file location from the environment
     println!("\nDiscovering database location...");
-    let env_database_file_path = std::env::var("HELIPAD_DATABASE_DIR");
-    if env_database_file_path.is_ok() {
-        helipad_config.database_file_path = env_database_file_path.unwrap();
+    if let Ok(env_database_file_path) = std::env::var("HELIPAD_DATABASE_DIR") {
+        helipad_config.database_file_path = env_database_file_path;
         println!(" - Using environment var(HELIPAD_DATABASE_DIR): [{}]", helipad_config.database_file_path);
     } else {
         //If that fails, try to get it from the config file
@@ -219,60 +210,271 @@ async fn main() {
         }
     }
     //Create the database file
-    match dbif::create_database(&helipad_config.database_file_path) {
-        Ok(_) => {
+    let db = match dbif::Database::open(&helipad_config.database_file_path) {
+        Ok(db) => {
             println!("Database file is ready...");
+            db
         }
         Err(e) => {
             eprintln!("Database error: {:#?}", e);
             std::process::exit(3);
         }
+    };
+
+    //Run the subcommand instead of the server if there was one
+    match command {
+        commands::Command::Serve | commands::Command::HashPassword => {}
+        commands::Command::Reparse => {
+            match commands::reparse(&db) {
+                Ok(count) => {
+                    println!("Reparsed {} payments.", count);
+                    std::process::exit(0);
+                }
+                Err(e) => {
+                    eprintln!("Reparse error: {:#?}", e);
+                    std::process::exit(4);
+                }
+            }
+        }
+        commands::Command::Export { format, filter, output } => {
+            match commands::export(&db, format, &filter, &output) {
+                Ok(count) => {
+                    println!("Exported {} payments to: [{}]", count, output);
+                    std::process::exit(0);
+                }
+                Err(e) => {
+                    eprintln!("Export error: {:#?}", e);
+                    std::process::exit(4);
+                }
+            }
+        }
+        commands::Command::Resync { from_index } => {
+            let lnd_connection = lightning::LndConnection::new(lightning::LndConfig::discover(&server_config));
+            match commands::resync(lnd_connection, &db, from_index).await {
+                Ok(_) => {
+                    println!("Resync complete.");
+                    std::process::exit(0);
+                }
+                Err(e) => {
+                    eprintln!("Resync error: {:#?}", e);
+                    std::process::exit(4);
+                }
+            }
+        }
+    }
+
+    //PASSWORD -----
+    //Without a password hash the web UI and api are open to anyone who can reach them
+    println!("\nDiscovering password...");
+    let mut auth_config = auth::AuthConfig::default();
+    if let Ok(env_password_hash) = std::env::var("HELIPAD_PASSWORD_HASH") {
+        auth_config.password_hash = Some(env_password_hash);
+        println!(" - Using environment var(HELIPAD_PASSWORD_HASH)");
+    } else if let Some(config_password_hash) = server_config.password_hash.clone() {
+        auth_config.password_hash = Some(config_password_hash);
+        println!(" - Using config file({})", HELIPAD_CONFIG_FILE);
+    } else {
+        if listen_host.is_loopback() {
+            eprintln!("ALERT: No password is set.  Helipad only listens on: [{}], so only this machine can reach it.", listen_host);
+        } else {
+            eprintln!("\n**********************************************************************************************\n\
+                       ALERT: No password is set and Helipad listens on: [{}].  Anyone who can reach this port can\n\
+                       read your boosts, change settings and send payments from your node.  Use \"helipad hash-password\"\n\
+                       and the HELIPAD_PASSWORD_HASH env var to set a password, or HELIPAD_LISTEN_HOST=127.0.0.1 to\n\
+                       keep Helipad to this machine.\n\
+                       **********************************************************************************************\n",
+                      listen_host);
+        }
+    }
+    if let Some(password_hash) = &auth_config.password_hash {
+        if let Err(e) = auth::validate_password_hash(password_hash) {
+            eprintln!("Password error: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    //TLS -----
+    //Serve HTTPS when a certificate and key are given, or when we're allowed to make our own
+    println!("\nDiscovering tls certificate...");
+    let mut tls_cert_path = None;
+    let mut tls_key_path = None;
+    if let Ok(env_tls_cert) = std::env::var("HELIPAD_TLS_CERT") {
+        println!(" - Using environment var(HELIPAD_TLS_CERT): [{}]", env_tls_cert);
+        tls_cert_path = Some(env_tls_cert);
+    } else if let Some(config_tls_cert) = server_config.tls_cert.clone() {
+        println!(" - Using config file({}): [{}]", HELIPAD_CONFIG_FILE, config_tls_cert);
+        tls_cert_path = Some(config_tls_cert);
     }
+    if let Ok(env_tls_key) = std::env::var("HELIPAD_TLS_KEY") {
+        println!(" - Using environment var(HELIPAD_TLS_KEY): [{}]", env_tls_key);
+        tls_key_path = Some(env_tls_key);
+    } else if let Some(config_tls_key) = server_config.tls_key.clone() {
+        println!(" - Using config file({}): [{}]", HELIPAD_CONFIG_FILE, config_tls_key);
+        tls_key_path = Some(config_tls_key);
+    }
+    let tls_self_signed = match std::env::var("HELIPAD_TLS_SELF_SIGNED") {
+        Ok(env_tls_self_signed) => env_tls_self_signed == "true" || env_tls_self_signed == "1",
+        Err(_) => server_config.tls_self_signed,
+    };
+    let tls_config = match (tls_cert_path, tls_key_path) {
+        (Some(cert_path), Some(key_path)) => Some(tls::TlsConfig { cert_path, key_path, self_signed: tls_self_signed }),
+        (None, None) if tls_self_signed => {
+            //Keep our own certificate with the database so it survives a container rebuild
+            let directory = Path::new(&helipad_config.database_file_path).parent().unwrap_or_else(|| Path::new(""));
+            let tls_config = tls::TlsConfig {
+                cert_path: directory.join(HELIPAD_TLS_CERT_FILE).to_string_lossy().into_owned(),
+                key_path: directory.join(HELIPAD_TLS_KEY_FILE).to_string_lossy().into_owned(),
+                self_signed: true,
+            };
+            println!(" - Using self-signed certificate: [{}]", tls_config.cert_path);
+            Some(tls_config)
+        }
+        (None, None) => {
+            println!(" - Nothing else found.  Serving plain http.");
+            None
+        }
+        _ => {
+            eprintln!("Tls error: Set both tls_cert and tls_key, or neither.");
+            std::process::exit(1);
+        }
+    };
+    let tls_acceptor = match &tls_config {
+        Some(tls_config) => match tls::acceptor(tls_config) {
+            Ok(acceptor) => Some(acceptor),
+            Err(e) => {
+                eprintln!("Tls error: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    //INBOUND WARNING -----
+    //Running out of inbound liquidity means boosts quietly stop arriving
+    println!("\nDiscovering inbound liquidity warning threshold...");
+    let inbound_warning_sats = match std::env::var("HELIPAD_INBOUND_WARNING_SATS") {
+        Ok(env_inbound_warning_sats) => match env_inbound_warning_sats.parse::<u64>() {
+            Ok(sats) => {
+                println!(" - Using environment var(HELIPAD_INBOUND_WARNING_SATS): [{}]", sats);
+                sats
+            }
+            Err(_) => {
+                eprintln!("Invalid HELIPAD_INBOUND_WARNING_SATS: [{}].  Use a number of sats.", env_inbound_warning_sats);
+                std::process::exit(1);
+            }
+        },
+        Err(_) => match server_config.inbound_warning_sats {
+            Some(sats) => {
+                println!(" - Using config file({}): [{}]", HELIPAD_CONFIG_FILE, sats);
+                sats
+            }
+            None => {
+                println!(" - Nothing else found. Using default: [{}]", lightning::INBOUND_WARNING_STANDARD_SATS);
+                lightning::INBOUND_WARNING_STANDARD_SATS
+            }
+        },
+    };
+    let inbound_warning_msat = inbound_warning_sats.saturating_mul(1000);
 
-    //Start the LND polling thread.  This thread will poll LND every few seconds to
-    //get the latest invoices and store them in the database.
-    tokio::spawn(lnd_poller(server_config, helipad_config.database_file_path.clone()));
+    //Login cookies shouldn't go out over plain http once we're serving https
+    auth_config.secure_cookies = tls_acceptor.is_some();
+
+    //Start the LND polling thread.  This thread follows the LND invoice subscription and
+    //stores new invoices in the database as they are paid.
+    //Each boost it stores is also published to the live boosts channel for the stream endpoint.
+    let lnd_connection = lightning::LndConnection::new(lightning::LndConfig::discover(&server_config));
+    let (live_boosts, _) = broadcast::channel(LIVE_BOOSTS_CHANNEL_SIZE);
+    tokio::spawn(lnd_poller(lnd_connection.clone(), db.clone(), live_boosts.clone()));
+
+    //Start the split payer.  It passes on the configured splits of each boost as it arrives, and
+    //every so often retries the payouts that failed.
+    tokio::spawn(split_payer(lnd_connection.clone(), db.clone(), live_boosts.subscribe()));
+
+    //Start the payment poller.  It picks up the boosts and streams sent from the node by any app,
+    //along with how our own replies and payouts turned out.
+    tokio::spawn(payment_poller(lnd_connection.clone(), db.clone()));
+
+    //Keep an eye on inbound liquidity so running out of it shows up in the log
+    if inbound_warning_msat > 0 {
+        tokio::spawn(inbound_watcher(lnd_connection.clone(), inbound_warning_msat));
+    }
 
     //Router
     let some_state = "state".to_string();
     let mut router: Router = Router::new();
 
     //Base
-    router.get("/", Box::new(handler::home));
-    router.get("/pew.mp3", Box::new(handler::pewmp3));
-    router.get("/favicon.ico", Box::new(handler::favicon));
+    router.get("/", Access::Token, Box::new(handler::home));
+    router.get("/sent", Access::Token, Box::new(handler::sent_page));
+    router.get("/login", Access::Public, Box::new(handler::login_page));
+    router.post("/login", Access::Public, Box::new(handler::login));
+    router.post("/logout", Access::Public, Box::new(handler::logout));
+    router.get("/pew.mp3", Access::Public, Box::new(handler::pewmp3));
+    router.get("/favicon.ico", Access::Public, Box::new(handler::favicon));
     //Assets
-    router.get("/image", Box::new(handler::asset));
-    router.get("/html", Box::new(handler::asset));
-    router.get("/style", Box::new(handler::asset));
-    router.get("/script", Box::new(handler::asset));
-    router.get("/extra", Box::new(handler::asset));
+    router.get("/image", Access::Public, Box::new(handler::asset));
+    router.get("/html", Access::Public, Box::new(handler::asset));
+    router.get("/style", Access::Public, Box::new(handler::asset));
+    router.get("/script", Access::Public, Box::new(handler::asset));
+    router.get("/extra", Access::Public, Box::new(handler::asset));
     //Api
-    router.get("/boosts", Box::new(handler::boosts));
+    router.get("/boosts", Access::Token, Box::new(handler::boosts));
+    router.get("/boosts/stream", Access::Token, Box::new(handler::boosts_stream));
+    router.get("/boosts/search", Access::Token, Box::new(handler::search));
+    router.put("/boosts/:index", Access::Session, Box::new(handler::update_boost));
+    router.post("/boosts/:index/reply", Access::Session, Box::new(handler::reply));
+    router.get("/boosts/:index/replies", Access::Token, Box::new(handler::replies));
+    router.get("/boosts/:index/payouts", Access::Token, Box::new(handler::payouts));
+    router.get("/payouts", Access::Token, Box::new(handler::payouts));
+    router.get("/splits", Access::Token, Box::new(handler::splits));
+    router.post("/splits", Access::Session, Box::new(handler::create_split));
+    router.delete("/splits/:id", Access::Session, Box::new(handler::delete_split));
+    router.get("/sent/boosts", Access::Token, Box::new(handler::sent_boosts));
+    router.get("/sent/podcasts", Access::Token, Box::new(handler::sent_podcasts));
+    router.get("/status", Access::Token, Box::new(handler::status));
+    router.get("/node", Access::Token, Box::new(handler::node));
+    router.get("/stats", Access::Token, Box::new(handler::stats));
+    router.get("/stats/largest", Access::Token, Box::new(handler::stats_largest));
+    router.get("/stats/:grouping", Access::Token, Box::new(handler::stats_grouped));
+    router.get("/export", Access::Token, Box::new(handler::export));
+    router.get("/tokens", Access::Session, Box::new(handler::api_tokens));
+    router.post("/tokens", Access::Session, Box::new(handler::create_api_token));
+    router.delete("/tokens/:id", Access::Session, Box::new(handler::delete_api_token));
     //router.get("/streams", Box::new(handler::streams));
 
     let shared_router = Arc::new(router);
-    let db_filepath: String = helipad_config.database_file_path.clone();
-    let new_service = make_service_fn(move |conn: &AddrStream| {
+    let service_for = move |remote_ip: String| {
         let app_state = AppState {
             state_thing: some_state.clone(),
-            remote_ip: conn.remote_addr().to_string().clone(),
+            remote_ip,
+            lnd: lnd_connection.clone(),
+            live_boosts: live_boosts.clone(),
+            auth: auth_config.clone(),
+            inbound_warning_msat,
         };
 
-        let database_file_path = db_filepath.clone();
+        let db = db.clone();
 
         let router_capture = shared_router.clone();
-        async {
-            Ok::<_, Error>(service_fn(move |req| {
-                route(router_capture.clone(), req, app_state.clone(), database_file_path.clone())
-            }))
-        }
-    });
+        service_fn(move |req| {
+            route(router_capture.clone(), req, app_state.clone(), db.clone())
+        })
+    };
 
-    let binding = format!("0.0.0.0:{}", &listen_port);
-    let addr = binding.parse().expect("address creation works");
-    let server = Server::bind(&addr).serve(new_service);
-    println!("\nHelipad is listening on http://{}", addr);
+    let addr = match listen_port.parse::<u16>() {
+        Ok(port) => SocketAddr::new(listen_host, port),
+        Err(_) => {
+            eprintln!("Invalid listen port: [{}].", listen_port);
+            std::process::exit(1);
+        }
+    };
+    let listener = match std::net::TcpListener::bind(addr) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Could not listen on: [{}]: {}", addr, e);
+            std::process::exit(1);
+        }
+    };
+    println!("\nHelipad is listening on {}://{}", if tls_acceptor.is_some() { "https" } else { "http" }, addr);
 
     //If a "run as" user is set in the "HELIPAD_RUN_AS" environment variable, then switch to that user
     //and drop root privileges after we've bound to the low range socket
@@ -292,256 +494,377 @@ async fn main() {
         }
     }
 
-    let _ = server.await;
+    match tls_acceptor {
+        Some(acceptor) => {
+            listener.set_nonblocking(true).expect("listener can be made non-blocking");
+            let listener = tokio::net::TcpListener::from_std(listener).expect("listener works with tokio");
+            tls::serve(listener, acceptor, service_for).await;
+        }
+        None => {
+            let new_service = make_service_fn(move |conn: &AddrStream| {
+                let service = service_for(conn.remote_addr().to_string());
+                async { Ok::<_, Error>(service) }
+            });
+            let server = Server::from_tcp(listener).expect("listener works with hyper").serve(new_service);
+            let _ = server.await;
+        }
+    }
 }
 
 async fn route(
     router: Arc<Router>,
     req: Request<hyper::Body>,
     app_state: AppState,
-    database_file_path: String,
+    db: dbif::Database,
 ) -> Result<Response, Error> {
     let found_handler = router.route(req.uri().path(), req.method());
+    if let Err(e) = auth::authorize(found_handler.access, &req, &app_state.auth, &db).await {
+        return Ok(auth::denied(&req, e));
+    }
     let path = req.uri().path().to_owned();
     let resp = found_handler
         .handler
-        .invoke(Context::new(app_state, req, &path, found_handler.params, database_file_path))
+        .invoke(Context::new(app_state, req, &path, found_handler.params, db))
         .await;
     Ok(resp)
 }
 
+//Run a database call on the blocking thread pool so it doesn't hold up the async executor
+pub async fn db_call<T, F>(db: &dbif::Database, call: F) -> Result<T, Error>
+where
+    F: FnOnce(&dbif::Database) -> Result<T, Error> + Send + 'static,
+    T: Send + 'static,
+{
+    let db = db.clone();
+    tokio::task::spawn_blocking(move || call(&db)).await?
+}
+
 impl Context {
-    pub fn new(state: AppState, reqbody: Request<Body>, path: &str, params: Params, database_file_path: String) -> Context {
+    pub fn new(state: AppState, reqbody: Request<Body>, path: &str, params: Params, db: dbif::Database) -> Context {
         Context {
-            state: state,
+            state,
             req: reqbody,
             path: path.to_string(),
-            params: params,
-            database_file_path: database_file_path,
+            params,
+            db,
             body_bytes: None,
         }
     }
 
-    pub async fn body_json<T: serde::de::DeserializeOwned>(&mut self) -> Result<T, Error> {
+    //Read the request body as JSON.  The body is kept, so this can be called more than once.
+    pub async fn body_json<T: serde::de::DeserializeOwned>(&mut self) -> Result<T, router::ApiError> {
         let body_bytes = match self.body_bytes {
             Some(ref v) => v,
             _ => {
-                let body = to_bytes(self.req.body_mut()).await?;
+                let body = to_bytes(self.req.body_mut()).await
+                    .map_err(|e| router::ApiError::BadRequest(format!("Could not read the request body: {}", e)))?;
                 self.body_bytes = Some(body);
                 self.body_bytes.as_ref().expect("body_bytes was set above")
             }
         };
-        Ok(serde_json::from_slice(&body_bytes)?)
+        serde_json::from_slice(body_bytes)
+            .map_err(|e| router::ApiError::BadRequest(format!("Invalid JSON: {}", e)))
     }
 }
 
-//The LND poller runs in a thread and pulls new invoices
-async fn lnd_poller(server_config: Config, database_file_path: String) {
+//The LND poller runs in a thread and pulls new invoices as they arrive
+async fn lnd_poller(lnd_connection: lightning::LndConnection, db: dbif::Database, live_boosts: broadcast::Sender<dbif::BoostRecord>) {
 
-    let db_filepath = database_file_path;
+    //The main loop.  Catch up on anything we missed with a sweep of the invoice list, then follow
+    //the invoice subscription until the stream breaks, at which point we reconnect and start over.
+    loop {
+        let mut lightning = lnd_connection.connect().await;
 
-    //Get the macaroon and cert files.  Look in the local directory first as an override.
-    //If the files are not found in the currect working directory, look for them at their
-    //normal LND directory locations
-    println!("\nDiscovering macaroon file path...");
-    let macaroon_path;
-    let env_macaroon_path = std::env::var("LND_ADMINMACAROON");
-    //First try from the environment
-    if env_macaroon_path.is_ok() {
-        macaroon_path = env_macaroon_path.unwrap();
-        println!(" - Trying environment var(LND_ADMINMACAROON): [{}]", macaroon_path);
-    } else if server_config.macaroon.is_some() {
-        macaroon_path = server_config.macaroon.unwrap();
-        println!(" - Trying config file({}): [{}]", HELIPAD_CONFIG_FILE, macaroon_path);
-    } else {
-        macaroon_path = "admin.macaroon".to_string();
-        println!(" - Trying current directory: [{}]", macaroon_path);
-    }
-    let macaroon: Vec<u8>;
-    match fs::read(macaroon_path.clone()) {
-        Ok(macaroon_content) => {
-            println!(" - Success.");
-            macaroon = macaroon_content;
+        let sweep_start_index = match db_call(&db, |db| db.get_sweep_start_index()).await {
+            Ok(index) => index,
+            Err(e) => {
+                eprintln!("Error getting sweep start index from database: {:#?}", e);
+                0
+            }
+        };
+        if let Err(e) = sweep_invoices(&mut lightning, &db, &live_boosts, sweep_start_index).await {
+            lnd_connection.disconnected(&e);
+            continue;
         }
-        Err(_) => {
-            println!(" - Error reading macaroon from: [{}]", macaroon_path);
-            println!(" - Last fallback attempt: [{}]", LND_STANDARD_MACAROON_LOCATION);
-            match fs::read(LND_STANDARD_MACAROON_LOCATION) {
-                Ok(macaroon_content) => {
-                    macaroon = macaroon_content;
+
+        //Resume from the last settlement we know about.  LND replays everything settled after it
+        //before moving on to live updates.
+        let settle_index = match db_call(&db, |db| db.get_last_settle_index()).await {
+            Ok(index) => index,
+            Err(e) => {
+                eprintln!("Error getting last settle index from database: {:#?}", e);
+                0
+            }
+        };
+        println!("Current settle index: {}", settle_index);
+
+        match lightning.subscribe_invoices(0, settle_index).await {
+            Ok(mut stream) => {
+                println!("Subscribed to invoice updates.");
+                loop {
+                    match stream.message().await {
+                        Ok(Some(invoice)) => {
+                            //Invoices come through here when added as well as when settled.  We only
+                            //want them once they have been paid.
+                            if invoice.state == InvoiceState::Settled as i32 {
+                                store_invoice(&db, &live_boosts, invoice).await;
+                            }
+                        }
+                        Ok(None) => {
+                            lnd_connection.disconnected(&lnd::Status::unavailable("Invoice subscription was closed by LND."));
+                            break;
+                        }
+                        Err(e) => {
+                            lnd_connection.disconnected(&e);
+                            break;
+                        }
+                    }
                 }
-                Err(_) => {
-                    eprintln!("Cannot find a valid admin.macaroon file");
-                    std::process::exit(1);
+            }
+            Err(e) => {
+                lnd_connection.disconnected(&e);
+            }
+        }
+    }
+}
+
+//The split payer runs alongside the poller and pays out splits of the boosts it stores.  Boosts that
+//arrive while LND is away, or that the channel dropped because we fell behind, are picked up by the
+//retry pass along with the failed payouts.
+async fn split_payer(lnd_connection: lightning::LndConnection, db: dbif::Database, mut live_boosts: broadcast::Receiver<dbif::BoostRecord>) {
+    let mut retry = tokio::time::interval(SPLIT_RETRY_INTERVAL);
+
+    loop {
+        tokio::select! {
+            received = live_boosts.recv() => {
+                match received {
+                    Ok(boost) => {
+                        if let Some(mut lightning) = lnd_connection.client() {
+                            if let Err(e) = payments::pay_splits(&mut lightning, &db, &boost).await {
+                                eprintln!("** Error paying splits of boost: [{}]: {}", boost.index, e);
+                            }
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        eprintln!("** Split payer fell behind by: [{}] boosts.  They will be paid on the next retry.", missed);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+            _ = retry.tick() => {
+                if let Some(mut lightning) = lnd_connection.client() {
+                    retry_splits(&mut lightning, &db).await;
                 }
             }
         }
     }
+}
 
-    println!("\nDiscovering certificate file path...");
-    let cert_path;
-    let env_cert_path = std::env::var("LND_TLSCERT");
-    if env_cert_path.is_ok() {
-        cert_path = env_cert_path.unwrap();
-        println!(" - Trying environment var(LND_TLSCERT): [{}]", cert_path);
-    } else if server_config.cert.is_some() {
-        cert_path = server_config.cert.unwrap();
-        println!(" - Trying config file({}): [{}]", HELIPAD_CONFIG_FILE, cert_path);
-    } else {
-        cert_path = "tls.cert".to_string();
-        println!(" - Trying current directory: [{}]", cert_path);
+//Pay every split still owed, including the ones that failed before
+async fn retry_splits(lightning: &mut lnd::Lnd, db: &dbif::Database) {
+    let owing = match db_call(db, |db| db.get_boosts_owing_payouts(payments::MAX_PAYOUT_ATTEMPTS, SPLIT_RETRY_BATCH_SIZE)).await {
+        Ok(owing) => owing,
+        Err(e) => {
+            eprintln!("** Error getting boosts owing payouts: {}", e);
+            return;
+        }
+    };
+
+    for index in owing {
+        let boost = match db_call(db, move |db| db.get_boost(index)).await {
+            Ok(Some(boost)) => boost,
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!("** Error getting boost: [{}]: {}", index, e);
+                continue;
+            }
+        };
+        if let Err(e) = payments::pay_splits(lightning, db, &boost).await {
+            eprintln!("** Error paying splits of boost: [{}]: {}", index, e);
+        }
     }
-    let cert: Vec<u8>;
-    match fs::read(cert_path.clone()) {
-        Ok(cert_content) => {
-            println!(" - Success.");
-            cert = cert_content;
+}
+
+//LND only lists payments, there's no subscription to follow, so the payment poller sweeps the list
+//every so often while we're connected
+async fn payment_poller(lnd_connection: lightning::LndConnection, db: dbif::Database) {
+    let mut sweep = tokio::time::interval(PAYMENT_SWEEP_INTERVAL);
+
+    loop {
+        sweep.tick().await;
+        if let Some(mut lightning) = lnd_connection.client() {
+            if let Err(e) = sweep_payments(&mut lightning, &db).await {
+                eprintln!("** Error sweeping sent payments: {}", e);
+            }
         }
-        Err(_) => {
-            println!(" - Error reading certificate from: [{}]", cert_path);
-            println!(" - Last fallback attempt: [{}]", LND_STANDARD_TLSCERT_LOCATION);
-            match fs::read(LND_STANDARD_TLSCERT_LOCATION) {
-                Ok(cert_content) => {
-                    cert = cert_content;
+    }
+}
+
+//Check the node's inbound liquidity every so often and say so when it drops below the warning
+//threshold, and again when it's back above it
+async fn inbound_watcher(lnd_connection: lightning::LndConnection, inbound_warning_msat: u64) {
+    let mut check = tokio::time::interval(INBOUND_CHECK_INTERVAL);
+    let mut was_low = false;
+
+    loop {
+        check.tick().await;
+        let mut client = match lnd_connection.client() {
+            Some(client) => client,
+            None => continue,
+        };
+
+        match lightning::NodeInfo::fetch(&mut client, inbound_warning_msat).await {
+            Ok(node_info) if node_info.inbound.low => {
+                if !was_low {
+                    eprintln!("** WARNING: Inbound liquidity is down to: [{}] sats, below: [{}] sats.  Boosts may stop arriving.",
+                              node_info.inbound.msat / 1000, inbound_warning_msat / 1000);
                 }
-                Err(_) => {
-                    eprintln!("Cannot find a valid tls.cert file");
-                    std::process::exit(2);
+                was_low = true;
+            }
+            Ok(node_info) => {
+                if was_low {
+                    println!("Inbound liquidity is back up to: [{}] sats.", node_info.inbound.msat / 1000);
                 }
+                was_low = false;
             }
+            Err(e) => eprintln!("** Error checking inbound liquidity: {}", e),
         }
     }
+}
 
-    //Get the url connection string of the lnd node
-    println!("\nDiscovering LND node address...");
-    let node_address;
-    let env_lnd_url = std::env::var("LND_URL");
-    if env_lnd_url.is_ok() {
-        node_address = "https://".to_owned() + env_lnd_url.unwrap().as_str();
-        println!(" - Trying environment var(LND_URL): [{}]", node_address);
-    } else if server_config.lnd_url.is_some() {
-        node_address = server_config.lnd_url.unwrap();
-        println!(" - Trying config file({}): [{}]", HELIPAD_CONFIG_FILE, node_address);
-    } else {
-        node_address = String::from(LND_STANDARD_GRPC_URL);
-        println!(" - Trying localhost default: [{}].", node_address);
-    }
+//Page through the node's payment list from where the last sweep left off and store the podcast
+//payments in it
+async fn sweep_payments(lightning: &mut lnd::Lnd, db: &dbif::Database) -> Result<(), Error> {
+    let mut index_offset = db_call(db, |db| db.get_payment_sweep_start_index()).await?;
 
-    //Make the connection to LND
-    let mut lightning;
-    match lnd::Lnd::connect_with_macaroon(node_address.clone(), &cert, &macaroon).await {
-        Ok(lndconn) => {
-            println!(" - Success.");
-            lightning = lndconn;
+    loop {
+        let response = lightning.list_payments(true, index_offset, LND_INVOICE_PAGE_SIZE, false).await?;
+        let count = response.payments.len() as u64;
+
+        for payment in response.payments {
+            let sent = payments::parse_sent_payment(payment);
+            let payment_index = sent.payment_index;
+            if let Some(index) = db_call(db, move |db| db.add_sent_payment(&sent)).await? {
+                println!("Sent payment: [{:?}] stored as: [{}]", payment_index, index);
+            }
         }
-        Err(e) => {
-            println!("Could not connect to: [{}] using tls: [{}] and macaroon: [{}]", node_address, cert_path, macaroon_path);
-            eprintln!("{:#?}", e);
-            std::process::exit(1);
+
+        //Remember how far we've read, so payments that weren't kept aren't read again next time
+        let last_index_offset = response.last_index_offset;
+        if last_index_offset > index_offset {
+            db_call(db, move |db| db.set_payment_sweep_index(last_index_offset)).await?;
         }
+
+        if count < LND_INVOICE_PAGE_SIZE || last_index_offset <= index_offset {
+            return Ok(());
+        }
+        index_offset = last_index_offset;
     }
+}
+
+//Page through the invoice list after the given index and store the settled invoices.  Invoices we
+//stored before they were paid get their state brought up to date.
+pub async fn sweep_invoices(lightning: &mut lnd::Lnd, db: &dbif::Database, live_boosts: &broadcast::Sender<dbif::BoostRecord>, start_index: u64) -> Result<(), lnd::Status> {
+    let mut current_index = start_index;
+    println!("Current index: {}", current_index);
 
-    //The main loop
-    let mut current_index = dbif::get_last_boost_index_from_db(&db_filepath).unwrap();
     loop {
+        let response = lightning.list_invoices(false, current_index, LND_INVOICE_PAGE_SIZE, false).await?;
+        if response.invoices.is_empty() {
+            break;
+        }
+        for invoice in response.invoices {
+            if invoice.state == InvoiceState::Settled as i32 {
+                store_invoice(db, live_boosts, invoice).await;
+            } else if let Err(e) = db_call(db, move |db| db.update_invoice_state(invoice.add_index, invoice.state as u8)).await {
+                eprintln!("Error updating invoice state: {:#?}", e);
+            }
+        }
+        current_index = response.last_index_offset;
+    }
 
-        //Get a list of invoices
-        match lnd::Lnd::list_invoices(&mut lightning, false, current_index.clone(), 500, false).await {
-            Ok(response) => {
-                for invoice in response.invoices {
-
-                    //Initialize a boost record
-                    let mut boost = dbif::BoostRecord {
-                        index: invoice.add_index,
-                        time: invoice.settle_date,
-                        value_msat: invoice.amt_paid_sat * 1000,
-                        value_msat_total: invoice.amt_paid_sat * 1000,
-                        action: 0,
-                        sender: "".to_string(),
-                        app: "".to_string(),
-                        message: "".to_string(),
-                        podcast: "".to_string(),
-                        episode: "".to_string(),
-                        tlv: "".to_string(),
-                    };
-
-                    //Search for podcast boost tlvs
-                    for htlc in invoice.htlcs {
-                        for (idx, val) in htlc.custom_records {
-                            //Satoshis.stream record type
-                            if idx == 7629169 {
-                                boost.tlv = std::str::from_utf8(&val).unwrap().to_string();
-                                let tlv = std::str::from_utf8(&val).unwrap();
-                                println!("TLV: {:#?}", tlv);
-                                let json_result = serde_json::from_str::<RawBoost>(tlv);
-                                match json_result {
-                                    Ok(rawboost) => {
-                                        println!("{:#?}", rawboost);
-                                        //If there was a sat value in the tlv, override the invoice
-                                        if rawboost.value_msat.is_some() {
-                                            boost.value_msat = rawboost.value_msat.unwrap() as i64;
-                                        }
-                                        //Determine an action type for later filtering ability
-                                        if rawboost.action.is_some() {
-                                            boost.action = match rawboost.action.unwrap().as_str() {
-                                                "stream" => 1, //This indicates a per-minute podcast payment
-                                                "boost" => 2,  //This is a manual boost or boost-a-gram
-                                                _ => 3,
-                                            }
-                                        }
-                                        //Was a sender name given in the tlv?
-                                        if rawboost.sender_name.is_some() && !rawboost.sender_name.clone().unwrap().is_empty() {
-                                            boost.sender = rawboost.sender_name.unwrap();
-                                        }
-                                        //Was there a message in this tlv?
-                                        if rawboost.message.is_some() {
-                                            boost.message = rawboost.message.unwrap();
-                                        }
-                                        //Was an app name given?
-                                        if rawboost.app_name.is_some() {
-                                            boost.app = rawboost.app_name.unwrap();
-                                        }
-                                        //Was a podcast name given?
-                                        if rawboost.podcast.is_some() {
-                                            boost.podcast = rawboost.podcast.unwrap();
-                                        }
-                                        //Episode name?
-                                        if rawboost.episode.is_some() {
-                                            boost.episode = rawboost.episode.unwrap();
-                                        }
-                                        //Look for an original sat value in the tlv
-                                        if rawboost.value_msat_total.is_some() {
-                                            boost.value_msat_total = rawboost.value_msat_total.unwrap() as i64;
-                                        }
-                                    }
-                                    Err(e) => {
-                                        eprintln!("{}", e);
-                                    }
-                                }
-                            }
-                        }
-                    }
+    Ok(())
+}
 
-                    //Give some output
-                    println!("Boost: {:#?}", boost);
+//Parse an invoice into a boost record, save it and let any live listeners know about it
+async fn store_invoice(db: &dbif::Database, live_boosts: &broadcast::Sender<dbif::BoostRecord>, invoice: lnd::lnrpc::lnrpc::Invoice) {
+    let (boost, htlcs, records) = parse_boost_from_invoice(invoice);
 
-                    //Store in the database
-                    println!("{:#?}", boost);
-                    match dbif::add_invoice_to_db(&db_filepath, boost) {
-                        Ok(_) => println!("New invoice added."),
-                        Err(e) => eprintln!("Error adding invoice: {:#?}", e)
-                    }
-                }
-            }
-            Err(e) => {
-                eprintln!("{}", e);
-            }
+    //Give some output
+    println!("Boost: {:#?}", boost);
+
+    //Store in the database
+    let stored = boost.clone();
+    match db_call(db, move |db| db.store_invoice(&stored, &htlcs, &records)).await {
+        Ok(_) => {
+            println!("New invoice added.");
+            //It's fine if nobody is listening
+            let _ = live_boosts.send(boost);
+        }
+        Err(e) => eprintln!("Error adding invoice: {:#?}", e)
+    }
+}
+
+//Build a boost record from an invoice, along with the htlcs that paid it and their custom records
+fn parse_boost_from_invoice(invoice: lnd::lnrpc::lnrpc::Invoice) -> (dbif::BoostRecord, Vec<dbif::Htlc>, Vec<dbif::CustomRecord>) {
+    //Initialize a boost record.  Until we find a podcast tlv, this is either a keysend
+    //payment or a regular invoice.
+    let mut boost = dbif::BoostRecord {
+        index: invoice.add_index,
+        settle_index: invoice.settle_index,
+        state: invoice.state as u8,
+        payment_hash: hex::encode(&invoice.r_hash),
+        time: invoice.settle_date,
+        value_msat: invoice.amt_paid_sat * 1000,
+        value_msat_total: invoice.amt_paid_sat * 1000,
+        action: if invoice.is_keysend { dbif::ACTION_KEYSEND } else { dbif::ACTION_INVOICE },
+        sender: "".to_string(),
+        app: "".to_string(),
+        message: "".to_string(),
+        podcast: "".to_string(),
+        episode: "".to_string(),
+        tlv: "".to_string(),
+        ..Default::default()
+    };
+
+    //Keep every htlc and custom record, sorted so they're decoded and stored in a stable order
+    let mut htlcs: Vec<dbif::Htlc> = Vec::new();
+    let mut records: Vec<dbif::CustomRecord> = Vec::new();
+    for htlc in invoice.htlcs {
+        htlcs.push(dbif::Htlc {
+            boost_index: invoice.add_index,
+            htlc_index: htlc.htlc_index,
+            chan_id: htlc.chan_id,
+            amt_msat: htlc.amt_msat,
+            state: htlc.state as u8,
+        });
+        let mut custom_records: Vec<(u64, Vec<u8>)> = htlc.custom_records.into_iter().collect();
+        custom_records.sort_by_key(|(record_type, _)| *record_type);
+        for (record_type, value) in custom_records {
+            records.push(dbif::CustomRecord {
+                boost_index: invoice.add_index,
+                htlc_index: htlc.htlc_index,
+                record_type,
+                value,
+            });
         }
+    }
+    htlcs.sort_by_key(|htlc| htlc.htlc_index);
+
+    tlv::RecordDecoders::default().decode(&mut boost, &records);
+    apply_settled_value(&mut boost, &htlcs);
 
-        //Make sure we are tracking our position properly
-        current_index = dbif::get_last_boost_index_from_db(&db_filepath).unwrap();
-        println!("Current index: {}", current_index);
+    (boost, htlcs, records)
+}
 
-        std::thread::sleep(std::time::Duration::from_millis(9000));
+//What a payment was worth is what actually arrived, so once we know the htlcs use the sum of the
+//settled ones rather than the invoice or tlv amount
+pub fn apply_settled_value(boost: &mut dbif::BoostRecord, htlcs: &[dbif::Htlc]) {
+    let settled: Vec<&dbif::Htlc> = htlcs.iter().filter(|h| h.state == dbif::HTLC_STATE_SETTLED).collect();
+    if settled.is_empty() {
+        return;
     }
-}
\ No newline at end of file
+
+    let value_msat: u64 = settled.iter().map(|h| h.amt_msat).sum();
+    boost.value_msat = i64::try_from(value_msat).unwrap_or(i64::MAX);
+}
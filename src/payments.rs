@@ -0,0 +1,337 @@
+use crate::{db_call, tlv, Error};
+use lnd::lnrpc::lnrpc::htlc_attempt::HtlcStatus;
+use lnd::lnrpc::lnrpc::payment::PaymentStatus;
+use lnd::lnrpc::lnrpc::{Payment, PaymentFailureReason, SendRequest};
+use lnd::Code;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::convert::TryFrom;
+
+
+//Constants --------------------------------------------------------------------------------------------------
+const PREIMAGE_BYTES: usize = 32;
+const PUBKEY_BYTES: usize = 33;
+//How many times a split payout is tried before giving up on it
+pub const MAX_PAYOUT_ATTEMPTS: u64 = 5;
+//The smallest payout worth sending
+const MIN_PAYOUT_MSAT: i64 = 1000;
+//What LND says when a payment hash has been paid before
+const ALREADY_PAID: &str = "already paid";
+
+
+//Functions --------------------------------------------------------------------------------------------------
+//Send a keysend boost to the payment's recipient and keep track of it in the outgoing table.  The
+//payment is stored before it's sent, so one we lose track of part way still shows up.  Returns the
+//payment as it was stored, with how it turned out.
+//
+//A payment that already has a preimage (ex. a retry of a split payout) pays the same hash again,
+//which LND will refuse if it went through before.
+pub async fn send_boost(lightning: &mut lnd::Lnd, db: &dbif::Database, mut payment: dbif::OutgoingPayment) -> Result<dbif::OutgoingPayment, Error> {
+    let dest = parse_pubkey(&payment.recipient)
+        .ok_or(format!("Invalid recipient pubkey: [{}].", payment.recipient))?;
+
+    //Keysend payments carry their own preimage for the recipient's node to settle with
+    let preimage = match hex::decode(&payment.preimage) {
+        Ok(preimage) if preimage.len() == PREIMAGE_BYTES => preimage,
+        _ => {
+            let mut preimage = vec![0u8; PREIMAGE_BYTES];
+            rand::thread_rng().fill_bytes(&mut preimage);
+            preimage
+        }
+    };
+    let payment_hash = Sha256::digest(&preimage).to_vec();
+    let custom_record = payment.custom_key.map(|key| (key, payment.custom_value.as_bytes()));
+    let dest_custom_records = tlv::keysend_records(payment.tlv.as_bytes(), &preimage, custom_record);
+
+    payment.time = chrono::Utc::now().timestamp();
+    payment.payment_hash = hex::encode(&payment_hash);
+    payment.preimage = hex::encode(&preimage);
+    tlv::describe_sent(&mut payment, &dest_custom_records);
+    payment.state = dbif::OUTGOING_STATE_IN_FLIGHT;
+    let stored = payment.clone();
+    payment.index = db_call(db, move |db| db.add_outgoing(&stored)).await?;
+
+    println!("Sending boost: [{}] of: [{}] msat to: [{}]", payment.index, payment.value_msat, payment.recipient);
+    let request = SendRequest {
+        dest,
+        amt_msat: payment.value_msat,
+        payment_hash,
+        dest_custom_records,
+        ..Default::default()
+    };
+    match lightning.send_payment_sync(request).await {
+        Ok(response) if response.payment_error.is_empty() => {
+            payment.state = dbif::OUTGOING_STATE_SUCCEEDED;
+            payment.fee_msat = response.payment_route.map(|route| route.total_fees_msat).unwrap_or(0);
+        }
+        Ok(response) => {
+            payment.state = dbif::OUTGOING_STATE_FAILED;
+            payment.error = response.payment_error;
+        }
+        //An earlier attempt at this payment went through after all
+        Err(status) if status.message().contains(ALREADY_PAID) => {
+            payment.state = dbif::OUTGOING_STATE_SUCCEEDED;
+        }
+        //LND turned the payment down before trying it
+        Err(status) if is_rejection(status.code()) => {
+            payment.state = dbif::OUTGOING_STATE_FAILED;
+            payment.error = status.message().to_string();
+        }
+        //Anything else (ex. the connection dropped) may have happened after the payment went out,
+        //so it stays in flight rather than being reported as failed
+        Err(status) => {
+            payment.error = format!("Lost track of the payment: {}", status.message());
+        }
+    }
+    println!("Boost: [{}] finished with state: [{}] {}", payment.index, payment.state, payment.error);
+
+    let result = payment.clone();
+    db_call(db, move |db| db.update_outgoing_result(result.index, result.state, result.fee_msat, &result.error)).await?;
+
+    Ok(payment)
+}
+
+//Pass on the splits of a payment we received.  Splits already paid or skipped, or tried too many
+//times, are left alone, so this is safe to call again for the same boost to retry the ones that failed.
+pub async fn pay_splits(lightning: &mut lnd::Lnd, db: &dbif::Database, boost: &dbif::BoostRecord) -> Result<(), Error> {
+    if boost.state != dbif::INVOICE_STATE_SETTLED
+        || (boost.action != dbif::ACTION_BOOST && boost.action != dbif::ACTION_STREAM) {
+        return Ok(());
+    }
+
+    let for_boost = boost.clone();
+    let splits = db_call(db, move |db| db.get_splits_for(&for_boost)).await?;
+
+    let amounts = split_amounts(boost.value_msat, &splits);
+    for split in &splits {
+        let (boost_index, split_id) = (boost.index, split.id);
+        let attempts = db_call(db, move |db| db.get_payout_attempts(boost_index, split_id)).await?;
+        if attempts.iter().any(|attempt| attempt.state == dbif::OUTGOING_STATE_SUCCEEDED
+                                         || attempt.state == dbif::OUTGOING_STATE_SKIPPED)
+            || attempts.len() as u64 >= MAX_PAYOUT_ATTEMPTS {
+            continue;
+        }
+
+        //A share too small to send is recorded as skipped, so it isn't looked at again
+        let value_msat = match amounts.iter().find(|(paid, _)| paid.id == split.id) {
+            Some((_, value_msat)) => *value_msat,
+            None => {
+                let skipped = dbif::OutgoingPayment {
+                    boost_index: Some(boost.index),
+                    time: chrono::Utc::now().timestamp(),
+                    recipient: split.recipient.clone(),
+                    custom_key: split.custom_key,
+                    custom_value: split.custom_value.clone(),
+                    state: dbif::OUTGOING_STATE_SKIPPED,
+                    error: format!("The share is below the smallest payout of: [{}] msat.", MIN_PAYOUT_MSAT),
+                    split_id: Some(split.id),
+                    ..Default::default()
+                };
+                db_call(db, move |db| db.add_outgoing(&skipped)).await?;
+                continue;
+            }
+        };
+
+        let podcast_record = tlv::OutgoingBoost::split_of(boost, value_msat as u64, &split.name);
+        let payment = dbif::OutgoingPayment {
+            boost_index: Some(boost.index),
+            value_msat,
+            recipient: split.recipient.clone(),
+            custom_key: split.custom_key,
+            custom_value: split.custom_value.clone(),
+            sender: boost.sender.clone(),
+            message: boost.message.clone(),
+            tlv: serde_json::to_string(&podcast_record)?,
+            split_id: Some(split.id),
+            //Every attempt pays the same hash
+            preimage: attempts.first().map(|attempt| attempt.preimage.clone()).unwrap_or_default(),
+            ..Default::default()
+        };
+        send_boost(lightning, db, payment).await?;
+    }
+
+    Ok(())
+}
+
+//How much of a payment each split gets, in the order they're paid.  A split never gets more than
+//what's left after the ones before it, and splits too small to send are left out.
+pub fn split_amounts(value_msat: i64, splits: &[dbif::Split]) -> Vec<(&dbif::Split, i64)> {
+    let mut remaining = value_msat.max(0);
+    let mut amounts = Vec::new();
+
+    for split in splits {
+        let wanted = match (split.percent, split.sats) {
+            (Some(percent), _) => (value_msat as f64 * percent / 100.0).floor() as i64,
+            (None, Some(sats)) => i64::try_from(sats).unwrap_or(i64::MAX).saturating_mul(1000),
+            (None, None) => 0,
+        };
+        let value_msat = wanted.min(remaining);
+        if value_msat < MIN_PAYOUT_MSAT {
+            continue;
+        }
+
+        remaining -= value_msat;
+        amounts.push((split, value_msat));
+    }
+
+    amounts
+}
+
+//Build an outgoing payment from one in the node's payment list.  Where it went and what it said come
+//from the custom records on the last hop of its route: the htlc that went through, or the latest
+//attempt when none has yet.
+pub fn parse_sent_payment(payment: Payment) -> dbif::OutgoingPayment {
+    let state = match payment.status() {
+        PaymentStatus::Succeeded => dbif::OUTGOING_STATE_SUCCEEDED,
+        PaymentStatus::Failed => dbif::OUTGOING_STATE_FAILED,
+        PaymentStatus::InFlight | PaymentStatus::Unknown => dbif::OUTGOING_STATE_IN_FLIGHT,
+    };
+    let error = match payment.failure_reason() {
+        PaymentFailureReason::FailureReasonNone => "".to_string(),
+        reason => format!("{:?}", reason),
+    };
+
+    let last_hop = payment.htlcs.iter()
+        .find(|htlc| htlc.status() == HtlcStatus::Succeeded)
+        .or_else(|| payment.htlcs.last())
+        .and_then(|htlc| htlc.route.as_ref())
+        .and_then(|route| route.hops.last());
+
+    let mut sent = dbif::OutgoingPayment {
+        time: payment.creation_time_ns / 1_000_000_000,
+        value_msat: payment.value_msat,
+        fee_msat: payment.fee_msat,
+        payment_hash: payment.payment_hash.clone(),
+        state,
+        error,
+        payment_index: Some(payment.payment_index),
+        action: dbif::ACTION_KEYSEND,
+        ..Default::default()
+    };
+    if let Some(hop) = last_hop {
+        sent.recipient = hop.pub_key.clone();
+        //A record outside the ones podcast apps use is what picks out the recipient's wallet
+        if let Some((key, value)) = hop.custom_records.iter()
+            .filter(|(key, _)| !tlv::is_podcast_record_type(**key))
+            .min_by_key(|(key, _)| **key) {
+            sent.custom_key = Some(*key);
+            sent.custom_value = String::from_utf8_lossy(value).to_string();
+        }
+        tlv::describe_sent(&mut sent, &hop.custom_records);
+    }
+
+    sent
+}
+
+//A node pubkey given as hex
+pub fn parse_pubkey(pubkey: &str) -> Option<Vec<u8>> {
+    match hex::decode(pubkey.trim()) {
+        Ok(bytes) if bytes.len() == PUBKEY_BYTES => Some(bytes),
+        _ => None,
+    }
+}
+
+//Whether a failed call means LND refused the payment outright, so it was never sent
+fn is_rejection(code: Code) -> bool {
+    matches!(code, Code::InvalidArgument | Code::FailedPrecondition | Code::NotFound | Code::AlreadyExists
+                   | Code::PermissionDenied | Code::Unauthenticated | Code::OutOfRange)
+}
+
+
+//Tests ------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lnd::lnrpc::lnrpc::{Hop, HtlcAttempt, Route};
+    use std::collections::HashMap;
+
+    fn split(id: u64, percent: Option<f64>, sats: Option<u64>) -> dbif::Split {
+        dbif::Split {
+            id,
+            percent,
+            sats,
+            ..Default::default()
+        }
+    }
+
+    fn sent_payment(status: PaymentStatus, htlcs: Vec<(HtlcStatus, HashMap<u64, Vec<u8>>)>) -> Payment {
+        Payment {
+            payment_hash: "ab".repeat(32),
+            value_msat: 100_000,
+            fee_msat: 12,
+            creation_time_ns: 1_650_000_000_123_456_789,
+            status: status as i32,
+            payment_index: 42,
+            htlcs: htlcs.into_iter().map(|(htlc_status, custom_records)| HtlcAttempt {
+                status: htlc_status as i32,
+                route: Some(Route {
+                    hops: vec![Hop::default(), Hop {
+                        pub_key: "03ae9f".to_string(),
+                        custom_records,
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn sent_payments_are_read_from_the_last_hop() {
+        let podcast_record = br#"{"action":"stream","podcast":"Podcasting 2.0","episode":"Episode 80","app_name":"Breez","sender_name":"dave","feedID":920666}"#;
+        let mut records = HashMap::new();
+        records.insert(tlv::TLV_PODCAST, podcast_record.to_vec());
+        records.insert(tlv::TLV_KEYSEND_PREIMAGE, vec![7u8; 32]);
+        records.insert(696969, b"wallet".to_vec());
+
+        let failed = (HtlcStatus::Failed, HashMap::new());
+        let sent = parse_sent_payment(sent_payment(PaymentStatus::Succeeded, vec![(HtlcStatus::Succeeded, records), failed]));
+        assert_eq!(sent.state, dbif::OUTGOING_STATE_SUCCEEDED);
+        assert_eq!(sent.time, 1_650_000_000);
+        assert_eq!(sent.payment_index, Some(42));
+        assert_eq!(sent.recipient, "03ae9f");
+        assert_eq!(sent.custom_key, Some(696969));
+        assert_eq!(sent.custom_value, "wallet");
+        assert_eq!(sent.action, dbif::ACTION_STREAM);
+        assert_eq!(sent.podcast, "Podcasting 2.0");
+        assert_eq!(sent.app, "Breez");
+        assert_eq!(sent.sender, "dave");
+        assert_eq!(sent.feed_id, Some(920666));
+        assert_eq!(sent.tlv, String::from_utf8_lossy(podcast_record));
+    }
+
+    #[test]
+    fn sent_payments_without_a_podcast_record() {
+        let sent = parse_sent_payment(sent_payment(PaymentStatus::Failed, vec![]));
+        assert_eq!(sent.state, dbif::OUTGOING_STATE_FAILED);
+        assert_eq!(sent.action, dbif::ACTION_KEYSEND);
+        assert!(sent.tlv.is_empty());
+        assert!(sent.recipient.is_empty());
+    }
+
+    #[test]
+    fn splits_are_a_percent_or_fixed_sats() {
+        let splits = vec![split(1, Some(10.0), None), split(2, None, Some(50)), split(3, Some(2.5), None)];
+        let amounts: Vec<(u64, i64)> = split_amounts(1_000_000, &splits).iter().map(|(s, v)| (s.id, *v)).collect();
+
+        assert_eq!(amounts, vec![(1, 100_000), (2, 50_000), (3, 25_000)]);
+    }
+
+    #[test]
+    fn splits_never_pay_out_more_than_arrived() {
+        let splits = vec![split(1, Some(60.0), None), split(2, None, Some(300)), split(3, Some(10.0), None)];
+        let amounts: Vec<(u64, i64)> = split_amounts(500_000, &splits).iter().map(|(s, v)| (s.id, *v)).collect();
+
+        assert_eq!(amounts, vec![(1, 300_000), (2, 200_000)]);
+    }
+
+    #[test]
+    fn tiny_splits_are_skipped() {
+        let splits = vec![split(1, Some(1.0), None), split(2, None, Some(0)), split(3, None, None)];
+
+        assert!(split_amounts(50_000, &splits).is_empty());
+        assert_eq!(split_amounts(100_000, &splits).len(), 1);
+    }
+}
@@ -3,7 +3,9 @@ use async_trait::async_trait;
 use futures::future::Future;
 use hyper::{Method, StatusCode};
 use route_recognizer::{Match, Params, Router as InternalRouter};
+use serde::Serialize;
 use std::collections::HashMap;
+use std::fmt;
 
 #[async_trait]
 pub trait Handler: Send + Sync + 'static {
@@ -22,13 +24,28 @@ where
     }
 }
 
+//Who may call a route.  Static assets are public, api tokens may only read, and anything that
+//changes something or hands out tokens needs the host to be logged in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    Public,
+    Token,
+    Session,
+}
+
 pub struct RouterMatch<'a> {
     pub handler: &'a dyn Handler,
     pub params: Params,
+    pub access: Access,
+}
+
+struct Route {
+    handler: Box<dyn Handler>,
+    access: Access,
 }
 
 pub struct Router {
-    method_map: HashMap<Method, InternalRouter<Box<dyn Handler>>>,
+    method_map: HashMap<Method, InternalRouter<Route>>,
 }
 
 //This web router is based on the example code here:
@@ -40,34 +57,52 @@ impl Router {
         }
     }
 
-    pub fn get(&mut self, path: &str, handler: Box<dyn Handler>) {
-        self.method_map
-            .entry(Method::GET)
-            .or_insert_with(InternalRouter::new)
-            .add(path, handler)
+    pub fn get(&mut self, path: &str, access: Access, handler: Box<dyn Handler>) {
+        self.add(Method::GET, path, access, handler)
     }
 
-    // pub fn post(&mut self, path: &str, handler: Box<dyn Handler>) {
-    //     self.method_map
-    //         .entry(Method::POST)
-    //         .or_insert_with(InternalRouter::new)
-    //         .add(path, handler)
-    // }
+    pub fn post(&mut self, path: &str, access: Access, handler: Box<dyn Handler>) {
+        self.add(Method::POST, path, access, handler)
+    }
+
+    pub fn put(&mut self, path: &str, access: Access, handler: Box<dyn Handler>) {
+        self.add(Method::PUT, path, access, handler)
+    }
+
+    pub fn delete(&mut self, path: &str, access: Access, handler: Box<dyn Handler>) {
+        self.add(Method::DELETE, path, access, handler)
+    }
+
+    fn add(&mut self, method: Method, path: &str, access: Access, handler: Box<dyn Handler>) {
+        self.method_map
+            .entry(method)
+            .or_default()
+            .add(path, Route { handler, access })
+    }
 
     pub fn route(&self, path: &str, method: &Method) -> RouterMatch<'_> {
-        if let Some(Match { handler, params }) = self
+        if let Some(Match { handler: route, params }) = self
             .method_map
             .get(method)
             .and_then(|r| r.recognize(path).ok())
         {
             RouterMatch {
-                handler: &**handler,
+                handler: &*route.handler,
                 params,
+                access: route.access,
+            }
+        } else if self.method_map.values().any(|r| r.recognize(path).is_ok()) {
+            //The path exists, just not for this method
+            RouterMatch {
+                handler: &method_not_allowed_handler,
+                params: Params::new(),
+                access: Access::Public,
             }
         } else {
             RouterMatch {
                 handler: &not_found_handler,
                 params: Params::new(),
+                access: Access::Public,
             }
         }
     }
@@ -80,6 +115,13 @@ async fn not_found_handler(_cx: Context) -> Response {
         .unwrap()
 }
 
+async fn method_not_allowed_handler(_cx: Context) -> Response {
+    hyper::Response::builder()
+        .status(StatusCode::METHOD_NOT_ALLOWED)
+        .body("METHOD NOT ALLOWED".into())
+        .unwrap()
+}
+
 pub trait IntoResponse: Send + Sized {
     fn into_response(self) -> Response;
 }
@@ -100,4 +142,152 @@ impl IntoResponse for String {
     fn into_response(self) -> Response {
         Response::new(self.into())
     }
-}
\ No newline at end of file
+}
+impl<T: IntoResponse, E: IntoResponse> IntoResponse for Result<T, E> {
+    fn into_response(self) -> Response {
+        match self {
+            Ok(value) => value.into_response(),
+            Err(e) => e.into_response(),
+        }
+    }
+}
+
+//A value sent back as a JSON document
+#[derive(Debug)]
+pub struct Json<T>(pub T);
+
+impl<T: Serialize + Send> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        match serde_json::to_string(&self.0) {
+            Ok(json_doc) => json_response(StatusCode::OK, json_doc),
+            Err(e) => ApiError::Internal(format!("Could not serialize the response: {}", e)).into_response(),
+        }
+    }
+}
+
+impl<T: serde::de::DeserializeOwned> Json<T> {
+    //Read the request body as JSON.  A body that doesn't parse into a T is the client's mistake.
+    pub async fn extract(ctx: &mut Context) -> Result<Json<T>, ApiError> {
+        ctx.body_json().await.map(Json)
+    }
+}
+
+//An error from an api handler.  Each kind maps to a status code, and the message is sent back as
+//{"error": "..."}.
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Internal(String),
+    Unavailable(String),
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            ApiError::BadRequest(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Forbidden(message)
+            | ApiError::NotFound(message)
+            | ApiError::Internal(message)
+            | ApiError::Unavailable(message) => message,
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.status(), self.message())
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+//Errors coming up from the database or LND are our problem, not the client's, so the details go to
+//the log rather than the client.  A search query the database couldn't understand is the exception.
+impl From<crate::Error> for ApiError {
+    fn from(e: crate::Error) -> ApiError {
+        if let Some(e) = e.downcast_ref::<dbif::InvalidSearchQuery>() {
+            return ApiError::BadRequest(e.to_string());
+        }
+        eprintln!("** Internal error: {}", e);
+        ApiError::Internal("Internal error.".to_string())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let json_doc = serde_json::json!({ "error": self.message() }).to_string();
+        json_response(self.status(), json_doc)
+    }
+}
+
+pub fn json_response(status: StatusCode, json_doc: String) -> Response {
+    hyper::Response::builder()
+        .status(status)
+        .header("Content-type", "application/json")
+        .body(json_doc.into())
+        .unwrap()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn body_string(response: Response) -> String {
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn json_sets_the_content_type() {
+        let response = Json(vec![1, 2, 3]).into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["Content-type"], "application/json");
+        assert_eq!(body_string(response).await, "[1,2,3]");
+    }
+
+    #[tokio::test]
+    async fn errors_map_to_status_codes() {
+        let response = ApiError::BadRequest("Bad \"index\".".to_string()).into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()["Content-type"], "application/json");
+        assert_eq!(body_string(response).await, r#"{"error":"Bad \"index\"."}"#);
+
+        assert_eq!(ApiError::Unauthorized("".to_string()).status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(ApiError::Forbidden("".to_string()).status(), StatusCode::FORBIDDEN);
+        assert_eq!(ApiError::NotFound("".to_string()).status(), StatusCode::NOT_FOUND);
+        assert_eq!(ApiError::Unavailable("".to_string()).status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn internal_details_stay_in_the_log() {
+        let e: crate::Error = "disk I/O error at /var/lib/helipad".into();
+        let response = ApiError::from(e).into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body_string(response).await, r#"{"error":"Internal error."}"#);
+    }
+
+    #[tokio::test]
+    async fn results_use_either_side() {
+        let ok: Result<Json<&str>, ApiError> = Ok(Json("fine"));
+        assert_eq!(ok.into_response().status(), StatusCode::OK);
+
+        let err: Result<Json<&str>, ApiError> = Err(ApiError::NotFound("gone".to_string()));
+        assert_eq!(err.into_response().status(), StatusCode::NOT_FOUND);
+    }
+}
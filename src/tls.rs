@@ -0,0 +1,166 @@
+use crate::{Error, Response};
+use hyper::server::conn::Http;
+use hyper::service::Service;
+use hyper::{Body, Request};
+use openssl::asn1::{Asn1Integer, Asn1Time};
+use openssl::bn::{BigNum, MsbOption};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::PKey;
+use openssl::ssl::{Ssl, SslAcceptor, SslFiletype, SslMethod};
+use openssl::x509::extension::{BasicConstraints, ExtendedKeyUsage, KeyUsage, SubjectAlternativeName};
+use openssl::x509::{X509NameBuilder, X509};
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::pin::Pin;
+use tokio::net::TcpListener;
+use tokio_openssl::SslStream;
+
+
+//Constants --------------------------------------------------------------------------------------------------
+//Self-signed certificates last as long as the ones LND makes for itself
+const SELF_SIGNED_VALID_DAYS: u32 = 425;
+const SELF_SIGNED_ORGANIZATION: &str = "helipad autogenerated cert";
+
+
+//Structs and Enums ------------------------------------------------------------------------------------------
+//Where the web server's certificate and key are, and whether we may make our own
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+    pub self_signed: bool,
+}
+
+
+//Functions --------------------------------------------------------------------------------------------------
+//Load the certificate and key for serving HTTPS.  With self signing allowed, a missing or expired
+//certificate is replaced with a new self-signed one first.
+pub fn acceptor(config: &TlsConfig) -> Result<SslAcceptor, Error> {
+    if config.self_signed && needs_new_certificate(&config.cert_path, &config.key_path)? {
+        println!("Generating a self-signed certificate: [{}]...", config.cert_path);
+        generate_self_signed(&config.cert_path, &config.key_path)?;
+    }
+
+    let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server())?;
+    builder.set_certificate_chain_file(&config.cert_path)
+        .map_err(|e| format!("Could not load the tls certificate: [{}]: {}", config.cert_path, e))?;
+    builder.set_private_key_file(&config.key_path, SslFiletype::PEM)
+        .map_err(|e| format!("Could not load the tls key: [{}]: {}", config.key_path, e))?;
+    builder.check_private_key()
+        .map_err(|e| format!("The tls key: [{}] doesn't match the certificate: [{}]: {}", config.key_path, config.cert_path, e))?;
+
+    Ok(builder.build())
+}
+
+//Accept connections and serve them over TLS.  A failed handshake only loses that one connection.
+pub async fn serve<F, S>(listener: TcpListener, acceptor: SslAcceptor, service_for: F)
+where
+    F: Fn(String) -> S,
+    S: Service<Request<Body>, Response = Response, Error = Error> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    loop {
+        let (stream, remote_addr) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                eprintln!("** Could not accept a connection: {}", e);
+                continue;
+            }
+        };
+
+        let ssl = match Ssl::new(acceptor.context()) {
+            Ok(ssl) => ssl,
+            Err(e) => {
+                eprintln!("** Could not start a tls session: {}", e);
+                continue;
+            }
+        };
+        let service = service_for(remote_addr.to_string());
+
+        tokio::spawn(async move {
+            let mut stream = match SslStream::new(ssl, stream) {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("** Could not start a tls session: {}", e);
+                    return;
+                }
+            };
+            if let Err(e) = Pin::new(&mut stream).accept().await {
+                eprintln!("** Tls handshake with: [{}] failed: {}", remote_addr, e);
+                return;
+            }
+            if let Err(e) = Http::new().serve_connection(stream, service).await {
+                eprintln!("** Connection with: [{}] failed: {}", remote_addr, e);
+            }
+        });
+    }
+}
+
+//Whether the certificate is missing or has run out
+fn needs_new_certificate(cert_path: &str, key_path: &str) -> Result<bool, Error> {
+    if !Path::new(cert_path).exists() || !Path::new(key_path).exists() {
+        return Ok(true);
+    }
+
+    let cert = X509::from_pem(&fs::read(cert_path)?)
+        .map_err(|e| format!("Could not read the tls certificate: [{}]: {}", cert_path, e))?;
+    if cert.not_after() < Asn1Time::days_from_now(0)? {
+        println!("The tls certificate: [{}] has expired.", cert_path);
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+//Make a self-signed certificate for this machine's host name, localhost and the loopback addresses
+fn generate_self_signed(cert_path: &str, key_path: &str) -> Result<(), Error> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+    let key = PKey::from_ec_key(EcKey::generate(&group)?)?;
+
+    let host_name = fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|name| name.trim().to_string())
+        .unwrap_or_default();
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_nid(Nid::ORGANIZATIONNAME, SELF_SIGNED_ORGANIZATION)?;
+    name.append_entry_by_nid(Nid::COMMONNAME, if host_name.is_empty() { "localhost" } else { &host_name })?;
+    let name = name.build();
+
+    let mut serial = BigNum::new()?;
+    serial.rand(128, MsbOption::MAYBE_ZERO, false)?;
+
+    let mut builder = X509::builder()?;
+    builder.set_version(2)?;
+    builder.set_serial_number(Asn1Integer::from_bn(&serial)?.as_ref())?;
+    builder.set_subject_name(&name)?;
+    builder.set_issuer_name(&name)?;
+    builder.set_pubkey(&key)?;
+    builder.set_not_before(Asn1Time::days_from_now(0)?.as_ref())?;
+    builder.set_not_after(Asn1Time::days_from_now(SELF_SIGNED_VALID_DAYS)?.as_ref())?;
+    builder.append_extension(BasicConstraints::new().critical().ca().build()?)?;
+    builder.append_extension(KeyUsage::new().critical().digital_signature().key_encipherment().key_cert_sign().build()?)?;
+    builder.append_extension(ExtendedKeyUsage::new().server_auth().build()?)?;
+
+    let mut alt_names = SubjectAlternativeName::new();
+    alt_names.dns("localhost").ip("127.0.0.1").ip("::1");
+    if !host_name.is_empty() && host_name != "localhost" {
+        alt_names.dns(&host_name);
+    }
+    let alt_names = alt_names.build(&builder.x509v3_context(None, None))?;
+    builder.append_extension(alt_names)?;
+
+    builder.sign(&key, MessageDigest::sha256())?;
+    let cert = builder.build();
+
+    //The key is only for us to read
+    let mut key_file = fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(key_path)
+        .map_err(|e| format!("Could not write the tls key: [{}]: {}", key_path, e))?;
+    key_file.write_all(&key.private_key_to_pem_pkcs8()?)?;
+    fs::write(cert_path, cert.to_pem()?)
+        .map_err(|e| format!("Could not write the tls certificate: [{}]: {}", cert_path, e))?;
+
+    Ok(())
+}
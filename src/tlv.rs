@@ -0,0 +1,793 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+
+//Constants --------------------------------------------------------------------------------------------------
+pub const TLV_PODCAST: u64 = 7629169;
+pub const TLV_TIP_NOTE: u64 = 7629171;
+pub const TLV_PODCAST_INDEX: u64 = 133773310;
+pub const TLV_KEYSEND_MESSAGE: u64 = 34349334;
+pub const TLV_KEYSEND_PREIMAGE: u64 = 5482373484;
+const OUTGOING_APP_NAME: &str = "Helipad";
+
+
+//Structs and Enums ------------------------------------------------------------------------------------------
+//Fills in a boost from the value of one custom record type
+pub type RecordDecoder = fn(&mut dbif::BoostRecord, &[u8]);
+
+//The decoders for the custom record types we understand, keyed by tlv type.  They run in the order
+//they were registered, so a later decoder can choose to only fill in what an earlier one left blank.
+//Records with no decoder (ex. the keysend preimage) are still stored, just not decoded.
+pub struct RecordDecoders {
+    decoders: Vec<(u64, RecordDecoder)>,
+}
+
+#[allow(dead_code)]
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct RawBoost {
+    #[serde(default="d_action", deserialize_with="de_optional_string")]
+    action: Option<String>,
+    #[serde(default="d_blank", deserialize_with="de_optional_string")]
+    app_name: Option<String>,
+    #[serde(default="d_blank", deserialize_with="de_optional_string")]
+    app_version: Option<String>,
+    #[serde(default="d_blank", deserialize_with="de_optional_string")]
+    boost_link: Option<String>,
+    #[serde(default="d_blank", deserialize_with="de_optional_string")]
+    message: Option<String>,
+    #[serde(default="d_blank", deserialize_with="de_optional_string")]
+    name: Option<String>,
+    #[serde(default="d_blank", deserialize_with="de_optional_string")]
+    pubkey: Option<String>,
+    #[serde(default="d_blank", deserialize_with="de_optional_string")]
+    sender_key: Option<String>,
+    #[serde(default="d_blank", deserialize_with="de_optional_string")]
+    sender_name: Option<String>,
+    #[serde(default="d_blank", deserialize_with="de_optional_string")]
+    sender_id: Option<String>,
+    #[serde(default="d_blank", deserialize_with="de_optional_string")]
+    sig_fields: Option<String>,
+    #[serde(default="d_blank", deserialize_with="de_optional_string")]
+    signature: Option<String>,
+    #[serde(default="d_blank", deserialize_with="de_optional_string")]
+    speed: Option<String>,
+    #[serde(default="d_blank", deserialize_with="de_optional_string")]
+    uuid: Option<String>,
+    #[serde(default="d_blank", deserialize_with="de_optional_string")]
+    podcast: Option<String>,
+    #[serde(default="d_zero", deserialize_with="de_optional_string_or_number")]
+    feedID: Option<u64>,
+    #[serde(default="d_blank", deserialize_with="de_optional_string")]
+    guid: Option<String>,
+    #[serde(default="d_blank", deserialize_with="de_optional_string")]
+    url: Option<String>,
+    #[serde(default="d_blank", deserialize_with="de_optional_string")]
+    episode: Option<String>,
+    #[serde(default="d_zero", deserialize_with="de_optional_string_or_number")]
+    itemID: Option<u64>,
+    #[serde(default="d_blank", deserialize_with="de_optional_string")]
+    episode_guid: Option<String>,
+    #[serde(default="d_blank", deserialize_with="de_optional_string")]
+    time: Option<String>,
+    #[serde(default="d_zero", deserialize_with="de_optional_string_or_number")]
+    ts: Option<u64>,
+    #[serde(default="d_zero", deserialize_with="de_optional_string_or_number")]
+    value_msat: Option<u64>,
+    #[serde(default="d_zero", deserialize_with="de_optional_string_or_number")]
+    value_msat_total: Option<u64>,
+    #[serde(default="d_blank", deserialize_with="de_optional_string")]
+    reply_address: Option<String>,
+    #[serde(default="d_zero", deserialize_with="de_optional_string_or_number")]
+    reply_custom_key: Option<u64>,
+    #[serde(default="d_blank", deserialize_with="de_optional_string")]
+    reply_custom_value: Option<String>,
+}
+
+//The podcast record we send with a boost of our own, like a reply to a listener
+#[derive(Serialize, Debug, Default)]
+#[allow(non_snake_case)]
+pub struct OutgoingBoost {
+    pub action: String,
+    pub app_name: String,
+    pub app_version: String,
+    pub value_msat: u64,
+    pub value_msat_total: u64,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub sender_name: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub message: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub podcast: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub episode: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub feedID: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub itemID: Option<u64>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub guid: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub episode_guid: String,
+    //Who a split is for
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub name: String,
+}
+
+
+//Traits------------------------------------------------------------------------------------------------------
+fn d_action() -> Option<String> {
+    Some("stream".to_string())
+}
+fn d_blank() -> Option<String> {
+    None
+}
+fn d_zero() -> Option<u64> {
+    None
+}
+
+//Apps don't always agree on types, so take a number from a number or a numeric string.  Anything
+//that isn't a whole number we can make sense of (ex. negative, or not a number at all) is ignored
+//rather than failing the whole record.  Fractions are truncated.
+fn de_optional_string_or_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::String(s) => {
+            let s = s.trim();
+            match s.parse::<u64>() {
+                Ok(num) => Some(num),
+                Err(_) => s.parse::<f64>().ok().and_then(u64_from_f64),
+            }
+        },
+        Value::Number(num) => {
+            match num.as_u64() {
+                Some(num) => Some(num),
+                None => num.as_f64().and_then(u64_from_f64),
+            }
+        },
+        _ => None
+    })
+}
+
+//Take text from a string, or from a number or bool written where a string was expected
+fn de_optional_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::String(s) => Some(s),
+        Value::Number(num) => Some(num.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None
+    })
+}
+
+fn u64_from_f64(num: f64) -> Option<u64> {
+    if num.is_finite() && num >= 0.0 && num < u64::MAX as f64 {
+        Some(num as u64)
+    } else {
+        None
+    }
+}
+
+
+//Functions --------------------------------------------------------------------------------------------------
+impl RecordDecoders {
+    pub fn new() -> RecordDecoders {
+        RecordDecoders {
+            decoders: Vec::new(),
+        }
+    }
+
+    //Add a decoder for a record type.  Registering a type again replaces its decoder.
+    pub fn register(&mut self, record_type: u64, decoder: RecordDecoder) {
+        match self.decoders.iter_mut().find(|(t, _)| *t == record_type) {
+            Some(entry) => entry.1 = decoder,
+            None => self.decoders.push((record_type, decoder)),
+        }
+    }
+
+    //Run each decoder over the record of its type from the lowest numbered htlc.  Multi-part payments
+    //should repeat the same records on every htlc, so when the copies disagree the boost is flagged.
+    pub fn decode(&self, boost: &mut dbif::BoostRecord, records: &[dbif::CustomRecord]) {
+        boost.tlv_conflict = false;
+        for (record_type, decoder) in &self.decoders {
+            let mut copies = records.iter().filter(|r| r.record_type == *record_type);
+            if let Some(record) = copies.clone().min_by_key(|r| r.htlc_index) {
+                if copies.any(|r| r.value != record.value) {
+                    eprintln!("The htlcs of payment: [{}] carry different values for record type: [{}].",
+                              boost.index, record_type);
+                    boost.tlv_conflict = true;
+                }
+                decoder(boost, &record.value);
+            }
+        }
+    }
+}
+
+impl Default for RecordDecoders {
+    fn default() -> RecordDecoders {
+        let mut decoders = RecordDecoders::new();
+        decoders.register(TLV_PODCAST, apply_podcast_record);
+        decoders.register(TLV_PODCAST_INDEX, apply_podcast_index_record);
+        decoders.register(TLV_TIP_NOTE, apply_message_record);
+        decoders.register(TLV_KEYSEND_MESSAGE, apply_message_record);
+        decoders
+    }
+}
+
+//Store the raw podcast record on a boost and fill in what we can from it.  This never fails: a
+//record we can't read is kept (hex encoded if it isn't utf-8) along with the reason, so the
+//payment itself is always recorded.
+pub fn apply_podcast_record(boost: &mut dbif::BoostRecord, record: &[u8]) {
+    match std::str::from_utf8(record) {
+        Ok(tlv) => {
+            boost.tlv = tlv.to_string();
+            boost.tlv_hex = false;
+        }
+        Err(e) => {
+            boost.tlv = hex::encode(record);
+            boost.tlv_hex = true;
+            boost.tlv_error = format!("Record is not valid utf-8 ({}), stored as hex.", e);
+            //It's still a podcast payment, we just can't tell what kind
+            boost.action = dbif::ACTION_UNKNOWN;
+            eprintln!("{}", boost.tlv_error);
+            return;
+        }
+    }
+
+    //Only take an object, since serde would otherwise happily read the fields from an array
+    let parsed = serde_json::from_str::<serde_json::Map<String, Value>>(boost.tlv.as_str())
+        .and_then(|fields| RawBoost::deserialize(Value::Object(fields)));
+    match parsed {
+        Ok(rawboost) => {
+            boost.tlv_error = "".to_string();
+            //If there was a sat value in the tlv, override the invoice
+            if let Some(value_msat) = rawboost.value_msat {
+                boost.value_msat = i64::try_from(value_msat).unwrap_or(i64::MAX);
+            }
+            //Look for an original sat value in the tlv
+            if let Some(value_msat_total) = rawboost.value_msat_total {
+                boost.value_msat_total = i64::try_from(value_msat_total).unwrap_or(i64::MAX);
+            }
+            //Determine an action type for later filtering ability
+            boost.action = match rawboost.action.map(|a| a.trim().to_lowercase()).as_deref() {
+                Some("stream") => dbif::ACTION_STREAM,
+                Some("boost") => dbif::ACTION_BOOST,
+                _ => dbif::ACTION_UNKNOWN,
+            };
+            //Who sent it, from which app, and what they had to say
+            boost.sender = rawboost.sender_name.unwrap_or_default();
+            boost.message = rawboost.message.unwrap_or_default();
+            boost.app = rawboost.app_name.unwrap_or_default();
+            //Which podcast and episode it was for
+            boost.podcast = rawboost.podcast.unwrap_or_default();
+            boost.episode = rawboost.episode.unwrap_or_default();
+            //Everything else identifying the feed, episode, sender and app
+            boost.feed_id = rawboost.feedID;
+            boost.item_id = rawboost.itemID;
+            boost.ts = rawboost.ts;
+            boost.guid = rawboost.guid.unwrap_or_default();
+            boost.episode_guid = rawboost.episode_guid.unwrap_or_default();
+            boost.episode_time = rawboost.time.unwrap_or_default();
+            boost.url = rawboost.url.unwrap_or_default();
+            boost.speed = rawboost.speed.unwrap_or_default();
+            boost.uuid = rawboost.uuid.unwrap_or_default();
+            boost.boost_link = rawboost.boost_link.unwrap_or_default();
+            boost.sender_id = rawboost.sender_id.unwrap_or_default();
+            boost.app_version = rawboost.app_version.unwrap_or_default();
+            boost.name = rawboost.name.unwrap_or_default();
+            boost.signature = rawboost.signature.unwrap_or_default();
+            //Where the sender takes boosts in reply
+            boost.reply_address = rawboost.reply_address.map(|a| a.trim().to_string()).unwrap_or_default();
+            boost.reply_custom_key = rawboost.reply_custom_key;
+            boost.reply_custom_value = rawboost.reply_custom_value.unwrap_or_default();
+        }
+        Err(e) => {
+            //It's still a podcast payment, we just can't tell what kind
+            boost.action = dbif::ACTION_UNKNOWN;
+            boost.tlv_error = format!("Record is not a podcast json object: {}", e);
+            eprintln!("{}", boost.tlv_error);
+        }
+    }
+}
+
+//Some apps send the podcast json under the podcast index type instead.  When both are present the
+//standard record wins.
+fn apply_podcast_index_record(boost: &mut dbif::BoostRecord, record: &[u8]) {
+    if boost.tlv.is_empty() {
+        apply_podcast_record(boost, record);
+    }
+}
+
+//A plain text note from the sender, used when the podcast record didn't carry a message
+fn apply_message_record(boost: &mut dbif::BoostRecord, record: &[u8]) {
+    if boost.message.is_empty() {
+        boost.message = String::from_utf8_lossy(record).trim().to_string();
+    }
+}
+
+impl OutgoingBoost {
+    //A boost of value_msat from us, about the same podcast and episode as the boost it replies to
+    pub fn reply_to(boost: &dbif::BoostRecord, value_msat: u64, sender_name: &str, message: &str) -> OutgoingBoost {
+        OutgoingBoost {
+            action: "boost".to_string(),
+            app_name: OUTGOING_APP_NAME.to_string(),
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            value_msat,
+            value_msat_total: value_msat,
+            sender_name: sender_name.to_string(),
+            message: message.to_string(),
+            podcast: boost.podcast.clone(),
+            episode: boost.episode.clone(),
+            feedID: boost.feed_id,
+            itemID: boost.item_id,
+            guid: boost.guid.clone(),
+            episode_guid: boost.episode_guid.clone(),
+            ..Default::default()
+        }
+    }
+
+    //A split of value_msat passed on from a payment we received.  It keeps the listener's action,
+    //name and message, so the recipient sees the boost as it was sent.
+    pub fn split_of(boost: &dbif::BoostRecord, value_msat: u64, name: &str) -> OutgoingBoost {
+        OutgoingBoost {
+            action: dbif::action_name(boost.action).to_string(),
+            value_msat_total: boost.value_msat_total.max(0) as u64,
+            sender_name: boost.sender.clone(),
+            message: boost.message.clone(),
+            name: name.to_string(),
+            ..OutgoingBoost::reply_to(boost, value_msat, "", "")
+        }
+    }
+}
+
+//The custom records for a keysend payment: the podcast record, the preimage the recipient's node
+//needs to settle it, and the record that routes it to the recipient's wallet if they gave one
+pub fn keysend_records(podcast_record: &[u8], preimage: &[u8], custom_record: Option<(u64, &[u8])>) -> HashMap<u64, Vec<u8>> {
+    let mut records = HashMap::new();
+    records.insert(TLV_PODCAST, podcast_record.to_vec());
+    records.insert(TLV_KEYSEND_PREIMAGE, preimage.to_vec());
+    if let Some((record_type, value)) = custom_record {
+        records.insert(record_type, value.to_vec());
+    }
+
+    records
+}
+
+//Fill in what a payment we sent was from the custom records it carried, the same way they're read
+//on payments we receive.  What's already set on the payment (ex. the sender of a reply) is kept.
+pub fn describe_sent(payment: &mut dbif::OutgoingPayment, records: &HashMap<u64, Vec<u8>>) {
+    let mut decoded = dbif::BoostRecord {
+        action: dbif::ACTION_KEYSEND,
+        ..Default::default()
+    };
+    let custom_records: Vec<dbif::CustomRecord> = records.iter()
+        .map(|(record_type, value)| dbif::CustomRecord {
+            boost_index: 0,
+            htlc_index: 0,
+            record_type: *record_type,
+            value: value.clone(),
+        })
+        .collect();
+    RecordDecoders::default().decode(&mut decoded, &custom_records);
+
+    payment.action = decoded.action;
+    payment.podcast = decoded.podcast;
+    payment.episode = decoded.episode;
+    payment.app = decoded.app;
+    payment.feed_id = decoded.feed_id;
+    if payment.sender.is_empty() {
+        payment.sender = decoded.sender;
+    }
+    if payment.message.is_empty() {
+        payment.message = decoded.message;
+    }
+    if payment.tlv.is_empty() {
+        payment.tlv = decoded.tlv;
+    }
+}
+
+//Whether a record type is one of the ones podcast apps send along with the payment itself, as
+//opposed to one that routes it to a wallet
+pub fn is_podcast_record_type(record_type: u64) -> bool {
+    matches!(record_type, TLV_PODCAST | TLV_TIP_NOTE | TLV_PODCAST_INDEX | TLV_KEYSEND_MESSAGE | TLV_KEYSEND_PREIMAGE)
+}
+
+//Clear everything the decoders fill in on a stored boost, so decoding its records again gives what
+//a new payment would get rather than keeping values from records that are no longer read.  The
+//action goes back to what the payment was before any podcast record: a keysend payment carries its
+//preimage, anything else keeps being a regular invoice if it was stored as one.
+pub fn clear_decoded(boost: &mut dbif::BoostRecord, records: &[dbif::CustomRecord]) {
+    let keysend = records.iter().any(|r| r.record_type == TLV_KEYSEND_PREIMAGE);
+    let action = if !keysend && boost.action == dbif::ACTION_INVOICE { dbif::ACTION_INVOICE } else { dbif::ACTION_KEYSEND };
+
+    *boost = dbif::BoostRecord {
+        index: boost.index,
+        settle_index: boost.settle_index,
+        state: boost.state,
+        payment_hash: std::mem::take(&mut boost.payment_hash),
+        time: boost.time,
+        value_msat: boost.value_msat,
+        value_msat_total: boost.value_msat,
+        action,
+        read: boost.read,
+        starred: boost.starred,
+        archived: boost.archived,
+        note: std::mem::take(&mut boost.note),
+        ..Default::default()
+    };
+}
+
+//The raw bytes of the podcast record stored on a boost, for decoding it again
+pub fn stored_podcast_record(boost: &dbif::BoostRecord) -> Vec<u8> {
+    if boost.tlv_hex {
+        hex::decode(&boost.tlv).unwrap_or_default()
+    } else {
+        boost.tlv.as_bytes().to_vec()
+    }
+}
+
+
+//Tests ------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::fs;
+
+    const CORPUS_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tlv");
+
+    //A settled keysend payment of 1000 sats for a record to be applied to
+    fn payment() -> dbif::BoostRecord {
+        dbif::BoostRecord {
+            index: 1,
+            state: dbif::INVOICE_STATE_SETTLED,
+            value_msat: 1000000,
+            value_msat_total: 1000000,
+            action: dbif::ACTION_KEYSEND,
+            ..Default::default()
+        }
+    }
+
+    fn decode(record: &[u8]) -> dbif::BoostRecord {
+        let mut boost = payment();
+        apply_podcast_record(&mut boost, record);
+        boost
+    }
+
+    fn record(record_type: u64, value: &[u8]) -> dbif::CustomRecord {
+        dbif::CustomRecord {
+            boost_index: 1,
+            htlc_index: 0,
+            record_type,
+            value: value.to_vec(),
+        }
+    }
+
+    fn decode_corpus_file(name: &str) -> dbif::BoostRecord {
+        decode(&fs::read(format!("{}/{}", CORPUS_PATH, name)).unwrap())
+    }
+
+    #[test]
+    fn every_corpus_record_is_kept() {
+        for entry in fs::read_dir(CORPUS_PATH).unwrap() {
+            let record = fs::read(entry.unwrap().path()).unwrap();
+            let boost = decode(&record);
+
+            assert_eq!(stored_podcast_record(&boost), record);
+            assert!(boost.action == dbif::ACTION_STREAM || boost.action == dbif::ACTION_BOOST || boost.action == dbif::ACTION_UNKNOWN);
+        }
+    }
+
+    #[test]
+    fn well_formed_boost() {
+        let boost = decode_corpus_file("fountain_boost.json");
+
+        assert_eq!(boost.tlv_error, "");
+        assert_eq!(boost.action, dbif::ACTION_BOOST);
+        assert_eq!(boost.value_msat, 1000000);
+        assert_eq!(boost.value_msat_total, 10000000);
+        assert_eq!(boost.sender, "sirlisten");
+        assert_eq!(boost.message, "Boosting from the beach!");
+        assert_eq!(boost.feed_id, Some(920666));
+        assert_eq!(boost.item_id, Some(13124245655));
+        assert_eq!(boost.ts, Some(1834));
+        assert_eq!(boost.episode_time, "00:30:34");
+        assert_eq!(boost.episode_guid, "PC2107");
+    }
+
+    #[test]
+    fn reply_address_is_kept() {
+        let boost = decode_corpus_file("fountain_reply_address.json");
+
+        assert_eq!(boost.tlv_error, "");
+        assert_eq!(boost.reply_address, "030a58b8653d32b99200a2334cfe913e51dc7d155aa0116c176657a4f1722677a3");
+        assert_eq!(boost.reply_custom_key, Some(696969));
+        assert_eq!(boost.reply_custom_value, "eChoVKtO1KujpAA5HCoB");
+    }
+
+    #[test]
+    fn replies_carry_the_podcast_record_and_preimage() {
+        let boost = decode_corpus_file("fountain_reply_address.json");
+        let reply = OutgoingBoost::reply_to(&boost, 5000, "Adam", "Thanks!");
+        let podcast_record = serde_json::to_vec(&reply).unwrap();
+
+        let fields: serde_json::Map<String, Value> = serde_json::from_slice(&podcast_record).unwrap();
+        assert_eq!(fields["action"], "boost");
+        assert_eq!(fields["podcast"], "Podcasting 2.0");
+        assert_eq!(fields["feedID"], 920666);
+        assert_eq!(fields["value_msat"], 5000);
+        assert!(!fields.contains_key("guid"));
+
+        let records = keysend_records(&podcast_record, &[7u8; 32], Some((696969, b"eChoVKtO1KujpAA5HCoB")));
+        assert_eq!(records[&TLV_PODCAST], podcast_record);
+        assert_eq!(records[&TLV_KEYSEND_PREIMAGE], vec![7u8; 32]);
+        assert_eq!(records[&696969], b"eChoVKtO1KujpAA5HCoB".to_vec());
+    }
+
+    #[test]
+    fn missing_action_is_a_stream() {
+        let boost = decode_corpus_file("castamatic_no_action.json");
+
+        assert_eq!(boost.tlv_error, "");
+        assert_eq!(boost.action, dbif::ACTION_STREAM);
+        assert_eq!(boost.value_msat, 1000000);
+        assert_eq!(boost.value_msat_total, 100000);
+    }
+
+    #[test]
+    fn numbers_in_strings() {
+        let boost = decode_corpus_file("podverse_numeric_strings.json");
+
+        assert_eq!(boost.tlv_error, "");
+        assert_eq!(boost.feed_id, Some(1234567));
+        assert_eq!(boost.item_id, Some(987654321));
+        assert_eq!(boost.ts, Some(512));
+        assert_eq!(boost.value_msat, 50000);
+        assert_eq!(boost.speed, "1.5");
+    }
+
+    #[test]
+    fn fractions_are_truncated() {
+        let boost = decode_corpus_file("curiocaster_float_numbers.json");
+
+        assert_eq!(boost.tlv_error, "");
+        assert_eq!(boost.item_id, Some(1520312));
+        assert_eq!(boost.ts, Some(1206));
+        assert_eq!(boost.value_msat, 1000);
+        assert_eq!(boost.value_msat_total, 2000);
+    }
+
+    #[test]
+    fn negative_numbers_are_ignored() {
+        let boost = decode_corpus_file("negative_numbers.json");
+
+        assert_eq!(boost.tlv_error, "");
+        assert_eq!(boost.action, dbif::ACTION_BOOST);
+        assert_eq!(boost.feed_id, None);
+        assert_eq!(boost.ts, None);
+        assert_eq!(boost.value_msat, 1000000);
+        assert_eq!(boost.value_msat_total, 1000000);
+    }
+
+    #[test]
+    fn out_of_range_numbers_are_ignored() {
+        let boost = decode_corpus_file("huge_numbers.json");
+
+        assert_eq!(boost.tlv_error, "");
+        assert_eq!(boost.feed_id, None);
+        assert_eq!(boost.ts, None);
+        assert_eq!(boost.value_msat, i64::MAX);
+        assert_eq!(boost.value_msat_total, 1000000);
+    }
+
+    #[test]
+    fn scalars_where_text_was_expected() {
+        let boost = decode_corpus_file("wrong_types.json");
+
+        assert_eq!(boost.tlv_error, "");
+        assert_eq!(boost.action, dbif::ACTION_BOOST);
+        assert_eq!(boost.app, "12");
+        assert_eq!(boost.podcast, "123");
+        assert_eq!(boost.episode, "false");
+        assert_eq!(boost.message, "42");
+        assert_eq!(boost.sender, "true");
+        assert_eq!(boost.url, "");
+        assert_eq!(boost.feed_id, None);
+        assert_eq!(boost.item_id, None);
+        assert_eq!(boost.value_msat, 1000000);
+    }
+
+    #[test]
+    fn null_action_is_unknown() {
+        let boost = decode_corpus_file("nulls.json");
+
+        assert_eq!(boost.tlv_error, "");
+        assert_eq!(boost.action, dbif::ACTION_UNKNOWN);
+        assert_eq!(boost.value_msat, 1000000);
+    }
+
+    #[test]
+    fn action_case_and_whitespace() {
+        let boost = decode_corpus_file("uppercase_action.json");
+
+        assert_eq!(boost.action, dbif::ACTION_BOOST);
+    }
+
+    #[test]
+    fn unicode_text() {
+        let boost = decode_corpus_file("unicode_message.json");
+
+        assert_eq!(boost.tlv_error, "");
+        assert_eq!(boost.sender, "José 🚀");
+        assert_eq!(boost.message, "Ñandú says 你好 — boost! ⚡️⚡️⚡️");
+    }
+
+    #[test]
+    fn unreadable_records_keep_the_payment() {
+        for name in ["not_an_object.json", "truncated.json", "empty.json"] {
+            let boost = decode_corpus_file(name);
+
+            assert!(!boost.tlv_hex, "{}", name);
+            assert_ne!(boost.tlv_error, "", "{}", name);
+            assert_eq!(boost.action, dbif::ACTION_UNKNOWN, "{}", name);
+            assert_eq!(boost.value_msat, 1000000, "{}", name);
+        }
+    }
+
+    #[test]
+    fn non_utf8_is_stored_as_hex() {
+        let boost = decode_corpus_file("not_utf8.json");
+
+        assert!(boost.tlv_hex);
+        assert_ne!(boost.tlv_error, "");
+        assert_eq!(boost.action, dbif::ACTION_UNKNOWN);
+        assert!(boost.tlv.starts_with("7b22616374696f6e22"));
+        assert_eq!(boost.value_msat, 1000000);
+    }
+
+    #[test]
+    fn reparsing_clears_an_old_error() {
+        let mut boost = decode(b"{\"action\":\"boost\"");
+        assert_ne!(boost.tlv_error, "");
+
+        apply_podcast_record(&mut boost, b"{\"action\":\"boost\"}");
+        assert_eq!(boost.tlv_error, "");
+        assert_eq!(boost.action, dbif::ACTION_BOOST);
+    }
+
+    #[test]
+    fn reparsing_drops_what_a_missing_record_said() {
+        let decoders = RecordDecoders::default();
+        let mut boost = payment();
+        decoders.decode(&mut boost, &[
+            record(TLV_PODCAST, br#"{"action":"boost","sender_name":"alice","app_name":"Fountain","podcast":"PC20","episode":"1","feedID":920666,"message":"hi"}"#),
+            record(TLV_KEYSEND_PREIMAGE, &[7; 32]),
+        ]);
+        boost.starred = true;
+        assert_eq!(boost.action, dbif::ACTION_BOOST);
+        assert_eq!(boost.feed_id, Some(920666));
+
+        //The podcast record is gone the second time, only the keysend message is left
+        let records = [record(TLV_KEYSEND_MESSAGE, b"just a note"), record(TLV_KEYSEND_PREIMAGE, &[7; 32])];
+        clear_decoded(&mut boost, &records);
+        decoders.decode(&mut boost, &records);
+        assert_eq!(boost.action, dbif::ACTION_KEYSEND);
+        assert_eq!(boost.message, "just a note");
+        assert_eq!((boost.sender.as_str(), boost.app.as_str()), ("", ""));
+        assert_eq!((boost.podcast.as_str(), boost.episode.as_str()), ("", ""));
+        assert_eq!(boost.feed_id, None);
+        assert_eq!(boost.tlv, "");
+        assert!(boost.starred);
+    }
+
+    #[test]
+    fn keysend_message_fills_an_empty_message() {
+        let mut boost = payment();
+        RecordDecoders::default().decode(&mut boost, &[
+            record(TLV_KEYSEND_MESSAGE, b" hello from sphinx "),
+            record(TLV_KEYSEND_PREIMAGE, &[7; 32]),
+        ]);
+        assert_eq!(boost.message, "hello from sphinx");
+        assert_eq!(boost.action, dbif::ACTION_KEYSEND);
+    }
+
+    #[test]
+    fn podcast_message_wins_over_notes() {
+        let mut boost = payment();
+        RecordDecoders::default().decode(&mut boost, &[
+            record(TLV_TIP_NOTE, b"tip note"),
+            record(TLV_PODCAST, br#"{"action":"boost","message":"from the podcast record","sender_name":"alice"}"#),
+        ]);
+        assert_eq!(boost.message, "from the podcast record");
+        assert_eq!(boost.sender, "alice");
+    }
+
+    #[test]
+    fn podcast_index_record_is_a_fallback() {
+        let mut boost = payment();
+        RecordDecoders::default().decode(&mut boost, &[
+            record(TLV_PODCAST_INDEX, br#"{"action":"boost","sender_name":"bob"}"#),
+        ]);
+        assert_eq!(boost.sender, "bob");
+        assert_eq!(boost.action, dbif::ACTION_BOOST);
+
+        let mut boost = payment();
+        RecordDecoders::default().decode(&mut boost, &[
+            record(TLV_PODCAST_INDEX, br#"{"action":"boost","sender_name":"bob"}"#),
+            record(TLV_PODCAST, br#"{"action":"stream","sender_name":"alice"}"#),
+        ]);
+        assert_eq!(boost.sender, "alice");
+        assert_eq!(boost.action, dbif::ACTION_STREAM);
+    }
+
+    #[test]
+    fn disagreeing_htlcs_are_flagged() {
+        let mut boost = payment();
+        let mut second = record(TLV_PODCAST, br#"{"action":"boost","sender_name":"mallory"}"#);
+        second.htlc_index = 1;
+        RecordDecoders::default().decode(&mut boost, &[
+            second,
+            record(TLV_PODCAST, br#"{"action":"boost","sender_name":"alice"}"#),
+        ]);
+        assert!(boost.tlv_conflict);
+        assert_eq!(boost.sender, "alice");
+
+        let mut boost = payment();
+        let mut second = record(TLV_PODCAST, br#"{"action":"boost","sender_name":"alice"}"#);
+        second.htlc_index = 1;
+        RecordDecoders::default().decode(&mut boost, &[
+            record(TLV_PODCAST, br#"{"action":"boost","sender_name":"alice"}"#),
+            second,
+        ]);
+        assert!(!boost.tlv_conflict);
+    }
+
+    //Any json scalar, which is what apps put in their records
+    fn json_scalar() -> impl Strategy<Value = Value> {
+        prop_oneof![
+            Just(Value::Null),
+            any::<bool>().prop_map(Value::Bool),
+            any::<i64>().prop_map(Value::from),
+            any::<u64>().prop_map(Value::from),
+            any::<f64>().prop_map(Value::from),
+            ".*".prop_map(Value::String),
+            "[0-9]{1,25}(\\.[0-9]{0,5})?".prop_map(Value::String),
+        ]
+    }
+
+    fn json_field() -> impl Strategy<Value = String> {
+        prop_oneof![
+            Just("action".to_string()),
+            Just("app_name".to_string()),
+            Just("message".to_string()),
+            Just("sender_name".to_string()),
+            Just("podcast".to_string()),
+            Just("feedID".to_string()),
+            Just("itemID".to_string()),
+            Just("ts".to_string()),
+            Just("time".to_string()),
+            Just("value_msat".to_string()),
+            Just("value_msat_total".to_string()),
+            "[a-z_]{1,12}",
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn arbitrary_bytes_never_panic(record in proptest::collection::vec(any::<u8>(), 0..512)) {
+            let boost = decode(&record);
+
+            prop_assert_eq!(stored_podcast_record(&boost), record);
+        }
+
+        #[test]
+        fn any_json_object_decodes(fields in proptest::collection::hash_map(json_field(), json_scalar(), 0..12)) {
+            let record = serde_json::to_vec(&fields).unwrap();
+            let boost = decode(&record);
+
+            prop_assert_eq!(boost.tlv_error, "");
+            prop_assert!(boost.value_msat >= 0);
+            prop_assert!(boost.value_msat_total >= 0);
+        }
+    }
+}
@@ -9,24 +9,39 @@ $(document).ready(function () {
     var intvlChatPolling = null;
     var connection = null;
     var messageIds = [];
+    var liveBoosts = null;
 
-    getBoosts();
+    //Our login session ran out, so log in again
+    $.ajaxSetup({
+        statusCode: {
+            401: function () {
+                window.location = '/login';
+            }
+        }
+    });
 
-    function getBoosts(startIndex, max, scrollToTop, old) {
-        var noIndex = false;
+    getBoosts();
+    getNodeWarnings();
 
-        //Find newest index
-        let lastIndex = $('div.outgoing_msg:first').data('msgid');
-        if (typeof lastIndex === "undefined") {
-            lastIndex = "";
-        }
-        //console.log("Last index: ["+lastIndex+"]");
-        let firstIndex = $('div.outgoing_msg:last').data('msgid');
-        if (typeof firstIndex === "undefined") {
-            firstIndex = "";
-        }
-        // console.log("First index: ["+firstIndex+"]");
+    //Show what the node says needs looking at, like running low on inbound liquidity
+    function getNodeWarnings() {
+        $.ajax({
+            url: '/node',
+            type: "GET",
+            contentType: "application/json; charset=utf-8",
+            dataType: "json",
+            success: function (node) {
+                let warnings = $('div.node_warnings').empty();
+                node.warnings.forEach((warning) => {
+                    warnings.append($('<div class="alert alert-warning">').text(warning));
+                });
+            }
+        });
+    }
 
+    //Load a page of boosts.  With no cursor this is the newest page, otherwise the cursor says which
+    //side of an index to page from (ex. {before: 1234} for older boosts).
+    function getBoosts(cursor, max, scrollToTop) {
         //Get current id set
         messageIds = [];
         $('div.outgoing_msg').map(function () {
@@ -35,17 +50,6 @@ $(document).ready(function () {
         // console.log(messageIds);
 
         //Params
-        if (typeof startIndex === "number") {
-            boostIndex = startIndex;
-        } else {
-            boostIndex = lastIndex;
-        }
-        if(typeof boostIndex !== "number") {
-            noIndex = true;
-        }
-        if(startIndex === null) {
-            boostIndex = "";
-        }
         if (typeof max !== "number") {
             max = 0;
         }
@@ -54,12 +58,15 @@ $(document).ready(function () {
         }
 
         //Build the endpoint url
-        var url = '/boosts?index=' + boostIndex;
-        if(max > 0) {
-            url += '&count=' + max;
+        var url = '/boosts?';
+        if (cursor && typeof cursor.before === "number") {
+            url += 'before=' + cursor.before + '&';
         }
-        if(old) {
-            url += '&old=true';
+        if (cursor && typeof cursor.after === "number") {
+            url += 'after=' + cursor.after + '&';
+        }
+        if (max > 0) {
+            url += 'count=' + max;
         }
 
         $.ajax({
@@ -68,142 +75,232 @@ $(document).ready(function () {
             contentType: "application/json; charset=utf-8",
             dataType: "json",
             success: function (data) {
-                data.forEach((element, index) => {
-                    let displayedMessageCount = $('div.outgoing_msg').length;
-                    //console.log(element);
-                    let boostMessage = element.message || "";
-                    let boostSats = Math.trunc(element.value_msat_total / 1000) || Math.trunc(element.value_msat / 1000);
-                    let boostIndex = element.index;
-                    let boostAction = element.action;
-                    let boostSender = element.sender;
-                    let boostApp = element.app;
-                    let boostPodcast = element.podcast;
-                    let boostEpisode = element.episode;
-
-                    //Icon
-                    var appIconUrl = "";
-                    switch (boostApp.toLowerCase()) {
-                        case 'fountain':
-                            appIconUrl = appIconUrlBase + 'fountain';
-                            break;
-                        case 'podfriend':
-                            appIconUrl = appIconUrlBase + 'podfriend';
-                            break;
-                        case 'castamatic':
-                            appIconUrl = appIconUrlBase + 'castamatic';
-                            break;
-                        case 'curiocaster':
-                            appIconUrl = appIconUrlBase + 'curiocaster';
-                            break;
-                        case 'breez':
-                            appIconUrl = appIconUrlBase + 'breez';
-                            break;
-                        case 'podstation':
-                            appIconUrl = appIconUrlBase + 'podstation';
-                            break;
-                        case 'sphinx':
-                            appIconUrl = appIconUrlBase + 'sphinxchat';
-                            break;
-                        case 'podverse':
-                            appIconUrl = appIconUrlBase + 'podverse';
-                            break;
-                        case 'zion':
-                            appIconUrl = appIconUrlBase + 'zion';
-                            break;
+                data.boosts.forEach((element, index) => {
+                    addBoost(element, scrollToTop);
+                });
 
+                //Show a message if still building
+                if ($('div.outgoing_msg').length == 0 && $('div.nodata').length == 0) {
+                    inbox.prepend('<div class="nodata">No data to show yet. Building the initial database may take some time...</div>');
+                }
+
+                //Load more link, unless this was a page of newer boosts
+                if (!cursor || typeof cursor.after !== "number") {
+                    $('div.loadmore').remove();
+                    if (data.next !== null) {
+                        inbox.append('<div class="loadmore"><a href="#" data-before="' + data.next + '">Show older boosts...</a></div>');
                     }
+                }
 
-                    if (!messageIds.includes(boostIndex) && element.action == 2) {
-                        let dateTime = new Date(element.time * 1000).toISOString();
-                        $('div.nodata').remove();
-
-                        //Build the message element
-                        elMessage = '' +
-                            '<div class="outgoing_msg message" data-msgid="' + boostIndex + '">' +
-                            '  <div class="sent_msg">' +
-                            '    <div class="sent_withd_msg">' +
-                            '      <span class="app"><img src="' + appIconUrl + '"></span>' +
-                            '      <h5>' + boostSats + ' sats <small>from ' + boostSender + '</small></h5>' +
-                            '      <span class="time_date" data-timestamp="' + dateTime + '">' + prettyDate(dateTime) + '</span>' +
-                            '      <small class="podcast_episode">' + boostPodcast + ' - ' + boostEpisode + '</small>' +
-                            '      <br>' +
-                            '      <hr>' +
-                            '      <p>' + boostMessage + '</p>' +
-                            '    </div>' +
-                            '  </div>' +
-                            '</div>';
-
-                        //Insert the message in the right spot
-                        if(displayedMessageCount == 0) {
-                            inbox.prepend(elMessage);
-                            //Scroll the list back up if necessary
-                            if (scrollToTop) {
-                                inbox.scrollTop();
-                            }
-                        } else {
-                            //Get the closest matching id
-                            var prepend = false;
-                            let closestId = closest(messageIds, boostIndex);
-                            if(boostIndex < closestId) {
-                                prepend = true;
-                            }
+                //Once the first page is in, follow along live
+                followBoosts();
+            }
+        });
 
-                            if(prepend) {
-                                $('div.outgoing_msg[data-msgid='+closestId+']').after(elMessage);
-                            } else {
-                                $('div.outgoing_msg[data-msgid='+closestId+']').before(elMessage);
-                            }
 
-                        }
+    }
 
-                        //Update the tracking array
-                        messageIds.push(boostIndex);
-                        messageIds = messageIds.sort((a,b) => a-b);
+    //Render a boost into the message list unless it's already there
+    function addBoost(element, scrollToTop) {
+        let displayedMessageCount = $('div.outgoing_msg').length;
+        //console.log(element);
+        let boostMessage = element.message || "";
+        let boostSats = Math.trunc(element.value_msat_total / 1000) || Math.trunc(element.value_msat / 1000);
+        let boostIndex = element.index;
+        let boostAction = element.action;
+        let boostSender = element.sender;
+        let boostApp = element.app;
+        let boostPodcast = element.podcast;
+        let boostEpisode = element.episode;
 
-                        //Pew pew pew!
-                        pewAudio.play();
-                    }
-                });
+        //Icon
+        var appIconUrl = "";
+        switch (boostApp.toLowerCase()) {
+            case 'fountain':
+                appIconUrl = appIconUrlBase + 'fountain';
+                break;
+            case 'podfriend':
+                appIconUrl = appIconUrlBase + 'podfriend';
+                break;
+            case 'castamatic':
+                appIconUrl = appIconUrlBase + 'castamatic';
+                break;
+            case 'curiocaster':
+                appIconUrl = appIconUrlBase + 'curiocaster';
+                break;
+            case 'breez':
+                appIconUrl = appIconUrlBase + 'breez';
+                break;
+            case 'podstation':
+                appIconUrl = appIconUrlBase + 'podstation';
+                break;
+            case 'sphinx':
+                appIconUrl = appIconUrlBase + 'sphinxchat';
+                break;
+            case 'podverse':
+                appIconUrl = appIconUrlBase + 'podverse';
+                break;
+            case 'zion':
+                appIconUrl = appIconUrlBase + 'zion';
+                break;
 
-                //Show a message if still building
-                if ($('div.outgoing_msg').length == 0 && $('div.nodata').length == 0) {
-                    inbox.prepend('<div class="nodata">No data to show yet. Building the initial database may take some time...</div>');
+        }
+
+        if (!messageIds.includes(boostIndex) && element.action == 2) {
+            let dateTime = new Date(element.time * 1000).toISOString();
+            $('div.nodata').remove();
+
+            //Boosts that carry a reply address can be boosted back
+            var elReply = '';
+            if (element.reply_address) {
+                elReply = '<a href="#" class="reply" data-msgid="' + boostIndex + '">Boost back</a>';
+            }
+
+            //Build the message element
+            elMessage = '' +
+                '<div class="outgoing_msg message" data-msgid="' + boostIndex + '">' +
+                '  <div class="sent_msg">' +
+                '    <div class="sent_withd_msg">' +
+                '      <span class="app"><img src="' + appIconUrl + '"></span>' +
+                '      <h5>' + boostSats + ' sats <small>from ' + boostSender + '</small></h5>' +
+                '      <span class="time_date" data-timestamp="' + dateTime + '">' + prettyDate(dateTime) + '</span>' +
+                '      <small class="podcast_episode">' + boostPodcast + ' - ' + boostEpisode + '</small>' +
+                '      <br>' +
+                '      <hr>' +
+                '      <p>' + boostMessage + '</p>' +
+                '      ' + elReply +
+                '    </div>' +
+                '  </div>' +
+                '</div>';
+
+            //Insert the message in the right spot
+            if(displayedMessageCount == 0) {
+                inbox.prepend(elMessage);
+                //Scroll the list back up if necessary
+                if (scrollToTop) {
+                    inbox.scrollTop();
+                }
+            } else {
+                //Get the closest matching id
+                var prepend = false;
+                let closestId = closest(messageIds, boostIndex);
+                if(boostIndex < closestId) {
+                    prepend = true;
                 }
 
-                //Load more link
-                if ($('div.outgoing_msg').length > 0 && $('div.loadmore').length == 0 && ( boostIndex > 1 || noIndex)) {
-                    inbox.append('<div class="loadmore"><a href="#">Show older boosts...</a></div>');
+                if(prepend) {
+                    $('div.outgoing_msg[data-msgid='+closestId+']').after(elMessage);
+                } else {
+                    $('div.outgoing_msg[data-msgid='+closestId+']').before(elMessage);
                 }
+
             }
-        });
 
+            //Update the tracking array
+            messageIds.push(boostIndex);
+            messageIds = messageIds.sort((a,b) => a-b);
 
+            //Pew pew pew!
+            pewAudio.play();
+        }
     }
 
     //Load more messages handler
     $(document).on('click', 'div.loadmore a', function () {
-        var old = true;
-        let boostIndex = $('div.outgoing_msg:last').data('msgid');
-        if (typeof boostIndex === "undefined") {
-            return false;
-        }
+        getBoosts({before: $(this).data('before')}, 200, false);
 
-        boostIndex = boostIndex;
-        if(boostIndex < 1) {
-            boostIndex = 1;
-            max = boostIndex
-            old = false;
-        }
+        return false;
+    });
 
-        getBoosts(boostIndex, 200, false, old);
+    //Reply handler.  Sends a boost back to the listener who sent this one.
+    $(document).on('click', 'a.reply', function () {
+        let boostIndex = $(this).data('msgid');
+
+        bootbox.dialog({
+            title: 'Boost back',
+            message: '' +
+                '<form class="reply_form">' +
+                '  <input type="number" min="1" name="sats" class="form-control" placeholder="Sats" value="100">' +
+                '  <textarea name="message" class="form-control" placeholder="Message"></textarea>' +
+                '</form>',
+            buttons: {
+                cancel: {
+                    label: 'Cancel'
+                },
+                send: {
+                    label: 'Send',
+                    className: 'btn-primary',
+                    callback: function () {
+                        let form = $('form.reply_form');
+                        $.ajax({
+                            url: '/boosts/' + boostIndex + '/reply',
+                            type: "POST",
+                            contentType: "application/json; charset=utf-8",
+                            dataType: "json",
+                            data: JSON.stringify({
+                                sats: parseInt(form.find('input[name=sats]').val()),
+                                message: form.find('textarea[name=message]').val()
+                            }),
+                            success: function (payment) {
+                                if (payment.state == 1) {
+                                    bootbox.alert('Boost sent.');
+                                } else if (payment.state == 0) {
+                                    bootbox.alert('The boost is still on its way.');
+                                } else {
+                                    bootbox.alert($('<div>').text('The boost did not go through: ' + payment.error).html());
+                                }
+                            },
+                            error: function (xhr) {
+                                let message = xhr.responseJSON && xhr.responseJSON.error ? xhr.responseJSON.error : 'Could not send the boost.';
+                                bootbox.alert($('<div>').text(message).html());
+                            }
+                        });
+                    }
+                }
+            }
+        });
 
         return false;
     });
 
-    //Set a periodic checker for new boosts
-    setInterval(function () {
-        getBoosts(null, 20, true);
-    }, 7000);
+    //Log out handler
+    $(document).on('click', 'a.logout', function () {
+        $.ajax({
+            url: '/logout',
+            type: "POST",
+            complete: function () {
+                window.location = '/login';
+            }
+        });
+
+        return false;
+    });
+
+    //Have new boosts pushed to us as they arrive, starting after the newest one shown.  The browser
+    //reconnects on its own and sends the last index it saw, so anything missed while disconnected is
+    //replayed.  Older browsers poll instead.
+    function followBoosts() {
+        if (liveBoosts !== null) {
+            return;
+        }
+        if (typeof EventSource === "undefined") {
+            liveBoosts = setInterval(function () {
+                let newestIndex = $('div.outgoing_msg:first').data('msgid');
+                getBoosts(typeof newestIndex === "number" ? {after: newestIndex} : null, 20, true);
+            }, 7000);
+            return;
+        }
+
+        var url = '/boosts/stream';
+        let newestIndex = $('div.outgoing_msg:first').data('msgid');
+        if (typeof newestIndex === "number") {
+            url += '?since=' + newestIndex;
+        }
+        liveBoosts = new EventSource(url);
+        liveBoosts.addEventListener('boost', function (event) {
+            addBoost(JSON.parse(event.data), true);
+        });
+    }
 
 });
 
@@ -0,0 +1,27 @@
+$(document).ready(function () {
+    let form = $('form.login');
+    let loginError = form.find('div.login_error');
+
+    //Trade the password for a session cookie, then go to the boosts
+    form.on('submit', function () {
+        loginError.text('');
+
+        $.ajax({
+            url: '/login',
+            type: "POST",
+            contentType: "application/json; charset=utf-8",
+            dataType: "json",
+            data: JSON.stringify({password: form.find('input[name=password]').val()}),
+            success: function () {
+                window.location = '/';
+            },
+            error: function (xhr) {
+                let message = xhr.responseJSON && xhr.responseJSON.error ? xhr.responseJSON.error : 'Could not log in.';
+                loginError.text(message);
+                form.find('input[name=password]').val('').focus();
+            }
+        });
+
+        return false;
+    });
+});
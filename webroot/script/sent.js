@@ -0,0 +1,136 @@
+$(document).ready(function () {
+    let messages = $('div.mesgs');
+    let outbox = messages.find('div.msg_history');
+    let totals = $('div.sent_totals');
+    let appIconUrlBase = '/image?name=';
+    let pageSize = 50;
+
+    //Our login session ran out, so log in again
+    $.ajaxSetup({
+        statusCode: {
+            401: function () {
+                window.location = '/login';
+            }
+        }
+    });
+
+    getTotals();
+    getSent();
+
+    //What went through to each podcast, biggest first
+    function getTotals() {
+        $.ajax({
+            url: '/sent/podcasts',
+            type: "GET",
+            contentType: "application/json; charset=utf-8",
+            dataType: "json",
+            success: function (data) {
+                totals.empty();
+                if (data.length == 0) {
+                    return;
+                }
+
+                let table = $('<table>');
+                data.forEach((total) => {
+                    table.append($('<tr>')
+                        .append($('<td>').text(total.podcast))
+                        .append($('<td class="sats">').text(Math.trunc(total.value_msat / 1000) + ' sats in ' + total.count)));
+                });
+                totals.append('<h6>Podcasts supported</h6>').append(table);
+            }
+        });
+    }
+
+    //Load a page of sent boosts and streams, newest first, starting before the given index
+    function getSent(before) {
+        var url = '/sent/boosts?count=' + pageSize;
+        if (typeof before === "number") {
+            url += '&before=' + before;
+        }
+
+        $.ajax({
+            url: url,
+            type: "GET",
+            contentType: "application/json; charset=utf-8",
+            dataType: "json",
+            success: function (data) {
+                $('div.loadmore').remove();
+                data.forEach((element) => {
+                    addSent(element);
+                });
+
+                if ($('div.outgoing_msg').length == 0 && $('div.nodata').length == 0) {
+                    outbox.append('<div class="nodata">Nothing sent from this node yet.</div>');
+                }
+                if (data.length == pageSize) {
+                    outbox.append('<div class="loadmore"><a href="#" data-before="' + data[data.length - 1].index + '">Show older payments...</a></div>');
+                }
+            }
+        });
+    }
+
+    //Render a sent payment at the bottom of the list
+    function addSent(element) {
+        let sentSats = Math.trunc(element.value_msat / 1000);
+        let dateTime = new Date(element.time * 1000).toISOString();
+        let sentTo = element.podcast || element.recipient;
+        let sentKind = element.action == 1 ? 'streamed' : 'boosted';
+        let appIconUrl = element.app ? appIconUrlBase + element.app.toLowerCase() : '';
+
+        var elState = '';
+        if (element.state == 0) {
+            elState = '<span class="payment_state">In flight</span>';
+        } else if (element.state == 2) {
+            elState = '<span class="payment_state">Failed</span>';
+        }
+
+        let elMessage = $('' +
+            '<div class="outgoing_msg message" data-msgid="' + element.index + '">' +
+            '  <div class="sent_msg">' +
+            '    <div class="sent_withd_msg">' +
+            '      <span class="app"><img></span>' +
+            '      <h5>' + sentSats + ' sats <small></small></h5>' +
+            '      ' + elState +
+            '      <span class="time_date" data-timestamp="' + dateTime + '">' + prettyDate(dateTime) + '</span>' +
+            '      <small class="podcast_episode"></small>' +
+            '      <br>' +
+            '      <hr>' +
+            '      <p></p>' +
+            '    </div>' +
+            '  </div>' +
+            '</div>');
+        //Not every app has an icon
+        if (appIconUrl) {
+            elMessage.find('span.app img').on('error', function () {
+                $(this).remove();
+            }).attr('src', appIconUrl);
+        }
+        elMessage.find('h5 small').text(sentKind + ' to ' + sentTo);
+        elMessage.find('small.podcast_episode').text(element.episode);
+        elMessage.find('p').text(element.message);
+
+        $('div.nodata').remove();
+        outbox.append(elMessage);
+    }
+
+    //Load more messages handler
+    $(document).on('click', 'div.loadmore a', function () {
+        getSent($(this).data('before'));
+
+        return false;
+    });
+
+    //Log out handler
+    $(document).on('click', 'a.logout', function () {
+        $.ajax({
+            url: '/logout',
+            type: "POST",
+            complete: function () {
+                window.location = '/login';
+            }
+        });
+
+        return false;
+    });
+
+});